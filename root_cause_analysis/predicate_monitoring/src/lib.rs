@@ -59,9 +59,18 @@ pub struct RootCauseCandidate {
     pub address: usize,
     pub score: f64,
     pub predicate: Predicate,
+    pub hits: usize,
+    /// step of the last hit satisfying a deferred predicate
+    pub last_satisfied: Option<usize>,
 }
 
 impl RootCauseCandidate {
+    fn count_hit(&mut self, new_rip: usize) {
+        if self.predicate.counts_hit(new_rip) {
+            self.hits += 1;
+        }
+    }
+
     pub fn satisfied(
         &self,
         dbg: &mut Ptracer,
@@ -92,41 +101,35 @@ impl RootCauseCandidate {
             );
         }
 
-        match self.predicate {
+        self.evaluate(&self.predicate, dbg, old_registers, rflags)
+    }
+
+    fn evaluate(
+        &self,
+        predicate: &Predicate,
+        dbg: &mut Ptracer,
+        old_registers: &nix::libc::user_regs_struct,
+        rflags: RFlags,
+    ) -> nix::Result<bool> {
+        let old_rip = old_registers.rip;
+        let new_rip = dbg.registers.rip;
+
+        match *predicate {
             Predicate::Compare(ref compare) => {
-                let value = match compare.destination {
-                    ValueDestination::Register(ref reg) => reg.value(&dbg.registers),
-                    ValueDestination::Address(ref mem) => mem.address(&old_registers),
-                    ValueDestination::Memory(ref access_size, ref mem) => {
-                        let address = mem.address(&old_registers);
-                        debug!("address = {:#018x}", address);
-
-                        let value = ptracer::read(
-                            dbg.event().pid().expect("pid missing"),
-                            address as nix::sys::ptrace::AddressType,
-                        )
-                        .expect("failed to read memory value")
-                            as usize;
-                        debug!("raw value = {:#018x}", value);
-
-                        match 1usize.checked_shl(*access_size as u32) {
-                            Some(mask) => value & mask,
-                            _ => value,
-                        }
-                    }
-                };
+                let value = read_value(&compare.destination, dbg, old_registers);
                 debug!(
                     "value = {:#018x}, compare.value = {:#018x}",
                     value, compare.value
                 );
 
-                Ok(match compare.compare {
-                    Compare::Equal => value == compare.value,
-                    Compare::Greater => value > compare.value,
-                    Compare::GreaterOrEqual => value >= compare.value,
-                    Compare::Less => value < compare.value,
-                    Compare::NotEqual => value != compare.value,
-                })
+                Ok(compare.compare.evaluate(value, compare.value))
+            }
+            Predicate::Relation(ref relation) => {
+                let lhs = read_value(&relation.lhs, dbg, old_registers);
+                let rhs = read_value(&relation.rhs, dbg, old_registers);
+                debug!("lhs = {:#018x}, rhs = {:#018x}", lhs, rhs);
+
+                Ok(relation.compare.evaluate(lhs, rhs))
             }
             Predicate::Edge(ref edge) => match edge.transition {
                 EdgeTransition::Taken => {
@@ -138,6 +141,46 @@ impl RootCauseCandidate {
             },
             Predicate::Visited => Ok(true),
             Predicate::FlagSet(flag) => Ok(rflags.contains(flag)),
+            Predicate::InsCount(CountPredicate { compare, value })
+            | Predicate::EdgeCount(EdgeCountPredicate { compare, value, .. }) => {
+                debug!("hits = {}, value = {}", self.hits, value);
+                Ok(compare.evaluate(self.hits, value))
+            }
+            // predicates on the last execution are evaluated like their inner predicate
+            Predicate::LastExecution(ref predicate) => {
+                self.evaluate(predicate, dbg, old_registers, rflags)
+            }
+            Predicate::And(ref p1, ref p2) => Ok(self.evaluate(p1, dbg, old_registers, rflags)?
+                && self.evaluate(p2, dbg, old_registers, rflags)?),
+            Predicate::Or(ref p1, ref p2) => Ok(self.evaluate(p1, dbg, old_registers, rflags)?
+                || self.evaluate(p2, dbg, old_registers, rflags)?),
+        }
+    }
+}
+
+fn read_value(
+    destination: &ValueDestination,
+    dbg: &mut Ptracer,
+    old_registers: &nix::libc::user_regs_struct,
+) -> usize {
+    match destination {
+        ValueDestination::Register(ref reg) => reg.value(&dbg.registers),
+        ValueDestination::Address(ref mem) => mem.address(&old_registers),
+        ValueDestination::Memory(ref access_size, ref mem) => {
+            let address = mem.address(&old_registers);
+            debug!("address = {:#018x}", address);
+
+            let value = ptracer::read(
+                dbg.event().pid().expect("pid missing"),
+                address as nix::sys::ptrace::AddressType,
+            )
+            .expect("failed to read memory value") as usize;
+            debug!("raw value = {:#018x}", value);
+
+            match 1usize.checked_shl(*access_size as u32) {
+                Some(mask) => value & mask,
+                _ => value,
+            }
         }
     }
 }
@@ -146,8 +189,10 @@ fn convert_predicates(
     decoder: &Decoder,
     dbg: &mut Ptracer,
     predicates: Vec<SerializedPredicate>,
-) -> HashMap<usize, RootCauseCandidate> {
-    predicates
+) -> HashMap<usize, Vec<RootCauseCandidate>> {
+    let mut rccs: HashMap<usize, Vec<RootCauseCandidate>> = HashMap::new();
+
+    for rcc in predicates
         .into_iter()
         .map(|pred| {
             debug!("pred = {:?}", pred);
@@ -174,6 +219,8 @@ fn convert_predicates(
                     address,
                     score: pred.score,
                     predicate,
+                    hits: 0,
+                    last_satisfied: None,
                 })
             });
 
@@ -184,11 +231,15 @@ fn convert_predicates(
             converted
         })
         .filter_map(|pred| pred)
-        .map(|pred| (pred.address, pred))
-        .collect()
+    {
+        // several predicates may share an address, e.g. the top predicates of an address
+        rccs.entry(rcc.address).or_default().push(rcc);
+    }
+
+    rccs
 }
 
-fn insert_breakpoints(dbg: &mut Ptracer, rccs: &HashMap<usize, RootCauseCandidate>) {
+fn insert_breakpoints(dbg: &mut Ptracer, rccs: &HashMap<usize, Vec<RootCauseCandidate>>) {
     for address in rccs.keys() {
         dbg.insert_breakpoint(*address)
             .expect("failed to insert breakpoint");
@@ -199,26 +250,34 @@ fn insert_breakpoints(dbg: &mut Ptracer, rccs: &HashMap<usize, RootCauseCandidat
 fn add_rccs_single_steps(
     pid: Pid,
     dbg: &mut Ptracer,
-    rccs: &HashMap<usize, RootCauseCandidate>,
+    rccs: &HashMap<usize, Vec<RootCauseCandidate>>,
     single_steping: &mut HashMap<Pid, nix::libc::user_regs_struct>,
 ) {
     let rip = dbg.registers.rip;
 
-    if let Some(rcc) = rccs.get(&(dbg.registers.rip as usize)) {
+    if let Some(candidates) = rccs.get(&(dbg.registers.rip as usize)) {
         debug!(
-            "breakpoint at {:#018x} of predicate {:016x?} reached",
-            rip, rcc.predicate
+            "breakpoint at {:#018x} of predicates {:016x?} reached",
+            rip,
+            candidates
+                .iter()
+                .map(|rcc| &rcc.predicate)
+                .collect::<Vec<_>>()
         );
 
         single_steping.insert(pid, dbg.registers);
     }
 }
 
+/// Predicates in the order they were satisfied, with the step and address of the satisfying hit.
+type Satisfaction = Vec<(usize, usize, Predicate)>;
+
 fn check_rccs(
     dbg: &mut Ptracer,
     old_registers: &nix::libc::user_regs_struct,
-    rccs: &mut HashMap<usize, RootCauseCandidate>,
-    satisfaction: &mut Vec<(usize, Predicate)>,
+    rccs: &mut HashMap<usize, Vec<RootCauseCandidate>>,
+    step: usize,
+    satisfaction: &mut Satisfaction,
 ) {
     let old_rip = old_registers.rip;
     let remove_breakpoint = |dbg: &mut Ptracer, address| {
@@ -230,45 +289,79 @@ fn check_rccs(
         }
     };
 
-    if let Some(rcc) = rccs.get(&(old_rip as usize)) {
-        debug!(
-            "single step target at {:#018x} of predicate {:016x?} reached",
-            dbg.registers.rip, rcc.predicate
-        );
-
-        if !rcc
+    let candidates = match rccs.get_mut(&(old_rip as usize)) {
+        Some(candidates) => candidates,
+        None => {
+            // removing the breakpoint may have failed early when the predicates were satisfied
+            remove_breakpoint(dbg, old_rip as usize);
+            return;
+        }
+    };
+    debug!(
+        "single step target at {:#018x} of {} predicates reached",
+        dbg.registers.rip,
+        candidates.len()
+    );
+
+    let mut index = 0;
+    while index < candidates.len() {
+        let rcc = &mut candidates[index];
+        rcc.count_hit(dbg.registers.rip as usize);
+        let satisfied = rcc
             .satisfied(dbg, old_registers)
-            .expect("failed to test predicate")
-        {
+            .expect("failed to test predicate");
+
+        // deferred predicates are re-evaluated on every hit
+        if rcc.predicate.is_deferred() {
+            trace!(
+                "predicate {:016x?} satisfied on last execution: {}",
+                rcc.predicate,
+                satisfied
+            );
+            rcc.last_satisfied = match satisfied {
+                true => Some(step),
+                false => None,
+            };
+            index += 1;
+            continue;
+        }
+
+        if !satisfied {
             trace!("predicate {:016x?} NOT satisfied", rcc.predicate);
-            return;
+            index += 1;
+            continue;
         }
-    } else {
-        // removing the breakpoint may have failed early when the predicate was satisfied
-        remove_breakpoint(dbg, old_rip as usize);
-        return;
-    }
 
-    // predicate satisfied
-    if let Some(rcc) = rccs.remove(&(old_rip as usize)) {
+        // predicate satisfied
+        let rcc = candidates.remove(index);
         info!(
-            "predicate {:016x?} satisfied, moving predicate to satisfaction and removing breakpoint",
+            "predicate {:016x?} satisfied, moving predicate to satisfaction",
             rcc.predicate
         );
-        satisfaction.push((rcc.address, rcc.predicate));
-        remove_breakpoint(dbg, rcc.address);
+        satisfaction.push((step, rcc.address, rcc.predicate));
+    }
+
+    if candidates.is_empty() {
+        info!(
+            "all predicates at {:#018x} satisfied, removing breakpoint",
+            old_rip
+        );
+        rccs.remove(&(old_rip as usize));
+        remove_breakpoint(dbg, old_rip as usize);
     }
 }
 
 fn collect_satisfied(
     decoder: &Decoder,
     dbg: &mut Ptracer,
-    rccs: &mut HashMap<usize, RootCauseCandidate>,
+    rccs: &mut HashMap<usize, Vec<RootCauseCandidate>>,
     timeout: u64,
-) -> Vec<(usize, Predicate)> {
+) -> Satisfaction {
     let mut satisfaction = vec![];
     let mut single_steping = HashMap::new();
     let start_time = Instant::now();
+    // number of single steps on monitored addresses
+    let mut step = 0;
 
     loop {
         trace!("threads = {:?}", dbg.threads);
@@ -290,7 +383,8 @@ fn collect_satisfied(
             // otherwise we hit a breakpoint and need to request single stepping
             if let Some(old_registers) = single_steping.remove(&pid) {
                 // handle previous single steping request
-                check_rccs(dbg, &old_registers, rccs, &mut satisfaction)
+                step += 1;
+                check_rccs(dbg, &old_registers, rccs, step, &mut satisfaction)
             } else {
                 // add single stepping request
                 add_rccs_single_steps(pid, dbg, rccs, &mut single_steping);
@@ -365,11 +459,32 @@ fn collect_satisfied(
         }
     }
 
+    collect_last_satisfied(rccs, &mut satisfaction);
+
     info!("satisfaction = {:#018x?}", satisfaction);
 
     satisfaction
 }
 
+/// Merges the satisfied deferred predicates by the step of their last satisfying hit.
+fn collect_last_satisfied(
+    rccs: &HashMap<usize, Vec<RootCauseCandidate>>,
+    satisfaction: &mut Satisfaction,
+) {
+    for rcc in rccs.values().flatten() {
+        if let Some(step) = rcc.last_satisfied {
+            info!(
+                "predicate {:016x?} satisfied on last execution, moving predicate to satisfaction",
+                rcc.predicate
+            );
+            satisfaction.push((step, rcc.address, rcc.predicate.clone()));
+        }
+    }
+
+    // stable, so that predicates satisfied on the same hit keep their order
+    satisfaction.sort_by_key(|(step, _, _)| *step);
+}
+
 pub fn rank_predicates(
     mut dbg: Ptracer,
     predicates: Vec<SerializedPredicate>,
@@ -383,7 +498,16 @@ pub fn rank_predicates(
     insert_breakpoints(&mut dbg, &rccs);
 
     let satisfaction = collect_satisfied(&decoder, &mut dbg, &mut rccs, timeout);
-    satisfaction.into_iter().map(|(addr, _)| addr).collect()
+
+    // an address is ranked by its first satisfied predicate
+    let mut ranking: Vec<usize> = vec![];
+    for (_, address, _) in satisfaction {
+        if !ranking.contains(&address) {
+            ranking.push(address);
+        }
+    }
+
+    ranking
 }
 
 pub fn spawn_dbg(path: &Path, args: &[String]) -> Ptracer {
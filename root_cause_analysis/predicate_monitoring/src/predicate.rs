@@ -7,9 +7,56 @@ use crate::rflags::RFlags;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Predicate {
     Compare(ComparePredicate),
+    Relation(RelationPredicate),
     Edge(EdgePredicate),
+    EdgeCount(EdgeCountPredicate),
     FlagSet(RFlags),
+    InsCount(CountPredicate),
     Visited,
+    LastExecution(Box<Predicate>),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+}
+
+impl Predicate {
+    /// Wraps `predicate` if it only has to hold at the last execution of its address.
+    fn from_selector(function: &str, predicate: Predicate) -> Self {
+        match function.starts_with("last_") {
+            true => Self::LastExecution(Box::new(predicate)),
+            false => predicate,
+        }
+    }
+
+    /// Deferred predicates can only be decided once the debugee terminated.
+    pub fn is_deferred(&self) -> bool {
+        match self {
+            Self::LastExecution(_) => true,
+            Self::InsCount(count) => count.compare == Compare::Less,
+            Self::EdgeCount(count) => count.compare == Compare::Less,
+            Self::And(p1, p2) | Self::Or(p1, p2) => p1.is_deferred() || p2.is_deferred(),
+            _ => false,
+        }
+    }
+
+    fn is_count(&self) -> bool {
+        match self {
+            Self::InsCount(_) | Self::EdgeCount(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether the current execution is counted by count predicates.
+    pub fn counts_hit(&self, new_rip: usize) -> bool {
+        match self {
+            // edge counts only include transitions to the destination
+            Self::EdgeCount(count) => count.destination == new_rip,
+            Self::LastExecution(predicate) => predicate.counts_hit(new_rip),
+            Self::And(p1, p2) | Self::Or(p1, p2) => {
+                p1.counts_hit(new_rip) && p2.counts_hit(new_rip)
+            }
+            _ => true,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,6 +66,14 @@ pub struct ComparePredicate {
     pub value: usize,
 }
 
+/// Compares two values of the same execution, e.g. `rax < rbx`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelationPredicate {
+    pub lhs: ValueDestination,
+    pub compare: Compare,
+    pub rhs: ValueDestination,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValueDestination {
     Address(MemoryLocation),
@@ -90,6 +145,26 @@ pub enum Compare {
     GreaterOrEqual,
     Equal,
     NotEqual,
+    InRange(usize),
+}
+
+impl Compare {
+    pub fn evaluate(self, lhs: usize, rhs: usize) -> bool {
+        match self {
+            Self::Equal => lhs == rhs,
+            Self::Greater => lhs > rhs,
+            Self::GreaterOrEqual => lhs >= rhs,
+            Self::Less => lhs < rhs,
+            Self::NotEqual => lhs != rhs,
+            Self::InRange(end) => rhs <= lhs && lhs < end,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountPredicate {
+    pub compare: Compare,
+    pub value: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -99,94 +174,196 @@ pub struct EdgePredicate {
     pub destination: usize,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdgeCountPredicate {
+    pub source: usize,
+    pub destination: usize,
+    pub compare: Compare,
+    pub value: usize,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EdgeTransition {
     Taken,
     NotTaken,
 }
 
+fn value_destination(
+    name: &str,
+    instruction: &zydis::DecodedInstruction,
+) -> Option<ValueDestination> {
+    let memory_locations = instruction.operands[..instruction.operand_count as usize]
+        .into_iter()
+        .filter(|op| match op.ty {
+            zydis::OperandType::MEMORY => true,
+            _ => false,
+        });
+    let memory = memory_locations
+        .last()
+        .and_then(|op| Some(MemoryLocation::from_memory_info(&op.mem)));
+
+    let destination = match name {
+        "memory_address" => ValueDestination::Address(memory.expect("no memory location")),
+        "memory_value" => ValueDestination::Memory(
+            instruction.operand_width,
+            memory.expect("no memory location"),
+        ),
+
+        "seg_cs" => return None,
+        "seg_ss" => return None,
+        "seg_ds" => return None,
+        "seg_es" => return None,
+        "seg_fs" => return None,
+        "seg_gs" => return None,
+
+        "eflags" => return None,
+
+        register => ValueDestination::Register(
+            Register::from_str(register).expect("failed to parse register"),
+        ),
+    };
+
+    Some(destination)
+}
+
+fn convert_combination(
+    p1: &str,
+    p2: &str,
+    instruction: zydis::DecodedInstruction,
+) -> Option<(Box<Predicate>, Box<Predicate>)> {
+    let p1 = convert_predicate(p1, instruction.clone())?;
+    let p2 = convert_predicate(p2, instruction)?;
+
+    // candidates only track a single hit counter
+    if p1.is_count() && p2.is_count() {
+        log::warn!("cannot combine two count predicates {:?} and {:?}", p1, p2);
+        return None;
+    }
+
+    // deferred predicates are decided on the last execution, the others on any execution
+    if p1.is_deferred() != p2.is_deferred() {
+        log::warn!(
+            "cannot combine deferred and non-deferred predicates {:?} and {:?}",
+            p1,
+            p2
+        );
+        return None;
+    }
+
+    Some((Box::new(p1), Box::new(p2)))
+}
+
 pub fn convert_predicate(
     predicate: &str,
     instruction: zydis::DecodedInstruction,
 ) -> Option<Predicate> {
+    // "<p1> && <p2>" or "<p1> || <p2>"
+    if let Some(index) = predicate.find(" && ") {
+        return convert_combination(&predicate[..index], &predicate[index + 4..], instruction)
+            .map(|(p1, p2)| Predicate::And(p1, p2));
+    }
+    if let Some(index) = predicate.find(" || ") {
+        return convert_combination(&predicate[..index], &predicate[index + 4..], instruction)
+            .map(|(p1, p2)| Predicate::Or(p1, p2));
+    }
+
     let parts: Vec<_> = predicate.split(' ').collect();
     let function = match parts.len() {
         1 | 2 => parts[0],
-        3 => parts[1],
+        3 | 4 => parts[1],
         _ => unimplemented!(),
     };
 
-    if function.contains("edge") {
+    if function.contains("edge_count") {
         let source = usize::from_str_radix(&parts[0][2..], 16).expect("failed to parse source");
         let destination =
             usize::from_str_radix(&parts[2][2..], 16).expect("failed to parse destination");
-        let transition = match function {
-            "has_edge_to" => EdgeTransition::Taken,
-            "edge_only_taken_to" => EdgeTransition::NotTaken,
-            "last_edge_to" => return None,
+        let value = usize::from_str(parts[3]).expect("failed to parse value");
+        let compare = match function {
+            "edge_count_less" => Compare::Less,
+            "edge_count_greater_or_equal" => Compare::GreaterOrEqual,
             _ => unimplemented!(),
         };
 
-        return Some(Predicate::Edge(EdgePredicate {
+        return Some(Predicate::EdgeCount(EdgeCountPredicate {
             source,
-            transition,
             destination,
+            compare,
+            value,
         }));
-    } else if function.contains("reg_val") {
-        let value = usize::from_str_radix(&parts[2][2..], 16).expect("failed to parse value");
-        let memory_locations = instruction.operands[..instruction.operand_count as usize]
-            .into_iter()
-            .filter(|op| match op.ty {
-                zydis::OperandType::MEMORY => true,
-                _ => false,
-            });
-        let memory = memory_locations
-            .last()
-            .and_then(|op| Some(MemoryLocation::from_memory_info(&op.mem)));
-
-        let destination = match parts[0] {
-            "memory_address" => ValueDestination::Address(memory.expect("no memory location")),
-            "memory_value" => ValueDestination::Memory(
-                instruction.operand_width,
-                memory.expect("no memory location"),
-            ),
-
-            "seg_cs" => return None,
-            "seg_ss" => return None,
-            "seg_ds" => return None,
-            "seg_es" => return None,
-            "seg_fs" => return None,
-            "seg_gs" => return None,
-
-            "eflags" => return None,
+    } else if function.contains("edge") {
+        let source = usize::from_str_radix(&parts[0][2..], 16).expect("failed to parse source");
+        let destination =
+            usize::from_str_radix(&parts[2][2..], 16).expect("failed to parse destination");
+        let transition = match function {
+            "has_edge_to" => EdgeTransition::Taken,
+            "edge_only_taken_to" => EdgeTransition::NotTaken,
+            "last_edge_to" => EdgeTransition::Taken,
+            _ => unimplemented!(),
+        };
 
-            register => ValueDestination::Register(
-                Register::from_str(register).expect("failed to parse register"),
-            ),
+        return Some(Predicate::from_selector(
+            function,
+            Predicate::Edge(EdgePredicate {
+                source,
+                transition,
+                destination,
+            }),
+        ));
+    } else if function.ends_with("_reg") {
+        // "<reg> last_reg_less_reg <reg>"
+        let lhs = value_destination(parts[0], &instruction)?;
+        let rhs = value_destination(parts[2], &instruction)?;
+        let compare = match function {
+            "last_reg_less_reg" => Compare::Less,
+            "last_reg_equal_reg" => Compare::Equal,
+            _ => unimplemented!(),
         };
 
+        return Some(Predicate::from_selector(
+            function,
+            Predicate::Relation(RelationPredicate { lhs, compare, rhs }),
+        ));
+    } else if function.contains("reg_val") {
+        let value = usize::from_str_radix(&parts[2][2..], 16).expect("failed to parse value");
+        let destination = value_destination(parts[0], &instruction)?;
+
         let compare = match function {
             "min_reg_val_less" => Compare::Less,
             "max_reg_val_less" => Compare::Less,
-            "last_reg_val_less" => return None,
+            "last_reg_val_less" => Compare::Less,
             "max_min_diff_reg_val_less" => return None,
 
             "min_reg_val_greater_or_equal" => Compare::GreaterOrEqual,
             "max_reg_val_greater_or_equal" => Compare::GreaterOrEqual,
-            "last_reg_val_greater_or_equal" => return None,
+            "last_reg_val_greater_or_equal" => Compare::GreaterOrEqual,
             "max_min_diff_reg_val_greater_or_equal" => return None,
 
+            // "<reg> <selector>_reg_val_in_<region> <start> <end>"
+            _ if function.contains("reg_val_in_") => Compare::InRange(
+                usize::from_str_radix(&parts[3][2..], 16).expect("failed to parse range end"),
+            ),
+
             _ => unimplemented!(),
         };
 
-        return Some(Predicate::Compare(ComparePredicate {
-            destination,
-            compare,
-            value,
-        }));
+        return Some(Predicate::from_selector(
+            function,
+            Predicate::Compare(ComparePredicate {
+                destination,
+                compare,
+                value,
+            }),
+        ));
     } else if function.contains("ins_count") {
-        // "ins_count_less"
-        // "ins_count_greater_or_equal"
+        let value = usize::from_str(parts[1]).expect("failed to parse value");
+        let compare = match function {
+            "ins_count_less" => Compare::Less,
+            "ins_count_greater_or_equal" => Compare::GreaterOrEqual,
+            _ => unimplemented!(),
+        };
+
+        return Some(Predicate::InsCount(CountPredicate { compare, value }));
     } else if function.contains("selector_val") {
         // "selector_val_less_name"
         // "selector_val_less"
@@ -217,20 +394,20 @@ pub fn convert_predicate(
             "max_direction_flag_set" => RFlags::DIRECTION_FLAG,
             "max_overflow_flag_set" => RFlags::OVERFLOW_FLAG,
 
-            "last_carry_flag_set" => return None,
-            "last_parity_flag_set" => return None,
-            "last_adjust_flag_set" => return None,
-            "last_zero_flag_set" => return None,
-            "last_sign_flag_set" => return None,
-            "last_trap_flag_set" => return None,
-            "last_interrupt_flag_set" => return None,
-            "last_direction_flag_set" => return None,
-            "last_overflow_flag_set" => return None,
+            "last_carry_flag_set" => RFlags::CARRY_FLAG,
+            "last_parity_flag_set" => RFlags::PARITY_FLAG,
+            "last_adjust_flag_set" => RFlags::AUXILIARY_CARRY_FLAG,
+            "last_zero_flag_set" => RFlags::ZERO_FLAG,
+            "last_sign_flag_set" => RFlags::SIGN_FLAG,
+            "last_trap_flag_set" => RFlags::TRAP_FLAG,
+            "last_interrupt_flag_set" => RFlags::INTERRUPT_FLAG,
+            "last_direction_flag_set" => RFlags::DIRECTION_FLAG,
+            "last_overflow_flag_set" => RFlags::OVERFLOW_FLAG,
 
             _ => unimplemented!(),
         };
 
-        return Some(Predicate::FlagSet(flag));
+        return Some(Predicate::from_selector(function, Predicate::FlagSet(flag)));
     } else if function == "is_visited" {
         return Some(Predicate::Visited);
     } else {
@@ -2,35 +2,27 @@ use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::fs::read_to_string;
 
-use std::process::Command;
 use structopt::StructOpt;
 
 use root_cause_analysis::config::Config;
 use root_cause_analysis::monitor::executable;
-use root_cause_analysis::utils::{parse_hex, write_file};
-
-fn addr2line_args(config: &Config, address: usize) -> Vec<String> {
-    format!(
-        "-e {} -a 0x{:x} -f -C -s -i -p",
-        executable(config),
-        address - config.load_offset
-    )
-    .split_whitespace()
-    .map(|s| s.to_string())
-    .collect()
-}
-
-fn addr2line(config: &Config, address: usize) -> String {
-    let args = addr2line_args(config, address);
-
-    let output = Command::new("addr2line")
-        .args(args)
-        .output()
-        .expect("Could not execute addr2line");
+use root_cause_analysis::utils::{glob_paths, parse_hex, write_file};
+use trace_analysis::symbolization::Symbolizer;
+use trace_analysis::trace_analyzer::load_trace;
+
+/// Image base recorded in the crash traces, unless overridden by `--load-offset`.
+fn image_base(config: &Config) -> usize {
+    if let Some(load_offset) = config.load_offset {
+        return load_offset;
+    }
 
-    String::from_utf8_lossy(&output.stdout)[19..]
-        .trim()
-        .to_string()
+    let path = glob_paths(format!("{}/traces/crashes/*", config.trace_dir))
+        .into_iter()
+        .next()
+        .expect("No trace to read the image base from, pass --trace-dir or --load-offset");
+    load_trace(path)
+        .unwrap_or_else(|error| panic!("{}", error))
+        .image_base
 }
 
 fn read_trace_file(config: &Config) -> String {
@@ -65,9 +57,20 @@ fn unique_addresses(lines: &Vec<String>) -> HashSet<usize> {
 }
 
 fn map_address_to_src(config: &Config, addresses: &HashSet<usize>) -> HashMap<usize, String> {
+    let symbolizer = Symbolizer::new(&executable(config), image_base(config))
+        .unwrap_or_else(|error| panic!("{}", error));
+
     addresses
-        .par_iter()
-        .map(|address| (*address, addr2line(&config, *address)))
+        .iter()
+        .map(|address| {
+            (
+                *address,
+                symbolizer
+                    .symbolize(*address)
+                    .map(|location| location.to_string())
+                    .unwrap_or_else(|| "??".to_string()),
+            )
+        })
         .collect()
 }
 
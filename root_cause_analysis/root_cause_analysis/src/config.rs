@@ -1,6 +1,8 @@
 use std::num::ParseIntError;
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
+use trace_analysis::config::TRACE_ERROR_POLICIES;
+use trace_analysis::scoring::SCORING_FUNCTIONS;
 
 fn parse_hex(src: &str) -> Result<usize, ParseIntError> {
     usize::from_str_radix(&src.replace("0x", ""), 16)
@@ -15,7 +17,11 @@ global_settings = &[AppSettings::DisableVersion]
 pub struct Config {
     #[structopt(long = "trace-dir", default_value = "", help = "Path to traces")]
     pub trace_dir: String,
-    #[structopt(long = "eval-dir", help = "Path to evaluation folder")]
+    #[structopt(
+        long = "eval-dir",
+        default_value = "",
+        help = "Path to evaluation folder"
+    )]
     pub eval_dir: String,
     #[structopt(long = "rank-predicates", help = "Rank predicates")]
     pub rank_predicates: bool,
@@ -37,11 +43,72 @@ pub struct Config {
     pub debug_trace: bool,
     #[structopt(
         long = "load-offset",
-        default_value = "0x0000555555554000",
         parse(try_from_str = parse_hex),
-        help = "Load offset of the target"
+        help = "Load offset of the target (default: image base of the traces)"
+    )]
+    pub load_offset: Option<usize>,
+    #[structopt(
+        long = "combine",
+        default_value = "0",
+        help = "Combine the top k predicates per address into conjunctions and disjunctions"
+    )]
+    pub combine_predicates: usize,
+    #[structopt(
+        long = "collapse-predicates",
+        help = "Collapse predicates equivalent to a predicate at a dominating or post-dominating address"
+    )]
+    pub collapse_predicates: bool,
+    #[structopt(
+        long = "scoring",
+        default_value = "balanced-accuracy",
+        possible_values = SCORING_FUNCTIONS,
+        help = "Scoring function used to rank predicates"
+    )]
+    pub scoring: String,
+    #[structopt(
+        long = "min-score",
+        help = "Minimum score of the analyzed predicates (default: 90% of the best score reachable with the scoring function)"
+    )]
+    pub min_score: Option<f64>,
+    #[structopt(
+        long = "lower-bound",
+        help = "Select predicates by the lower confidence bound of their score"
+    )]
+    pub filter_lower_bound: bool,
+    #[structopt(
+        long = "stream",
+        help = "Aggregate traces while reading them instead of keeping them in memory"
+    )]
+    pub stream_traces: bool,
+    #[structopt(
+        long = "cache",
+        help = "Cache aggregates and predicates in the evaluation folder and only analyze new traces (implies --stream)"
+    )]
+    pub cache_analysis: bool,
+    #[structopt(
+        long = "on-trace-error",
+        default_value = "skip",
+        possible_values = TRACE_ERROR_POLICIES,
+        help = "Skip and report traces that cannot be loaded or fail on the first one"
+    )]
+    pub trace_error_policy: String,
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    #[structopt(about = "Convert JSON traces into the binary trace format")]
+    Convert {
+        #[structopt(help = "Folder containing the JSON traces (.zip or *trace)")]
+        input_dir: String,
+        #[structopt(help = "Output folder for the zipped binary traces")]
+        output_dir: String,
+    },
+    #[structopt(
+        about = "Merge the ranked predicates into report.json and report.html in the evaluation folder"
     )]
-    pub load_offset: usize,
+    Report,
 }
 
 impl Config {
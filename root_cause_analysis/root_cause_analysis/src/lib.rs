@@ -1,5 +1,6 @@
 pub mod config;
 pub mod monitor;
 pub mod rankings;
+pub mod report;
 pub mod traces;
 pub mod utils;
@@ -1,13 +1,29 @@
-use root_cause_analysis::config::Config;
+use root_cause_analysis::config::{Command, Config};
 use root_cause_analysis::monitor::monitor_predicates;
 use root_cause_analysis::rankings::rank_predicates;
-use root_cause_analysis::traces::analyze_traces;
+use root_cause_analysis::report::write_report;
+use root_cause_analysis::traces::{analyze_traces, convert_traces};
 use std::time::Instant;
 use structopt::StructOpt;
 
 fn main() {
     let config = Config::from_args();
 
+    match &config.command {
+        Some(Command::Convert {
+            input_dir,
+            output_dir,
+        }) => {
+            convert_traces(input_dir, output_dir);
+            return;
+        }
+        Some(Command::Report) => {
+            write_report(&config);
+            return;
+        }
+        None => {}
+    }
+
     let total_time = Instant::now();
 
     if config.analyze_traces() {
@@ -11,7 +11,8 @@ use trace_analysis::trace_analyzer::{blacklist_path, read_crash_blacklist};
 pub fn monitor_predicates(config: &Config) {
     let cmd_line = cmd_line(&config);
     let blacklist_paths =
-        read_crash_blacklist(config.blacklist_crashes(), &config.crash_blacklist_path);
+        read_crash_blacklist(config.blacklist_crashes(), &config.crash_blacklist_path)
+            .unwrap_or_else(|error| panic!("{}", error));
 
     let rankings = glob_paths(format!("{}/inputs/crashes/*", config.eval_dir))
         .into_par_iter()
@@ -116,6 +117,15 @@ pub fn executable(config: &Config) -> String {
     results.pop().expect("No trace executable found")
 }
 
+/// Like `executable`, but `None` if there is no unique executable in the evaluation folder.
+pub fn find_executable(config: &Config) -> Option<String> {
+    let mut results = glob_paths(format!("{}/*_trace", config.eval_dir));
+    match results.len() {
+        1 => results.pop(),
+        _ => None,
+    }
+}
+
 pub fn replace_input(cmd_line: &String, replacement: &String) -> (String, Option<String>) {
     match cmd_line.contains("@@") {
         true => (cmd_line.replace("@@", replacement), None),
@@ -1,10 +1,15 @@
 use crate::config::Config;
-use crate::traces::{deserialize_mnemonics, deserialize_predicates};
+use crate::traces::{
+    deserialize_disassembly, deserialize_mnemonics, deserialize_predicates, mnemonic,
+};
 use crate::utils::{read_file, write_file};
 use rayon::prelude::*;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::path::Path;
+use trace_analysis::disassembly::BlockContext;
 use trace_analysis::predicates::SerializedPredicate;
+use trace_analysis::scoring::cmp_scores;
 
 pub fn trunc_score(score: f64) -> f64 {
     (score * 100.0).trunc() as f64
@@ -14,24 +19,34 @@ fn predicate_order(
     p2: &SerializedPredicate,
     rankings: &Vec<Vec<usize>>,
 ) -> Ordering {
-    p2.score.partial_cmp(&p1.score).unwrap().then(
-        path_rank(p1.address, rankings)
-            .partial_cmp(&path_rank(p2.address, rankings))
-            .unwrap(),
-    )
+    cmp_scores(p2.score, p1.score).then(cmp_scores(
+        path_rank(p1.address, rankings),
+        path_rank(p2.address, rankings),
+    ))
 }
 
 pub fn rank_predicates(config: &Config) {
     let rankings = deserialize_rankings(config);
     let mnemonics = deserialize_mnemonics(config);
-    let mut predicates = deserialize_predicates(config);
-
-    predicates.par_sort_by(|p1, p2| predicate_order(p1, p2, &rankings));
+    let predicates = ranked_predicates(config, &rankings);
 
     dump_ranked_predicates(config, &predicates, &mnemonics, &rankings);
+
+    // evaluation folders of older runs have no disassembly
+    if Path::new(&format!("{}/disassembly.json", config.eval_dir)).exists() {
+        dump_ranked_disassembly(config, &predicates, &mnemonics, &rankings);
+    }
 }
 
-fn path_rank(address: usize, rankings: &Vec<Vec<usize>>) -> f64 {
+/// Predicates ordered by score and, for equal scores, by path rank.
+pub fn ranked_predicates(config: &Config, rankings: &Vec<Vec<usize>>) -> Vec<SerializedPredicate> {
+    let mut predicates = deserialize_predicates(config);
+    predicates.par_sort_by(|p1, p2| predicate_order(p1, p2, rankings));
+
+    predicates
+}
+
+pub fn path_rank(address: usize, rankings: &Vec<Vec<usize>>) -> f64 {
     rankings
         .par_iter()
         .map(|r| rank_path_level(address, r))
@@ -39,6 +54,22 @@ fn path_rank(address: usize, rankings: &Vec<Vec<usize>>) -> f64 {
         / rankings.len() as f64
 }
 
+/// Lists the addresses of the predicates collapsed into `predicate`, if any.
+fn merged_addresses(predicate: &SerializedPredicate) -> String {
+    match predicate.merged_addresses.is_empty() {
+        true => String::new(),
+        false => format!(
+            " (merged: {})",
+            predicate
+                .merged_addresses
+                .iter()
+                .map(|address| format!("{:#x}", address))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
 fn rank_path_level(address: usize, rank: &Vec<usize>) -> f64 {
     match rank.iter().position(|x| address == *x) {
         Some(pos) => pos as f64 / rank.len() as f64,
@@ -51,7 +82,7 @@ pub fn serialize_rankings(config: &Config, rankings: &Vec<Vec<usize>>) {
     write_file(&format!("{}/rankings.json", config.eval_dir), content);
 }
 
-fn deserialize_rankings(config: &Config) -> Vec<Vec<usize>> {
+pub fn deserialize_rankings(config: &Config) -> Vec<Vec<usize>> {
     let content = read_file(&format!("{}/rankings.json", config.eval_dir));
     serde_json::from_str(&content).expect("Could not deserialize rankings")
 }
@@ -66,10 +97,11 @@ fn dump_ranked_predicates(
         .iter()
         .map(|p| {
             format!(
-                "{} -- {} (path rank: {})\n",
+                "{} -- {} (path rank: {}){}\n",
                 p.to_string(),
-                mnemonics[&p.address],
-                path_rank(p.address, rankings)
+                mnemonic(mnemonics, p.address),
+                path_rank(p.address, rankings),
+                merged_addresses(p)
             )
         })
         .collect();
@@ -77,4 +109,44 @@ fn dump_ranked_predicates(
         &format!("{}/ranked_predicates.txt", config.eval_dir),
         content,
     );
+
+    let content = serde_json::to_string(predicates).expect("Could not serialize ranked predicates");
+    write_file(
+        &format!("{}/ranked_predicates.json", config.eval_dir),
+        content,
+    );
+}
+
+/// Lists the ranked predicates with the disassembly of their basic block and its neighbours.
+fn dump_ranked_disassembly(
+    config: &Config,
+    predicates: &Vec<SerializedPredicate>,
+    mnemonics: &HashMap<usize, String>,
+    rankings: &Vec<Vec<usize>>,
+) {
+    let contexts: HashMap<usize, BlockContext> = deserialize_disassembly(config)
+        .into_iter()
+        .map(|c| (c.address, c))
+        .collect();
+
+    let content: String = predicates
+        .iter()
+        .map(|p| {
+            format!(
+                "{} -- {} (path rank: {}){}\n{}\n",
+                p.to_string(),
+                mnemonic(mnemonics, p.address),
+                path_rank(p.address, rankings),
+                merged_addresses(p),
+                contexts
+                    .get(&p.address)
+                    .map(|c| c.to_string())
+                    .unwrap_or_default()
+            )
+        })
+        .collect();
+    write_file(
+        &format!("{}/ranked_predicates_disassembly.txt", config.eval_dir),
+        content,
+    );
 }
@@ -0,0 +1,125 @@
+use crate::config::Config;
+use crate::rankings::{deserialize_rankings, path_rank, ranked_predicates};
+use crate::traces::{
+    deserialize_disassembly, deserialize_distributions, deserialize_mnemonics, mnemonic,
+};
+use crate::utils::{read_file, write_file};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use trace_analysis::disassembly::BlockContext;
+use trace_analysis::distribution::ValueDistribution;
+use trace_analysis::scoring::PredicateStatistics;
+use trace_analysis::symbolization::SourceLocation;
+
+const HTML_TEMPLATE: &str = include_str!("report.html");
+
+/// Everything known about the predicates of one analysis run.
+#[derive(Serialize, Deserialize)]
+pub struct Report {
+    pub metric: String,
+    pub predicates: Vec<ReportEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportEntry {
+    pub rank: usize,
+    pub address: usize,
+    pub predicate: String,
+    pub score: f64,
+    pub path_rank: f64,
+    pub mnemonic: String,
+    pub statistics: Option<PredicateStatistics>,
+    pub location: Option<SourceLocation>,
+    pub distribution: Option<ValueDistribution>,
+    pub context: Option<BlockContext>,
+    /// addresses of equivalent predicates collapsed into this one
+    #[serde(default)]
+    pub merged_addresses: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct ScoringMetric {
+    #[serde(default = "default_metric")]
+    metric: String,
+}
+
+/// Scores written before the scoring function was selectable are balanced accuracies.
+fn default_metric() -> String {
+    "balanced-accuracy".to_string()
+}
+
+fn deserialize_metric(config: &Config) -> String {
+    let content = read_file(&format!(
+        "{}/scores_linear_serialized.json",
+        config.eval_dir
+    ));
+    let scores: ScoringMetric =
+        serde_json::from_str(&content).expect("Could not deserialize scores");
+
+    scores.metric
+}
+
+/// Merges the ranked predicates with their mnemonics, path ranks, locations, value distributions and
+/// disassembly.
+pub fn build_report(config: &Config) -> Report {
+    let rankings = deserialize_rankings(config);
+    let mnemonics = deserialize_mnemonics(config);
+    // evaluation folders of older runs have no distributions or disassembly
+    let mut distributions: HashMap<usize, ValueDistribution> =
+        match Path::new(&format!("{}/distributions.json", config.eval_dir)).exists() {
+            true => deserialize_distributions(config)
+                .into_iter()
+                .map(|d| (d.address, d))
+                .collect(),
+            false => HashMap::new(),
+        };
+    let mut contexts: HashMap<usize, BlockContext> =
+        match Path::new(&format!("{}/disassembly.json", config.eval_dir)).exists() {
+            true => deserialize_disassembly(config)
+                .into_iter()
+                .map(|c| (c.address, c))
+                .collect(),
+            false => HashMap::new(),
+        };
+
+    let predicates = ranked_predicates(config, &rankings)
+        .into_iter()
+        .enumerate()
+        .map(|(rank, p)| ReportEntry {
+            rank: rank + 1,
+            address: p.address,
+            predicate: p.name,
+            score: p.score,
+            path_rank: path_rank(p.address, &rankings),
+            mnemonic: mnemonic(&mnemonics, p.address).to_string(),
+            statistics: p.statistics,
+            location: p.location,
+            distribution: distributions.remove(&p.address),
+            context: contexts.remove(&p.address),
+            merged_addresses: p.merged_addresses,
+        })
+        .collect();
+
+    Report {
+        metric: deserialize_metric(config),
+        predicates,
+    }
+}
+
+/// Writes `report.json` and the self-contained `report.html` to the evaluation folder.
+pub fn write_report(config: &Config) {
+    let report = build_report(config);
+
+    let content = serde_json::to_string_pretty(&report).expect("Could not serialize report");
+    write_file(&format!("{}/report.json", config.eval_dir), content);
+
+    // the report is embedded in a script tag, which must not be closed by its content
+    let embedded = serde_json::to_string(&report)
+        .expect("Could not serialize report")
+        .replace("</", "<\\/");
+    write_file(
+        &format!("{}/report.html", config.eval_dir),
+        HTML_TEMPLATE.replace("{{REPORT}}", &embedded),
+    );
+}
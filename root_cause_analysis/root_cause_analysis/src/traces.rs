@@ -1,7 +1,17 @@
 use crate::config::Config;
+use crate::monitor::find_executable;
 use crate::utils::{read_file, write_file};
+use glob::glob;
+use rayon::prelude::*;
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use trace_analysis::disassembly::BlockContext;
+use trace_analysis::distribution::ValueDistribution;
+use trace_analysis::functions::dump_functions;
 use trace_analysis::predicates::SerializedPredicate;
+use trace_analysis::symbolization::Symbolizer;
+use trace_analysis::trace::Trace;
 use trace_analysis::trace_analyzer::TraceAnalyzer;
 
 pub fn analyze_traces(config: &Config) {
@@ -11,21 +21,57 @@ pub fn analyze_traces(config: &Config) {
     } else {
         None
     };
-    let trace_analysis_config = trace_analysis::config::Config::default(
+    let mut trace_analysis_config = trace_analysis::config::Config::default(
         &config.trace_dir,
         &trace_analysis_output_dir,
         &crash_blacklist_path,
     );
-    let trace_analyzer = TraceAnalyzer::new(&trace_analysis_config);
+    trace_analysis_config.combine_predicates = config.combine_predicates;
+    trace_analysis_config.collapse_predicates = config.collapse_predicates;
+    trace_analysis_config.scoring = config.scoring.to_string();
+    trace_analysis_config.stream_traces = config.stream_traces;
+    trace_analysis_config.cache_analysis = config.cache_analysis;
+    trace_analysis_config.trace_error_policy = config.trace_error_policy.to_string();
+    let trace_analyzer = TraceAnalyzer::new(&trace_analysis_config)
+        .unwrap_or_else(|error| panic!("Could not analyze traces: {}", error));
 
     println!("dumping linear scores");
     trace_analyzer.dump_scores(&trace_analysis_config, false, false);
 
-    let predicates = trace_analyzer.get_predicates_better_than(0.9);
+    let min_score = config
+        .min_score
+        .unwrap_or_else(|| 0.9 * trace_analyzer.max_score());
+    let mut predicates =
+        trace_analyzer.get_predicates_better_than(min_score, config.filter_lower_bound);
+
+    let symbolizer = load_symbolizer(config, trace_analyzer.image_base());
+    if let Some(symbolizer) = &symbolizer {
+        symbolizer.symbolize_predicates(predicates.iter_mut());
+    }
 
     serialize_mnemonics(config, &predicates, &trace_analyzer);
 
+    serialize_distributions(config, &predicates, &trace_analyzer);
+
+    serialize_disassembly(config, &predicates, &trace_analyzer);
+
     serialize_predicates(config, &predicates);
+
+    println!("dumping functions");
+    dump_functions(&trace_analysis_config, &trace_analyzer, symbolizer.as_ref());
+}
+
+/// Symbolizer for the traced executable, if it is in the evaluation folder and can be loaded.
+fn load_symbolizer(config: &Config, image_base: usize) -> Option<Symbolizer> {
+    let executable = find_executable(config)?;
+
+    match Symbolizer::new(&executable, image_base) {
+        Ok(symbolizer) => Some(symbolizer),
+        Err(error) => {
+            eprintln!("skipping symbolization: {}", error);
+            None
+        }
+    }
 }
 
 fn serialize_predicates(config: &Config, predicates: &Vec<SerializedPredicate>) {
@@ -57,3 +103,81 @@ pub fn deserialize_mnemonics(config: &Config) -> HashMap<usize, String> {
     let content = read_file(&format!("{}/mnemonics.json", config.eval_dir));
     serde_json::from_str(&content).expect("Could not deserialize mnemonics")
 }
+
+/// Mnemonic at `address`, or a placeholder if `mnemonics.json` does not contain it.
+pub fn mnemonic(mnemonics: &HashMap<usize, String>, address: usize) -> &str {
+    mnemonics.get(&address).map_or("<unknown>", String::as_str)
+}
+
+fn serialize_distributions(
+    config: &Config,
+    predicates: &Vec<SerializedPredicate>,
+    trace_analyzer: &TraceAnalyzer,
+) {
+    let distributions: Vec<_> = predicates
+        .iter()
+        .filter_map(|p| ValueDistribution::of_predicate(p, trace_analyzer))
+        .collect();
+    let content =
+        serde_json::to_string(&distributions).expect("Could not serialize value distributions");
+    write_file(&format!("{}/distributions.json", config.eval_dir), content);
+}
+
+pub fn deserialize_distributions(config: &Config) -> Vec<ValueDistribution> {
+    let content = read_file(&format!("{}/distributions.json", config.eval_dir));
+    serde_json::from_str(&content).expect("Could not deserialize value distributions")
+}
+
+fn serialize_disassembly(
+    config: &Config,
+    predicates: &Vec<SerializedPredicate>,
+    trace_analyzer: &TraceAnalyzer,
+) {
+    let contexts: Vec<_> = predicates
+        .iter()
+        .filter_map(|p| BlockContext::new(p, trace_analyzer))
+        .collect();
+    let content = serde_json::to_string(&contexts).expect("Could not serialize disassembly");
+    write_file(&format!("{}/disassembly.json", config.eval_dir), content);
+}
+
+pub fn deserialize_disassembly(config: &Config) -> Vec<BlockContext> {
+    let content = read_file(&format!("{}/disassembly.json", config.eval_dir));
+    serde_json::from_str(&content).expect("Could not deserialize disassembly")
+}
+
+pub fn convert_traces(input_dir: &String, output_dir: &String) {
+    fs::create_dir_all(output_dir).expect(&format!("Could not create folder {}", output_dir));
+
+    // not `glob_paths`, which panics on paths that are not valid UTF-8
+    let paths: Vec<PathBuf> = ["*.zip", "*trace"]
+        .iter()
+        .flat_map(|pattern| glob(&format!("{}/{}", input_dir, pattern)).unwrap())
+        .filter_map(Result::ok)
+        .collect();
+
+    let errors: Vec<_> = paths
+        .par_iter()
+        .filter_map(|path| {
+            let (path, file_name) = match (
+                path.to_str(),
+                path.file_name().and_then(|name| name.to_str()),
+            ) {
+                (Some(path), Some(file_name)) => (path, file_name),
+                _ => return Some(format!("{}: file name is not valid UTF-8", path.display())),
+            };
+            let output_path = match file_name.ends_with(".zip") {
+                true => format!("{}/{}", output_dir, file_name),
+                false => format!("{}/{}.zip", output_dir, file_name),
+            };
+            Trace::convert_to_binary(path, &output_path)
+                .err()
+                .map(|error| error.to_string())
+        })
+        .collect();
+
+    for error in errors.iter() {
+        eprintln!("skipping trace: {}", error);
+    }
+    println!("converted {} traces", paths.len() - errors.len());
+}
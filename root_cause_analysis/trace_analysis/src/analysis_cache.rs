@@ -0,0 +1,288 @@
+use crate::builder::Progress;
+use crate::error::TraceError;
+use crate::predicate_analysis::PredicateAnalyzer;
+use crate::predicates::{empty, Predicate};
+use crate::trace_aggregates::{LabeledCount, TraceAggregates};
+use crate::trace_analyzer::{load_trace, MemoryAddresses, RejectedTrace, TraceAnalyzer};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::mem;
+use std::sync::Mutex;
+
+const CACHE_VERSION: u32 = 3;
+
+/// 64-bit FNV-1a hash of a trace file.
+pub fn content_hash(content: &[u8]) -> u64 {
+    content.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TraceSummary {
+    pub path: String,
+    pub crash: bool,
+}
+
+/// Number of crashing and non-crashing copies of a trace.
+fn copies(summaries: &[TraceSummary]) -> LabeledCount {
+    let crashes = summaries.iter().filter(|summary| summary.crash).count();
+    LabeledCount {
+        crashes,
+        non_crashes: summaries.len() - crashes,
+    }
+}
+
+/// Predicate at an address and the number of crashes and non-crashes satisfying it.
+#[derive(Serialize, Deserialize)]
+struct CachedPredicate {
+    name: String,
+    satisfied: LabeledCount,
+}
+
+/// Aggregates and best predicates of previous runs.
+///
+/// Traces are identified by their content hash; like without the cache, every copy of a trace is
+/// aggregated. New traces are added to the aggregates; removed or modified traces invalidate the
+/// whole cache. Predicates are only synthesized again at addresses visited by new traces, since the
+/// aggregates of all other addresses did not change. There, the cached predicates are only rescored
+/// with the new number of crashes and non-crashes: their thresholds and the candidates are kept,
+/// although a fresh analysis may pick other thresholds or predicates since the best ones depend on
+/// the number of traces.
+#[derive(Serialize, Deserialize)]
+pub struct AnalysisCache {
+    version: u32,
+    /// all copies of each trace
+    pub traces: HashMap<u64, Vec<TraceSummary>>,
+    pub aggregates: TraceAggregates,
+    /// settings the cached predicates depend on
+    scoring: String,
+    memory_addresses: Option<MemoryAddresses>,
+    image: (usize, usize),
+    top_predicates: usize,
+    /// best predicates per address, best first
+    predicates: HashMap<usize, Vec<CachedPredicate>>,
+    /// addresses visited by traces added since the cache was loaded
+    #[serde(skip)]
+    dirty_addresses: HashSet<usize>,
+}
+
+impl AnalysisCache {
+    fn new() -> AnalysisCache {
+        AnalysisCache {
+            version: CACHE_VERSION,
+            traces: HashMap::new(),
+            aggregates: TraceAggregates::new(),
+            scoring: String::new(),
+            memory_addresses: None,
+            image: (0, 0),
+            top_predicates: 0,
+            predicates: HashMap::new(),
+            dirty_addresses: HashSet::new(),
+        }
+    }
+
+    /// Loads the cache from `file_path`; starts from scratch if there is no usable cache.
+    pub fn load(file_path: &str, progress: &dyn Fn(&Progress)) -> AnalysisCache {
+        let content = match fs::read(file_path) {
+            Ok(content) => content,
+            Err(_) => return AnalysisCache::new(),
+        };
+
+        match bincode::deserialize::<AnalysisCache>(&content) {
+            Ok(cache) if cache.version == CACHE_VERSION => cache,
+            _ => {
+                progress(&Progress::CacheDiscarded);
+                AnalysisCache::new()
+            }
+        }
+    }
+
+    pub fn store(
+        &mut self,
+        file_path: &str,
+        trace_analyzer: &mut TraceAnalyzer,
+    ) -> Result<(), TraceError> {
+        for address in self.dirty_addresses.drain() {
+            self.predicates.remove(&address);
+        }
+        for (address, predicates) in trace_analyzer.address_predicates.iter() {
+            self.predicates.insert(
+                *address,
+                predicates
+                    .iter()
+                    .map(|predicate| CachedPredicate {
+                        name: predicate.name.to_string(),
+                        satisfied: LabeledCount {
+                            crashes: predicate.matrix.true_positives,
+                            non_crashes: predicate.matrix.false_positives,
+                        },
+                    })
+                    .collect(),
+            );
+        }
+        self.scoring = trace_analyzer.scoring.name().to_string();
+        self.memory_addresses = Some(trace_analyzer.memory_addresses.clone());
+
+        self.aggregates = trace_analyzer.aggregates.take().unwrap();
+        let content = bincode::serialize(self).expect("Could not serialize analysis cache");
+        trace_analyzer.aggregates = Some(mem::take(&mut self.aggregates));
+
+        fs::write(file_path, content).map_err(|source| TraceError::Io {
+            path: file_path.to_string(),
+            source,
+        })
+    }
+
+    /// Hashes all traces and aggregates the ones not contained in the cache.
+    pub fn update(
+        &mut self,
+        crashes: &[String],
+        non_crashes: &[String],
+        fail_on_trace_error: bool,
+        rejected_traces: &mut Vec<RejectedTrace>,
+        progress: &dyn Fn(&Progress),
+    ) -> Result<(), TraceError> {
+        let paths: Vec<(String, bool)> = crashes
+            .iter()
+            .map(|path| (path.to_string(), true))
+            .chain(non_crashes.iter().map(|path| (path.to_string(), false)))
+            .collect();
+
+        let mut current: HashMap<u64, Vec<TraceSummary>> = HashMap::new();
+        let hashed: Vec<_> = paths
+            .into_par_iter()
+            .map(|(path, crash)| match fs::read(&path) {
+                Ok(content) => Ok((content_hash(&content), TraceSummary { path, crash })),
+                Err(source) => Err((TraceError::Io { path, source }, crash)),
+            })
+            .collect();
+        for hashed in hashed {
+            match hashed {
+                Ok((hash, summary)) => current.entry(hash).or_default().push(summary),
+                Err((error, _)) if fail_on_trace_error => return Err(error),
+                Err((error, crash)) => rejected_traces.push(RejectedTrace::new(error, crash)),
+            }
+        }
+
+        if self.traces.iter().any(|(hash, cached)| {
+            let (cached, current) = (copies(cached), copies(current.get(hash).map_or(&[], |c| c)));
+            current.crashes < cached.crashes || current.non_crashes < cached.non_crashes
+        }) {
+            progress(&Progress::CacheRebuilt);
+            *self = AnalysisCache::new();
+        }
+
+        // traces with more copies than cached, and the number of new crashing and non-crashing copies
+        let new_traces: Vec<_> = current
+            .into_iter()
+            .filter_map(|(hash, summaries)| {
+                let cached = copies(self.traces.get(&hash).map_or(&[], |c| c));
+                let current = copies(&summaries);
+                let new = LabeledCount {
+                    crashes: current.crashes - cached.crashes,
+                    non_crashes: current.non_crashes - cached.non_crashes,
+                };
+                match new.crashes + new.non_crashes {
+                    0 => None,
+                    _ => Some((hash, summaries, new)),
+                }
+            })
+            .collect();
+        progress(&Progress::CacheUpdated {
+            cached: self.traces.values().map(Vec::len).sum(),
+            new: new_traces
+                .iter()
+                .map(|(_, _, new)| new.crashes + new.non_crashes)
+                .sum(),
+        });
+
+        let rejected = Mutex::new(vec![]);
+        {
+            let cache = Mutex::new(&mut *self);
+            new_traces
+                .into_par_iter()
+                .map(|(hash, summaries, new)| {
+                    (
+                        load_trace(summaries[0].path.to_string()),
+                        hash,
+                        summaries,
+                        new,
+                    )
+                })
+                .try_for_each(|(trace, hash, summaries, new)| {
+                    match trace {
+                        Ok(trace) => {
+                            let mut cache = cache.lock().unwrap();
+                            for crash in (0..new.crashes)
+                                .map(|_| true)
+                                .chain((0..new.non_crashes).map(|_| false))
+                            {
+                                cache.aggregates.add_trace(&trace, crash);
+                            }
+                            cache.dirty_addresses.extend(trace.instructions.keys());
+                            cache.traces.insert(hash, summaries);
+                        }
+                        Err(error) if fail_on_trace_error => return Err(error),
+                        Err(error) => rejected
+                            .lock()
+                            .unwrap()
+                            .push(RejectedTrace::new(error, summaries[0].crash)),
+                    }
+                    Ok(())
+                })?;
+        }
+        rejected_traces.extend(rejected.into_inner().unwrap());
+
+        self.aggregates.finalize();
+
+        Ok(())
+    }
+
+    /// Takes the aggregates out of the cache; they are put back by `store`.
+    pub fn take_aggregates(&mut self) -> TraceAggregates {
+        mem::take(&mut self.aggregates)
+    }
+
+    /// Discards the cached predicates if the settings or image bounds they depend on changed.
+    pub fn check_predicates(&mut self, trace_analyzer: &TraceAnalyzer, top_predicates: usize) {
+        let aggregates = trace_analyzer.aggregates.as_ref().unwrap();
+        if self.top_predicates != top_predicates
+            || self.scoring != trace_analyzer.scoring.name()
+            || self.memory_addresses != Some(trace_analyzer.memory_addresses.clone())
+            || self.image != (aggregates.image_start, aggregates.image_end)
+        {
+            self.predicates.clear();
+        }
+        self.image = (aggregates.image_start, aggregates.image_end);
+        self.top_predicates = top_predicates;
+    }
+
+    /// Rescores and reranks the cached predicates at `address` unless new traces visited it.
+    pub fn cached_predicates(
+        &self,
+        address: usize,
+        trace_analyzer: &TraceAnalyzer,
+    ) -> Option<Vec<Predicate>> {
+        if self.dirty_addresses.contains(&address) {
+            return None;
+        }
+
+        self.predicates.get(&address).map(|cached| {
+            let predicates = cached
+                .iter()
+                .map(|cached| {
+                    // cached predicates are only scored, never executed on traces
+                    PredicateAnalyzer::scored(
+                        trace_analyzer,
+                        Predicate::new(&cached.name, address, empty, None, None),
+                        cached.satisfied,
+                    )
+                })
+                .collect();
+            Predicate::top_k(predicates, self.top_predicates)
+        })
+    }
+}
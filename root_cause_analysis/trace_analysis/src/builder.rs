@@ -0,0 +1,459 @@
+use crate::analysis_cache::AnalysisCache;
+use crate::control_flow_graph::{CFGCollector, ControlFlowGraph};
+use crate::error::TraceError;
+use crate::memory_regions::MemoryMap;
+use crate::scoring::{ln_factorials, BalancedAccuracy, ScoringFunction};
+use crate::trace::{Trace, TraceVec};
+use crate::trace_aggregates::TraceAggregates;
+use crate::trace_analyzer::{load_trace, MemoryAddresses, RejectedTrace, TraceAnalyzer};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Mutex;
+
+/// Where the traces of one class (crashes or non-crashes) come from.
+pub enum TraceSource {
+    /// trace files, zipped if their extension is `.zip`; loaded in parallel
+    Paths(Vec<String>),
+    Traces(Vec<Trace>),
+    Iter(Box<dyn Iterator<Item = Result<Trace, TraceError>> + Send>),
+}
+
+impl TraceSource {
+    fn paths(&self) -> Option<&Vec<String>> {
+        match self {
+            TraceSource::Paths(paths) => Some(paths),
+            _ => None,
+        }
+    }
+
+    /// Passes each loaded trace to `f`; unloadable traces are rejected unless `fail` is set.
+    fn for_each(
+        self,
+        crash: bool,
+        fail: bool,
+        rejected_traces: &mut Vec<RejectedTrace>,
+        f: impl Fn(Trace) + Send + Sync,
+    ) -> Result<(), TraceError> {
+        let handle = |trace: Result<Trace, TraceError>, rejected: &Mutex<Vec<RejectedTrace>>| {
+            match trace {
+                Ok(trace) => f(trace),
+                Err(error) if fail => return Err(error),
+                Err(error) => rejected
+                    .lock()
+                    .unwrap()
+                    .push(RejectedTrace::new(error, crash)),
+            }
+            Ok(())
+        };
+
+        let rejected = Mutex::new(vec![]);
+        match self {
+            TraceSource::Paths(paths) => paths
+                .into_par_iter()
+                .try_for_each(|path| handle(load_trace(path), &rejected))?,
+            TraceSource::Traces(traces) => traces
+                .into_iter()
+                .try_for_each(|trace| handle(Ok(trace), &rejected))?,
+            TraceSource::Iter(mut traces) => {
+                traces.try_for_each(|trace| handle(trace, &rejected))?
+            }
+        }
+        rejected_traces.extend(rejected.into_inner().unwrap());
+
+        Ok(())
+    }
+
+    /// Loads all traces, keeping the order of the source.
+    fn load(
+        self,
+        crash: bool,
+        fail: bool,
+        rejected_traces: &mut Vec<RejectedTrace>,
+    ) -> Result<Vec<Trace>, TraceError> {
+        let traces: Vec<Result<Trace, TraceError>> = match self {
+            TraceSource::Paths(paths) => paths.into_par_iter().map(load_trace).collect(),
+            TraceSource::Traces(traces) => return Ok(traces),
+            TraceSource::Iter(traces) => traces.collect(),
+        };
+
+        let mut ret = vec![];
+        for trace in traces {
+            match trace {
+                Ok(trace) => ret.push(trace),
+                Err(error) if fail => return Err(error),
+                Err(error) => rejected_traces.push(RejectedTrace::new(error, crash)),
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Progress of `TraceAnalyzerBuilder::build`.
+pub enum Progress {
+    ReadingCrashes,
+    ReadingNonCrashes,
+    CacheDiscarded,
+    CacheRebuilt,
+    CacheUpdated {
+        cached: usize,
+        new: usize,
+    },
+    TracesRead {
+        crashes: usize,
+        non_crashes: usize,
+        rejected: usize,
+    },
+    FillingCfg,
+    CalculatingScores,
+    StoringCache,
+    CollapsingPredicates,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Progress::ReadingCrashes => write!(f, "reading crashes"),
+            Progress::ReadingNonCrashes => write!(f, "reading non-crashes"),
+            Progress::CacheDiscarded => write!(f, "discarding outdated analysis cache"),
+            Progress::CacheRebuilt => write!(
+                f,
+                "traces were removed or modified, rebuilding analysis cache"
+            ),
+            Progress::CacheUpdated { cached, new } => {
+                write!(f, "{} cached and {} new traces", cached, new)
+            }
+            Progress::TracesRead {
+                crashes,
+                non_crashes,
+                rejected: 0,
+            } => write!(f, "{} crashes and {} non-crashes", crashes, non_crashes),
+            Progress::TracesRead {
+                crashes,
+                non_crashes,
+                rejected,
+            } => write!(
+                f,
+                "{} crashes and {} non-crashes (skipped {} traces)",
+                crashes, non_crashes, rejected
+            ),
+            Progress::FillingCfg => write!(f, "filling cfg"),
+            Progress::CalculatingScores => write!(f, "calculating scores"),
+            Progress::StoringCache => write!(f, "storing analysis cache"),
+            Progress::CollapsingPredicates => write!(f, "collapsing redundant predicates"),
+        }
+    }
+}
+
+/// Builds a `TraceAnalyzer` without touching the process: no output, no files besides the cache.
+///
+/// ```ignore
+/// let trace_analyzer = TraceAnalyzerBuilder::new(
+///     TraceSource::Paths(crash_paths),
+///     TraceSource::Traces(non_crashes),
+/// )
+/// .memory_addresses(memory_addresses)
+/// .progress(|progress| log(progress.to_string()))
+/// .build()?;
+/// ```
+pub struct TraceAnalyzerBuilder {
+    crashes: TraceSource,
+    non_crashes: TraceSource,
+    memory_addresses: MemoryAddresses,
+    scoring: Box<dyn ScoringFunction>,
+    stream_traces: bool,
+    cache_file: Option<String>,
+    filter_non_crashes: bool,
+    fail_on_trace_error: bool,
+    combine_predicates: usize,
+    top_predicates: usize,
+    fill_cfg: bool,
+    fill_scores: bool,
+    collapse_predicates: bool,
+    progress: Box<dyn Fn(&Progress) + Send + Sync>,
+}
+
+impl TraceAnalyzerBuilder {
+    pub fn new(crashes: TraceSource, non_crashes: TraceSource) -> TraceAnalyzerBuilder {
+        TraceAnalyzerBuilder {
+            crashes,
+            non_crashes,
+            memory_addresses: MemoryAddresses::default(),
+            scoring: Box::new(BalancedAccuracy),
+            stream_traces: false,
+            cache_file: None,
+            filter_non_crashes: false,
+            fail_on_trace_error: false,
+            combine_predicates: 0,
+            top_predicates: 3,
+            fill_cfg: true,
+            fill_scores: true,
+            collapse_predicates: false,
+            progress: Box::new(|_| {}),
+        }
+    }
+
+    /// Heap and stack ranges used by the region predicates.
+    pub fn memory_addresses(mut self, memory_addresses: MemoryAddresses) -> Self {
+        self.memory_addresses = memory_addresses;
+        self
+    }
+
+    pub fn scoring(mut self, scoring: Box<dyn ScoringFunction>) -> Self {
+        self.scoring = scoring;
+        self
+    }
+
+    /// Aggregates traces while reading them instead of keeping them in memory.
+    pub fn stream_traces(mut self, stream_traces: bool) -> Self {
+        self.stream_traces = stream_traces;
+        self
+    }
+
+    /// Caches aggregates and predicates in `cache_file` (implies streaming).
+    ///
+    /// Only used if both sources are paths and non-crashes are not filtered.
+    pub fn cache_file(mut self, cache_file: Option<String>) -> Self {
+        self.cache_file = cache_file;
+        self
+    }
+
+    /// Ignores non-crashes that do not visit the last address of any crash.
+    pub fn filter_non_crashes(mut self, filter_non_crashes: bool) -> Self {
+        self.filter_non_crashes = filter_non_crashes;
+        self
+    }
+
+    /// Fails on the first trace that cannot be loaded instead of skipping it.
+    pub fn fail_on_trace_error(mut self, fail_on_trace_error: bool) -> Self {
+        self.fail_on_trace_error = fail_on_trace_error;
+        self
+    }
+
+    pub fn combine_predicates(mut self, combine_predicates: usize) -> Self {
+        self.combine_predicates = combine_predicates;
+        self
+    }
+
+    /// Number of best predicates kept per address.
+    pub fn top_predicates(mut self, top_predicates: usize) -> Self {
+        self.top_predicates = top_predicates;
+        self
+    }
+
+    pub fn fill_cfg(mut self, fill_cfg: bool) -> Self {
+        self.fill_cfg = fill_cfg;
+        self
+    }
+
+    pub fn fill_scores(mut self, fill_scores: bool) -> Self {
+        self.fill_scores = fill_scores;
+        self
+    }
+
+    /// Collapses predicates equivalent to a predicate at a dominating or post-dominating address.
+    ///
+    /// Runs after storing the analysis cache, which keeps all predicates.
+    pub fn collapse_predicates(mut self, collapse_predicates: bool) -> Self {
+        self.collapse_predicates = collapse_predicates;
+        self
+    }
+
+    pub fn progress(mut self, progress: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Box::new(progress);
+        self
+    }
+
+    pub fn build(mut self) -> Result<TraceAnalyzer, TraceError> {
+        let use_cache = self.cache_file.is_some()
+            && !self.filter_non_crashes
+            && self.crashes.paths().is_some()
+            && self.non_crashes.paths().is_some();
+
+        let mut trace_analyzer = match (use_cache, self.stream_traces) {
+            (true, _) => self.analyze_cached()?,
+            (false, true) => self.analyze_aggregated()?,
+            (false, false) => self.analyze_traces()?,
+        };
+
+        if self.fill_cfg || self.fill_scores {
+            (self.progress)(&Progress::FillingCfg);
+            trace_analyzer.fill_cfg(&mut CFGCollector::new());
+        }
+
+        if self.fill_scores {
+            (self.progress)(&Progress::CalculatingScores);
+            trace_analyzer.fill_address_scores(self.combine_predicates, self.top_predicates);
+        }
+
+        if let Some(mut cache) = trace_analyzer.cache.take() {
+            (self.progress)(&Progress::StoringCache);
+            cache.store(self.cache_file.as_ref().unwrap(), &mut trace_analyzer)?;
+            trace_analyzer.cache = Some(cache);
+        }
+
+        // after storing the cache, so that cached runs can toggle collapsing
+        if self.fill_scores && self.collapse_predicates {
+            (self.progress)(&Progress::CollapsingPredicates);
+            trace_analyzer.collapse_predicates();
+        }
+
+        Ok(trace_analyzer)
+    }
+
+    fn sources(&mut self) -> (TraceSource, TraceSource) {
+        (
+            std::mem::replace(&mut self.crashes, TraceSource::Traces(vec![])),
+            std::mem::replace(&mut self.non_crashes, TraceSource::Traces(vec![])),
+        )
+    }
+
+    fn analyzer(
+        &mut self,
+        crashes: TraceVec,
+        non_crashes: TraceVec,
+        memory_map: MemoryMap,
+        aggregates: Option<TraceAggregates>,
+        rejected_traces: Vec<RejectedTrace>,
+    ) -> TraceAnalyzer {
+        let mut trace_analyzer = TraceAnalyzer {
+            crashes,
+            non_crashes,
+            address_scores: HashMap::new(),
+            address_predicates: HashMap::new(),
+            cfg: ControlFlowGraph::new(),
+            memory_addresses: self.memory_addresses.clone(),
+            memory_map,
+            scoring: std::mem::replace(&mut self.scoring, Box::new(BalancedAccuracy)),
+            ln_factorials: vec![],
+            aggregates,
+            rejected_traces,
+            cache: None,
+            merged_addresses: HashMap::new(),
+        };
+        let counts = trace_analyzer.trace_counts();
+        trace_analyzer.ln_factorials = ln_factorials(counts.crashes + counts.non_crashes);
+
+        trace_analyzer
+    }
+
+    fn analyze_traces(&mut self) -> Result<TraceAnalyzer, TraceError> {
+        let (crash_source, non_crash_source) = self.sources();
+        let mut rejected_traces = vec![];
+
+        (self.progress)(&Progress::ReadingCrashes);
+        let crashes = crash_source.load(true, self.fail_on_trace_error, &mut rejected_traces)?;
+        let crashing_addresses: HashSet<usize> = crashes.iter().map(|t| t.last_address).collect();
+
+        (self.progress)(&Progress::ReadingNonCrashes);
+        let non_crashes: Vec<_> = non_crash_source
+            .load(false, self.fail_on_trace_error, &mut rejected_traces)?
+            .into_iter()
+            .filter(|t| !self.filter_non_crashes || visits_any(t, &crashing_addresses))
+            .collect();
+
+        (self.progress)(&Progress::TracesRead {
+            crashes: crashes.len(),
+            non_crashes: non_crashes.len(),
+            rejected: rejected_traces.len(),
+        });
+
+        let (crashes, non_crashes) = (TraceVec::from_vec(crashes), TraceVec::from_vec(non_crashes));
+        let memory_map = MemoryMap::new(&self.memory_addresses, &[&crashes, &non_crashes]);
+
+        Ok(self.analyzer(crashes, non_crashes, memory_map, None, rejected_traces))
+    }
+
+    fn analyze_aggregated(&mut self) -> Result<TraceAnalyzer, TraceError> {
+        let (crash_source, non_crash_source) = self.sources();
+        let mut rejected_traces = vec![];
+        let aggregates = Mutex::new(TraceAggregates::new());
+
+        (self.progress)(&Progress::ReadingCrashes);
+        crash_source.for_each(true, self.fail_on_trace_error, &mut rejected_traces, |t| {
+            aggregates.lock().unwrap().add_trace(&t, true)
+        })?;
+        let crashing_addresses = aggregates.lock().unwrap().crashing_addresses.clone();
+
+        (self.progress)(&Progress::ReadingNonCrashes);
+        let filter_non_crashes = self.filter_non_crashes;
+        non_crash_source.for_each(false, self.fail_on_trace_error, &mut rejected_traces, |t| {
+            if !filter_non_crashes || visits_any(&t, &crashing_addresses) {
+                aggregates.lock().unwrap().add_trace(&t, false)
+            }
+        })?;
+
+        let mut aggregates = aggregates.into_inner().unwrap();
+        aggregates.finalize();
+
+        (self.progress)(&Progress::TracesRead {
+            crashes: aggregates.num_crashes,
+            non_crashes: aggregates.num_non_crashes,
+            rejected: rejected_traces.len(),
+        });
+
+        let memory_map = MemoryMap::with_image(
+            &self.memory_addresses,
+            aggregates.image_start,
+            aggregates.image_end,
+        );
+        let (crashes, non_crashes) = (TraceVec::from_vec(vec![]), TraceVec::from_vec(vec![]));
+
+        Ok(self.analyzer(
+            crashes,
+            non_crashes,
+            memory_map,
+            Some(aggregates),
+            rejected_traces,
+        ))
+    }
+
+    /// Streams only the traces not contained in the analysis cache.
+    fn analyze_cached(&mut self) -> Result<TraceAnalyzer, TraceError> {
+        let (crash_source, non_crash_source) = self.sources();
+        let cache_file = self.cache_file.clone().unwrap();
+        let mut rejected_traces = vec![];
+
+        let mut cache = AnalysisCache::load(&cache_file, &*self.progress);
+        cache.update(
+            crash_source.paths().unwrap(),
+            non_crash_source.paths().unwrap(),
+            self.fail_on_trace_error,
+            &mut rejected_traces,
+            &*self.progress,
+        )?;
+        let aggregates = cache.take_aggregates();
+
+        (self.progress)(&Progress::TracesRead {
+            crashes: aggregates.num_crashes,
+            non_crashes: aggregates.num_non_crashes,
+            rejected: rejected_traces.len(),
+        });
+
+        let memory_map = MemoryMap::with_image(
+            &self.memory_addresses,
+            aggregates.image_start,
+            aggregates.image_end,
+        );
+        let (crashes, non_crashes) = (TraceVec::from_vec(vec![]), TraceVec::from_vec(vec![]));
+
+        let mut trace_analyzer = self.analyzer(
+            crashes,
+            non_crashes,
+            memory_map,
+            Some(aggregates),
+            rejected_traces,
+        );
+        cache.check_predicates(&trace_analyzer, self.top_predicates);
+        trace_analyzer.cache = Some(cache);
+
+        Ok(trace_analyzer)
+    }
+}
+
+fn visits_any(trace: &Trace, addresses: &HashSet<usize>) -> bool {
+    addresses
+        .iter()
+        .any(|address| trace.instructions.contains_key(address))
+}
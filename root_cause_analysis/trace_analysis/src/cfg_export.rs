@@ -0,0 +1,227 @@
+use crate::config::Config;
+use crate::functions::{CallGraph, Function};
+use crate::symbolization::Symbolizer;
+use crate::trace::EdgeType;
+use crate::trace_analyzer::TraceAnalyzer;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+/// Block of an exported function, annotated with its best predicate and visits.
+#[derive(Serialize, Deserialize)]
+pub struct CfgNode {
+    pub start: usize,
+    pub instructions: Vec<(usize, String)>,
+    /// best predicate score of any instruction in the block
+    pub score: Option<f64>,
+    pub predicate: Option<String>,
+    /// rank among the exported top predicates, starting at 1
+    pub rank: Option<usize>,
+    pub crash_visits: usize,
+    pub non_crash_visits: usize,
+    /// fill color from white (score 0.5 or none) to red (score 1)
+    pub color: String,
+}
+
+/// Edge between the starts of two blocks.
+#[derive(Serialize, Deserialize)]
+pub struct CfgEdge {
+    pub from: usize,
+    pub to: usize,
+    pub edge_type: EdgeType,
+}
+
+/// Control flow graph of a function containing top-ranked predicates.
+#[derive(Serialize, Deserialize)]
+pub struct FunctionCfg {
+    pub entry: usize,
+    pub name: Option<String>,
+    pub crashes: usize,
+    pub non_crashes: usize,
+    pub nodes: Vec<CfgNode>,
+    pub edges: Vec<CfgEdge>,
+}
+
+/// Fill color from white (score 0.5 or none) to red (score 1).
+pub fn heat_color(score: Option<f64>) -> String {
+    let heat = score
+        .map(|s| ((s - 0.5) / 0.5).clamp(0.0, 1.0))
+        .unwrap_or(0.0);
+    let channel = |to: f64| (255.0 + (to - 255.0) * heat).round() as u8;
+
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(214.0),
+        channel(39.0),
+        channel(40.0)
+    )
+}
+
+fn ratio(visits: usize, total: usize) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => 100.0 * visits as f64 / total as f64,
+    }
+}
+
+/// Escapes quotes and backslashes in a Graphviz label.
+pub fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl FunctionCfg {
+    pub fn new(
+        function: &Function,
+        trace_analyzer: &TraceAnalyzer,
+        ranks: &HashMap<usize, usize>,
+        name: Option<String>,
+    ) -> FunctionCfg {
+        let totals = trace_analyzer.trace_counts();
+        let block_starts: HashMap<usize, usize> = function
+            .blocks
+            .iter()
+            .map(|bb| (bb.exit(), bb.start))
+            .collect();
+        let nodes = function
+            .blocks
+            .iter()
+            .map(|bb| {
+                let best = bb
+                    .body
+                    .iter()
+                    .filter_map(|address| trace_analyzer.address_scores.get(address))
+                    .min_by(|p1, p2| p1.rank(p2));
+                let visits = trace_analyzer.visits(bb.start);
+                CfgNode {
+                    start: bb.start,
+                    instructions: bb
+                        .body
+                        .iter()
+                        .map(|address| (*address, trace_analyzer.get_any_mnemonic(*address)))
+                        .collect(),
+                    score: best.map(|p| p.score),
+                    predicate: best.map(|p| p.to_string()),
+                    rank: bb.body.iter().filter_map(|a| ranks.get(a)).min().cloned(),
+                    crash_visits: visits.crashes,
+                    non_crash_visits: visits.non_crashes,
+                    color: heat_color(best.map(|p| p.score)),
+                }
+            })
+            .collect();
+
+        FunctionCfg {
+            entry: function.entry,
+            name,
+            crashes: totals.crashes,
+            non_crashes: totals.non_crashes,
+            nodes,
+            edges: function
+                .edges
+                .iter()
+                .map(|(from, to, edge_type)| CfgEdge {
+                    from: block_starts[from],
+                    to: *to,
+                    edge_type: *edge_type,
+                })
+                .collect(),
+        }
+    }
+
+    /// Graphviz graph with one box per block; top-ranked blocks have a bold border.
+    pub fn to_dot(&self) -> String {
+        let mut ret = format!(
+            "digraph \"{}\" {{\n  node [shape=box, style=filled, fontname=\"monospace\"];\n",
+            escape(&self.title())
+        );
+
+        for node in self.nodes.iter() {
+            let mut label: String = node
+                .instructions
+                .iter()
+                .map(|(address, mnemonic)| format!("{:#x}  {}\\l", address, escape(mnemonic)))
+                .collect();
+            if let (Some(score), Some(predicate)) = (node.score, &node.predicate) {
+                label.push_str(&format!("score {} ({})\\l", score, escape(predicate)));
+            }
+            if let Some(rank) = node.rank {
+                label.push_str(&format!("rank {}\\l", rank));
+            }
+            label.push_str(&format!(
+                "crashes {}/{} ({:.1}%), non-crashes {}/{} ({:.1}%)\\l",
+                node.crash_visits,
+                self.crashes,
+                ratio(node.crash_visits, self.crashes),
+                node.non_crash_visits,
+                self.non_crashes,
+                ratio(node.non_crash_visits, self.non_crashes)
+            ));
+
+            ret.push_str(&format!(
+                "  \"{:#x}\" [label=\"{}\", fillcolor=\"{}\"{}];\n",
+                node.start,
+                label,
+                node.color,
+                if node.rank.is_some() {
+                    ", penwidth=3"
+                } else {
+                    ""
+                }
+            ));
+        }
+
+        for edge in self.edges.iter() {
+            ret.push_str(&format!(
+                "  \"{:#x}\" -> \"{:#x}\" [label=\"{:?}\"];\n",
+                edge.from, edge.to, edge.edge_type
+            ));
+        }
+        ret.push_str("}\n");
+
+        ret
+    }
+
+    fn title(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({:#x})", name, self.entry),
+            None => format!("function {:#x}", self.entry),
+        }
+    }
+}
+
+/// Exports the functions containing the `--export-cfg` best predicates to `cfg/` in the output
+/// directory, as `function_<entry>.dot` and `function_<entry>.json`.
+pub fn export_function_cfgs(
+    config: &Config,
+    trace_analyzer: &TraceAnalyzer,
+    symbolizer: Option<&Symbolizer>,
+) {
+    let ranks: HashMap<usize, usize> = trace_analyzer
+        .sort_scores()
+        .iter()
+        .rev()
+        .take(config.export_cfg)
+        .enumerate()
+        .map(|(rank, p)| (p.address, rank + 1))
+        .collect();
+
+    let directory = format!("{}/cfg", config.output_directory);
+    fs::create_dir_all(&directory).expect(&format!("Could not create directory {}", directory));
+
+    for function in CallGraph::recover(trace_analyzer)
+        .functions
+        .iter()
+        .filter(|f| f.addresses().any(|a| ranks.contains_key(a)))
+    {
+        let name = symbolizer
+            .and_then(|s| s.symbolize(function.entry))
+            .and_then(|location| location.function);
+        let cfg = FunctionCfg::new(function, trace_analyzer, &ranks, name);
+
+        let file_path = format!("{}/function_{:#x}", directory, function.entry);
+        fs::write(format!("{}.dot", file_path), cfg.to_dot())
+            .expect(&format!("Could not write file {}.dot", file_path));
+        let content = serde_json::to_string_pretty(&cfg).expect("Could not serialize function CFG");
+        fs::write(format!("{}.json", file_path), content)
+            .expect(&format!("Could not write file {}.json", file_path));
+    }
+}
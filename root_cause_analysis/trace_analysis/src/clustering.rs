@@ -0,0 +1,295 @@
+use crate::builder::TraceSource;
+use crate::config::Config;
+use crate::error::TraceError;
+use crate::predicates::SerializedPredicate;
+use crate::trace::Trace;
+use crate::trace_analyzer::{load_trace, sample_traces, TraceAnalyzer};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+
+/// Number of CFG hops before the crashing instruction whose edges describe the crash context.
+const CRASH_CONTEXT_DEPTH: usize = 8;
+/// Number of best predicates listed per cluster in `clusters.json`.
+const REPORTED_PREDICATES: usize = 10;
+
+/// Features of a crashing trace that tell different bugs apart.
+pub struct CrashSignature {
+    pub path: String,
+    pub crash_site: CrashSite,
+    /// edges within `CRASH_CONTEXT_DEPTH` hops before the crashing instruction
+    pub crash_edges: BTreeSet<(usize, usize)>,
+    pub addresses: HashSet<usize>,
+}
+
+/// Address and mnemonic of the faulting instruction.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CrashSite {
+    pub address: usize,
+    pub mnemonic: String,
+}
+
+impl CrashSignature {
+    pub fn new(path: String, trace: &Trace) -> CrashSignature {
+        let mut predecessors: HashMap<usize, Vec<usize>> = HashMap::new();
+        for instruction in trace.instructions.values() {
+            for succ in instruction.successors.iter() {
+                predecessors
+                    .entry(succ.address)
+                    .or_default()
+                    .push(instruction.address);
+            }
+        }
+
+        let mut crash_edges = BTreeSet::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back((trace.last_address, 0));
+        visited.insert(trace.last_address);
+        while let Some((address, depth)) = queue.pop_front() {
+            if depth == CRASH_CONTEXT_DEPTH {
+                continue;
+            }
+            for pred in predecessors.get(&address).into_iter().flatten() {
+                crash_edges.insert((*pred, address));
+                if visited.insert(*pred) {
+                    queue.push_back((*pred, depth + 1));
+                }
+            }
+        }
+
+        CrashSignature {
+            path,
+            crash_site: CrashSite {
+                address: trace.last_address,
+                mnemonic: trace
+                    .instructions
+                    .get(&trace.last_address)
+                    .map(|i| i.mnemonic.to_string())
+                    .unwrap_or_default(),
+            },
+            crash_edges,
+            addresses: trace.visited_addresses(),
+        }
+    }
+
+    /// Mean of the crash site equality and the Jaccard similarities of crash edges and addresses.
+    ///
+    /// Crashes at different sites score at most 2/3 and are only merged below that threshold.
+    pub fn similarity(&self, other: &CrashSignature) -> f64 {
+        let same_site = (self.crash_site == other.crash_site) as usize as f64;
+        let crash_edges = jaccard(
+            self.crash_edges.intersection(&other.crash_edges).count(),
+            self.crash_edges.len(),
+            other.crash_edges.len(),
+        );
+        let addresses = jaccard(
+            self.addresses.intersection(&other.addresses).count(),
+            self.addresses.len(),
+            other.addresses.len(),
+        );
+
+        (same_site + crash_edges + addresses) / 3.0
+    }
+}
+
+fn jaccard(intersection: usize, len_a: usize, len_b: usize) -> f64 {
+    match len_a + len_b - intersection {
+        0 => 1.0,
+        union => intersection as f64 / union as f64,
+    }
+}
+
+fn find(parents: &mut Vec<usize>, i: usize) -> usize {
+    if parents[i] != i {
+        parents[i] = find(parents, parents[i]);
+    }
+    parents[i]
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CrashCluster {
+    pub id: usize,
+    pub crash_sites: Vec<CrashSite>,
+    pub traces: Vec<String>,
+    /// number of traces with a distinct crash site and crash context
+    pub unique_crashes: usize,
+    /// best predicates of the analysis of this cluster, best first
+    #[serde(default)]
+    pub predicates: Vec<SerializedPredicate>,
+}
+
+/// Single-linkage clustering: crashes end up in the same cluster if they are connected by a chain
+/// of crashes with a similarity of at least `threshold`.
+///
+/// Clusters are ordered by size, largest first.
+pub fn cluster_crashes(signatures: &[CrashSignature], threshold: f64) -> Vec<CrashCluster> {
+    let links: Vec<(usize, usize)> = (0..signatures.len())
+        .into_par_iter()
+        .flat_map(|i| {
+            (i + 1..signatures.len())
+                .filter(|j| signatures[i].similarity(&signatures[*j]) >= threshold)
+                .map(|j| (i, j))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let mut parents: Vec<usize> = (0..signatures.len()).collect();
+    for (i, j) in links {
+        let (i, j) = (find(&mut parents, i), find(&mut parents, j));
+        parents[i.max(j)] = i.min(j);
+    }
+
+    let mut members: HashMap<usize, Vec<&CrashSignature>> = HashMap::new();
+    for (i, signature) in signatures.iter().enumerate() {
+        let root = find(&mut parents, i);
+        members.entry(root).or_default().push(signature);
+    }
+
+    let mut clusters: Vec<Vec<&CrashSignature>> = members.into_values().collect();
+    clusters.sort_by(|c1, c2| c2.len().cmp(&c1.len()).then(c1[0].path.cmp(&c2[0].path)));
+
+    clusters
+        .into_iter()
+        .enumerate()
+        .map(|(id, members)| CrashCluster {
+            id,
+            crash_sites: members
+                .iter()
+                .map(|s| s.crash_site.clone())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            traces: members.iter().map(|s| s.path.to_string()).collect(),
+            unique_crashes: members
+                .iter()
+                .map(|s| (&s.crash_site, &s.crash_edges))
+                .collect::<HashSet<_>>()
+                .len(),
+            predicates: vec![],
+        })
+        .collect()
+}
+
+/// Clusters the crashes and runs the predicate analysis once per cluster against all non-crashes.
+///
+/// The scores of each cluster are written to `cluster_<id>` in the output directory, the clusters
+/// and their best predicates to `clusters.json`.
+pub fn analyze_clusters(config: &Config) -> Result<Vec<CrashCluster>, TraceError> {
+    let sample = sample_traces(config)?;
+
+    println!("clustering {} crashes", sample.crashes.len());
+    let signatures: Vec<Result<CrashSignature, TraceError>> = sample
+        .crashes
+        .into_par_iter()
+        .map(|path| {
+            let trace = load_trace(path.to_string())?;
+            Ok(CrashSignature::new(path, &trace))
+        })
+        .collect();
+    let mut loaded = vec![];
+    for signature in signatures {
+        match signature {
+            Ok(signature) => loaded.push(signature),
+            Err(error) if config.fail_on_trace_error() => return Err(error),
+            Err(error) => println!("skipping crash: {}", error),
+        }
+    }
+
+    let mut clusters = cluster_crashes(&loaded, config.cluster_threshold);
+    println!("{} clusters", clusters.len());
+
+    for cluster in clusters.iter_mut() {
+        println!(
+            "analyzing cluster {} ({} crashes)",
+            cluster.id,
+            cluster.traces.len()
+        );
+
+        let mut cluster_config = config.clone();
+        cluster_config.output_directory =
+            format!("{}/cluster_{}", config.output_directory, cluster.id);
+        fs::create_dir_all(&cluster_config.output_directory).expect(&format!(
+            "Could not create directory {}",
+            cluster_config.output_directory
+        ));
+
+        let trace_analyzer = TraceAnalyzer::configured_builder(
+            config,
+            TraceSource::Paths(cluster.traces.clone()),
+            TraceSource::Paths(sample.non_crashes.clone()),
+        )?
+        .cache_file(None)
+        .fill_cfg(true)
+        .fill_scores(true)
+        .build()?;
+        trace_analyzer.dump_rejected_traces(&cluster_config);
+        trace_analyzer.dump_scores(&cluster_config, false, false);
+        cluster.predicates = trace_analyzer
+            .sort_scores()
+            .iter()
+            .rev()
+            .take(REPORTED_PREDICATES)
+            .map(|p| {
+                p.to_serialzed(
+                    trace_analyzer.scoring.as_ref(),
+                    &trace_analyzer.ln_factorials,
+                )
+            })
+            .collect();
+    }
+
+    let file_path = format!("{}/clusters.json", config.output_directory);
+    let content = serde_json::to_string_pretty(&clusters).expect("Could not serialize clusters");
+    fs::write(&file_path, content).expect(&format!("Could not write file {}", file_path));
+
+    Ok(clusters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::tests::trace;
+
+    fn signature(name: &str, addresses: &[usize]) -> CrashSignature {
+        let path: Vec<(usize, &str)> = addresses.iter().map(|a| (*a, "nop")).collect();
+        CrashSignature::new(name.to_string(), &trace(&path, |_| 0))
+    }
+
+    #[test]
+    fn test_jaccard() {
+        assert_eq!(jaccard(0, 0, 0), 1.0);
+        assert_eq!(jaccard(1, 2, 3), 0.25);
+        assert_eq!(jaccard(2, 2, 2), 1.0);
+    }
+
+    #[test]
+    fn test_similarity() {
+        let a = signature("a", &[0x10, 0x14, 0x18]);
+        let b = signature("b", &[0x10, 0x14, 0x18]);
+        let c = signature("c", &[0x10, 0x14, 0x20]);
+
+        assert_eq!(a.similarity(&b), 1.0);
+        // different crash sites score at most 2/3
+        assert!(a.similarity(&c) < 2.0 / 3.0);
+    }
+
+    #[test]
+    fn test_cluster_crashes() {
+        let signatures = vec![
+            signature("a", &[0x10, 0x14, 0x18]),
+            signature("b", &[0x10, 0x14, 0x18]),
+            signature("c", &[0x30, 0x34, 0x38]),
+        ];
+
+        let clusters = cluster_crashes(&signatures, 0.75);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].traces.len(), 2);
+        assert_eq!(clusters[0].unique_crashes, 1);
+        assert_eq!(clusters[1].crash_sites[0].address, 0x38);
+
+        // every crash is similar enough to itself only
+        assert_eq!(cluster_crashes(&signatures, 1.1).len(), 3);
+    }
+}
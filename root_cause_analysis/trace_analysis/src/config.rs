@@ -1,21 +1,44 @@
+use crate::scoring::SCORING_FUNCTIONS;
+use crate::trace_integrity::TRACE_CHECKS;
 use std::num::ParseIntError;
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
+/// How to handle traces that cannot be loaded: skip and report them, or abort the analysis.
+pub const TRACE_ERROR_POLICIES: &[&str] = &["skip", "fail"];
+
 fn parse_hex(src: &str) -> Result<usize, ParseIntError> {
     usize::from_str_radix(&src.replace("0x", ""), 16)
 }
 
-#[derive(Debug, StructOpt)]
+fn parse_ratio(src: &str) -> Result<f64, String> {
+    match src.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!("{} is not a ratio between 0 and 1", src)),
+    }
+}
+
+#[derive(Clone, Debug, StructOpt)]
 #[structopt(
     name = "trace_analysis",
-    global_settings = &[AppSettings::DisableVersion]
+    global_settings = &[AppSettings::DisableVersion],
+    settings = &[AppSettings::ArgsNegateSubcommands]
 )]
-
 pub struct Config {
-    #[structopt(index = 1, help = "Path to traces of crashing inputs")]
+    // not required by clap, since subcommands do not take them; checked by `validate`
+    #[structopt(
+        index = 1,
+        default_value = "",
+        hide_default_value = true,
+        help = "Path to traces of crashing inputs"
+    )]
     pub path_to_crashes: String,
-    #[structopt(index = 2, help = "Path to traces of non-crashing inputs")]
+    #[structopt(
+        index = 2,
+        default_value = "",
+        hide_default_value = true,
+        help = "Path to traces of non-crashing inputs"
+    )]
     pub path_to_non_crashes: String,
     #[structopt(
         short = "c",
@@ -23,6 +46,27 @@ pub struct Config {
         help = "Performs trace integrity checks"
     )]
     pub check_traces: bool,
+    #[structopt(
+        long = "enable-check",
+        possible_values = TRACE_CHECKS,
+        number_of_values = 1,
+        help = "Only run the given integrity checks (repeatable)"
+    )]
+    pub enabled_checks: Vec<String>,
+    #[structopt(
+        long = "disable-check",
+        possible_values = TRACE_CHECKS,
+        number_of_values = 1,
+        help = "Skip the given integrity checks (repeatable)"
+    )]
+    pub disabled_checks: Vec<String>,
+    #[structopt(
+        long = "check-format",
+        default_value = "text",
+        possible_values = &["text", "json"],
+        help = "Output format of the integrity checks"
+    )]
+    pub check_format: String,
     #[structopt(short = "d", long = "dump-traces", help = "Dumps trace data")]
     pub dump_traces: bool,
     #[structopt(short = "s", long = "scores", help = "Dumps instruction scores")]
@@ -38,6 +82,32 @@ pub struct Config {
         help = "Select n random traces"
     )]
     pub random_traces: usize,
+    #[structopt(long = "seed", help = "Seed for selecting random traces")]
+    pub seed: Option<u64>,
+    #[structopt(
+        long = "crash-ratio",
+        parse(try_from_str = parse_ratio),
+        help = "Fraction of crashes among the n random traces (default: n crashes and n non-crashes)"
+    )]
+    pub crash_ratio: Option<f64>,
+    #[structopt(
+        long = "replay-sample",
+        default_value = "",
+        help = "Analyze the traces listed in a sampled_traces.json of a previous run"
+    )]
+    pub replay_sample_path: String,
+    #[structopt(
+        long = "cluster",
+        help = "Cluster the crashes and analyze each cluster separately"
+    )]
+    pub cluster_crashes: bool,
+    #[structopt(
+        long = "cluster-threshold",
+        default_value = "0.75",
+        parse(try_from_str = parse_ratio),
+        help = "Minimum similarity of crashes in the same cluster"
+    )]
+    pub cluster_threshold: f64,
     #[structopt(
         short = "f",
         long = "filter",
@@ -64,6 +134,101 @@ pub struct Config {
     help = "Dumps the best predicate at address"
     )]
     pub predicate_address: usize,
+    #[structopt(
+        long = "explain",
+        help = "Lists the traces misclassified by the best predicate at --debug-predicate"
+    )]
+    pub explain_predicate: bool,
+    #[structopt(
+        long = "export-blacklist",
+        default_value = "",
+        help = "Writes the crashes failing the explained predicate to a crash blacklist"
+    )]
+    pub blacklist_export_path: String,
+    #[structopt(
+        long = "binary",
+        default_value = "",
+        help = "Traced binary used to add source locations to the serialized predicates"
+    )]
+    pub binary_path: String,
+    #[structopt(
+        long = "export-cfg",
+        default_value = "0",
+        help = "Export the CFGs of the functions containing the n best predicates as DOT and JSON"
+    )]
+    pub export_cfg: usize,
+    #[structopt(
+        long = "functions",
+        help = "Dumps the recovered functions ranked by their best predicate and the call graph"
+    )]
+    pub dump_functions: bool,
+    #[structopt(
+        long = "combine",
+        default_value = "0",
+        help = "Combine the top k predicates per address into conjunctions and disjunctions"
+    )]
+    pub combine_predicates: usize,
+    #[structopt(
+        long = "collapse-predicates",
+        help = "Collapse predicates equivalent to a predicate at a dominating or post-dominating address"
+    )]
+    pub collapse_predicates: bool,
+    #[structopt(
+        long = "top-predicates",
+        default_value = "3",
+        help = "Number of best predicates kept and serialized per address"
+    )]
+    pub top_predicates: usize,
+    #[structopt(
+        long = "scoring",
+        default_value = "balanced-accuracy",
+        possible_values = SCORING_FUNCTIONS,
+        help = "Scoring function used to rank predicates"
+    )]
+    pub scoring: String,
+    #[structopt(
+        long = "null-region-end",
+        parse(try_from_str = parse_hex),
+        help = "Values below this hex address are considered to point near NULL [default: 10000]"
+    )]
+    pub null_region_end: Option<usize>,
+    #[structopt(
+        long = "stream",
+        help = "Aggregate traces while reading them instead of keeping them in memory (no relational predicates)"
+    )]
+    pub stream_traces: bool,
+    #[structopt(
+        long = "cache",
+        help = "Cache aggregates and predicates in the output directory and only analyze new traces (implies --stream)"
+    )]
+    pub cache_analysis: bool,
+    #[structopt(
+        long = "on-trace-error",
+        default_value = "skip",
+        possible_values = TRACE_ERROR_POLICIES,
+        help = "Skip and report traces that cannot be loaded or fail on the first one"
+    )]
+    pub trace_error_policy: String,
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum Command {
+    #[structopt(
+        about = "Parse traces, check their format version and run the integrity checks; prints the results as JSON and exits with 1 if a trace is invalid"
+    )]
+    Validate {
+        #[structopt(help = "Path to traces (.zip or *trace)")]
+        trace_dir: String,
+        #[structopt(
+            long = "disable-check",
+            possible_values = TRACE_CHECKS,
+            number_of_values = 1,
+            help = "Skip the given integrity checks (repeatable)"
+        )]
+        disabled_checks: Vec<String>,
+    },
 }
 
 impl Config {
@@ -76,11 +241,19 @@ impl Config {
             path_to_crashes: format!("{}/traces/crashes/", trace_dir),
             path_to_non_crashes: format!("{}/traces/non_crashes/", trace_dir),
             check_traces: false,
+            enabled_checks: vec![],
+            disabled_checks: vec![],
+            check_format: "text".to_string(),
             dump_traces: false,
             dump_scores: true,
             zipped: true,
             dump_address: 0,
             random_traces: 0,
+            seed: None,
+            crash_ratio: None,
+            replay_sample_path: "".to_string(),
+            cluster_crashes: false,
+            cluster_threshold: 0.75,
             filter_non_crashes: false,
             trace_info: false,
             output_directory: if output_dir.is_some() {
@@ -94,13 +267,82 @@ impl Config {
                 "".to_string()
             },
             predicate_address: 0,
+            explain_predicate: false,
+            blacklist_export_path: "".to_string(),
+            binary_path: "".to_string(),
+            export_cfg: 0,
+            dump_functions: false,
+            combine_predicates: 0,
+            collapse_predicates: false,
+            top_predicates: 3,
+            scoring: "balanced-accuracy".to_string(),
+            null_region_end: None,
+            stream_traces: false,
+            cache_analysis: false,
+            trace_error_policy: "skip".to_string(),
+            command: None,
         }
     }
 
+    /// Rejects missing trace paths, options that need the traces in memory if traces are only
+    /// aggregated, and options the analysis cache does not support.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.path_to_crashes.is_empty() || self.path_to_non_crashes.is_empty() {
+            return Err(
+                "the paths to the crashing and non-crashing traces are required".to_string(),
+            );
+        }
+
+        // the cache has to see the same traces on every run
+        if self.cache_analysis && (self.random_traces() || self.filter_non_crashes) {
+            return Err("--cache does not support --random or --filter".to_string());
+        }
+
+        if !self.stream_traces && !self.cache_analysis {
+            return Ok(());
+        }
+
+        for (enabled, option) in [
+            (self.combine_predicates > 0, "--combine"),
+            (self.check_traces, "--check-traces"),
+            (self.dump_traces, "--dump-traces"),
+            (self.dump_address(), "--dump-address"),
+            (self.trace_info, "--trace-info"),
+            (self.debug_predicate(), "--debug-predicate"),
+            (self.cluster_crashes, "--cluster"),
+        ]
+        .iter()
+        {
+            if *enabled {
+                return Err(format!(
+                    "{} requires the traces in memory (no --stream or --cache)",
+                    option
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn random_traces(&self) -> bool {
         self.random_traces > 0
     }
 
+    pub fn replay_sample(&self) -> bool {
+        self.replay_sample_path != ""
+    }
+
+    /// Number of crashes and non-crashes to select with `--random`.
+    pub fn sample_sizes(&self) -> (usize, usize) {
+        match self.crash_ratio {
+            Some(ratio) => {
+                let crashes = (self.random_traces as f64 * ratio).round() as usize;
+                (crashes, self.random_traces - crashes)
+            }
+            None => (self.random_traces, self.random_traces),
+        }
+    }
+
     pub fn dump_address(&self) -> bool {
         self.dump_address > 0
     }
@@ -109,7 +351,23 @@ impl Config {
         self.crash_blacklist_path != ""
     }
 
+    pub fn fail_on_trace_error(&self) -> bool {
+        self.trace_error_policy == "fail"
+    }
+
     pub fn debug_predicate(&self) -> bool {
         self.predicate_address > 0
     }
+
+    pub fn export_blacklist(&self) -> bool {
+        self.blacklist_export_path != ""
+    }
+
+    pub fn symbolize(&self) -> bool {
+        self.binary_path != ""
+    }
+
+    pub fn export_cfg(&self) -> bool {
+        self.export_cfg > 0
+    }
 }
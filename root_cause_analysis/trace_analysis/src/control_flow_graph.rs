@@ -1,3 +1,4 @@
+use crate::trace::EdgeType;
 use std::collections::hash_map::Keys;
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
@@ -38,15 +39,18 @@ impl BasicBlock {
 pub struct ControlFlowGraph {
     addr_to_bb_exit: HashMap<usize, usize>,
     exit_addr_to_bb: HashMap<usize, BasicBlock>,
+    edge_types: HashMap<(usize, usize), EdgeType>,
 }
 
 impl ControlFlowGraph {
     pub fn new() -> Self {
         let addr_to_bb_exit = HashMap::new();
         let exit_addr_to_bb = HashMap::new();
+        let edge_types = HashMap::new();
         ControlFlowGraph {
             addr_to_bb_exit,
             exit_addr_to_bb,
+            edge_types,
         }
     }
 
@@ -65,6 +69,27 @@ impl ControlFlowGraph {
         }
     }
 
+    pub fn get_edge_type(&self, src: usize, dst: usize) -> EdgeType {
+        *self
+            .edge_types
+            .get(&(src, dst))
+            .unwrap_or(&EdgeType::Unknown)
+    }
+
+    /// Instruction-level edges with their types.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize, EdgeType)> + '_ {
+        self.edge_types
+            .iter()
+            .map(|((src, dst), edge_type)| (*src, *dst, *edge_type))
+    }
+
+    /// All outgoing edges of the instruction at `address` are unconditional.
+    pub fn is_unconditional(&self, address: usize) -> bool {
+        self.get_instruction_successors(address)
+            .iter()
+            .all(|succ| self.get_edge_type(address, *succ).is_unconditional())
+    }
+
     pub fn is_bb_end(&self, address: usize) -> bool {
         self.exit_addr_to_bb.contains_key(&address)
     }
@@ -80,6 +105,28 @@ impl ControlFlowGraph {
         self.exit_addr_to_bb.values()
     }
 
+    pub fn contains(&self, addr: usize) -> bool {
+        self.addr_to_bb_exit.contains_key(&addr)
+    }
+
+    /// Blocks with an edge into the block containing `addr`.
+    pub fn predecessor_bbs(&self, addr: usize) -> Vec<&BasicBlock> {
+        self.get_bb(addr)
+            .predecessors
+            .iter()
+            .map(|pred| self.get_bb(*pred))
+            .collect()
+    }
+
+    /// Blocks reached by an edge from the block containing `addr`.
+    pub fn successor_bbs(&self, addr: usize) -> Vec<&BasicBlock> {
+        self.get_bb(addr)
+            .successors
+            .iter()
+            .map(|succ| self.get_bb(*succ))
+            .collect()
+    }
+
     pub fn get_bb(&self, addr: usize) -> &BasicBlock {
         let exit_addr = self
             .addr_to_bb_exit
@@ -123,6 +170,7 @@ impl ControlFlowGraph {
 pub struct CFGCollector {
     successors: HashMap<usize, HashSet<usize>>,
     predecessors: HashMap<usize, HashSet<usize>>,
+    edge_types: HashMap<(usize, usize), EdgeType>,
 }
 
 impl CFGCollector {
@@ -130,10 +178,13 @@ impl CFGCollector {
         CFGCollector {
             successors: HashMap::new(),
             predecessors: HashMap::new(),
+            edge_types: HashMap::new(),
         }
     }
 
-    pub fn add_edge(&mut self, src: usize, dst: usize) {
+    pub fn add_edge(&mut self, src: usize, dst: usize, edge_type: EdgeType) {
+        self.edge_types.insert((src, dst), edge_type);
+
         if !self.predecessors.contains_key(&src) {
             self.predecessors.insert(src, HashSet::new());
         }
@@ -186,6 +237,7 @@ impl CFGCollector {
 
     pub fn construct_graph(&self) -> ControlFlowGraph {
         let mut cfg = ControlFlowGraph::new();
+        cfg.edge_types = self.edge_types.clone();
         let mut bb = BasicBlock::new();
         let mut finished = false;
 
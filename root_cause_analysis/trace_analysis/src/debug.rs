@@ -1,10 +1,71 @@
 use crate::config::Config;
 use crate::predicate_analysis::PredicateAnalyzer;
-use crate::trace::Trace;
+use crate::predicates::Predicate;
+use crate::trace::{Registers, Trace, REGISTERS};
 use crate::trace_analyzer::TraceAnalyzer;
+use std::fs;
 use std::fs::File;
 use std::io::Write;
 
+/// Trace on the wrong side of a predicate: a crash failing it or a non-crash satisfying it.
+pub struct Misclassification<'a> {
+    pub trace: &'a Trace,
+    pub crash: bool,
+}
+
+impl Misclassification<'_> {
+    /// Minimum, maximum and last value of each register named in the predicate.
+    pub fn register_values(&self, predicate: &Predicate) -> Vec<String> {
+        let instruction = match self.trace.instructions.get(&predicate.address) {
+            Some(instruction) => instruction,
+            None => return vec![],
+        };
+
+        predicate
+            .name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter_map(|token| REGISTERS.iter().position(|reg| *reg == token))
+            .map(|index| {
+                format!(
+                    "{} min {} max {} last {}",
+                    REGISTERS[index],
+                    register_value(&instruction.registers_min, index),
+                    register_value(&instruction.registers_max, index),
+                    register_value(&instruction.registers_last, index),
+                )
+            })
+            .collect()
+    }
+}
+
+fn register_value(registers: &Registers, index: usize) -> String {
+    match registers.get(index) {
+        Some(register) => format!("{:#x}", register.value()),
+        None => "-".to_string(),
+    }
+}
+
+pub fn misclassified_traces<'a>(
+    predicate: &Predicate,
+    trace_analyzer: &'a TraceAnalyzer,
+) -> Vec<Misclassification<'a>> {
+    let crashes = trace_analyzer
+        .crashes
+        .iter()
+        .filter(|t| !predicate.execute(&t.instructions.get(&predicate.address)))
+        .map(|trace| Misclassification { trace, crash: true });
+    let non_crashes = trace_analyzer
+        .non_crashes
+        .iter()
+        .filter(|t| predicate.execute(&t.instructions.get(&predicate.address)))
+        .map(|trace| Misclassification {
+            trace,
+            crash: false,
+        });
+
+    crashes.chain(non_crashes).collect()
+}
+
 pub fn diff_traces(config: &Config, trace_analyzer: &TraceAnalyzer) {
     let mut file = File::create(format!("{}/verbose_info.csv", config.output_directory)).unwrap();
     /* addresses that have been seen in traces AND non-traces */
@@ -52,15 +113,94 @@ pub fn dump_trace_info(config: &Config, trace_analyzer: &TraceAnalyzer) {
     );
 }
 
-pub fn debug_predicate_at_address(address: usize, trace_analyzer: &TraceAnalyzer) {
-    let predicate = PredicateAnalyzer::evaluate_best_predicate_at_address(address, trace_analyzer);
+/// Whether any trace visits `address`; prints a note otherwise, since there is nothing to debug.
+fn is_visited(address: usize, trace_analyzer: &TraceAnalyzer) -> bool {
+    let visits = trace_analyzer.visits(address);
+    if visits.crashes + visits.non_crashes == 0 {
+        println!("0x{:x} was not visited by any trace", address);
+        return false;
+    }
+
+    true
+}
+
+pub fn debug_predicate_at_address(address: usize, trace_analyzer: &TraceAnalyzer, top_k: usize) {
+    if !is_visited(address, trace_analyzer) {
+        return;
+    }
+
+    for predicate in
+        PredicateAnalyzer::evaluate_top_predicates_at_address(address, trace_analyzer, top_k)
+    {
+        println!(
+            "0x{:x} -- {} -- {}",
+            predicate.address,
+            predicate.to_string(),
+            predicate.score
+        );
+    }
+}
+
+/// Lists the traces the best predicate at `--debug-predicate` misclassifies, with the values of
+/// the registers it tests, and optionally exports the failing crashes as a crash blacklist.
+pub fn explain_predicate_at_address(config: &Config, trace_analyzer: &TraceAnalyzer) {
+    if trace_analyzer.aggregates.is_some() {
+        println!("explaining predicates requires the traces in memory (no --stream or --cache)");
+        return;
+    }
+
+    let address = config.predicate_address;
+    if !is_visited(address, trace_analyzer) {
+        return;
+    }
+
+    let predicate = match trace_analyzer.address_scores.get(&address) {
+        Some(predicate) => predicate.clone(),
+        None => PredicateAnalyzer::evaluate_top_predicates_at_address(address, trace_analyzer, 1)
+            .remove(0),
+    };
+    let misclassified = misclassified_traces(&predicate, trace_analyzer);
+    let failing_crashes: Vec<_> = misclassified.iter().filter(|m| m.crash).collect();
 
     println!(
-        "0x{:x} -- {} -- {}",
-        predicate.address,
+        "0x{:x} -- {} -- {}: {} of {} crashes fail, {} of {} non-crashes satisfy",
+        address,
         predicate.to_string(),
-        predicate.score
+        predicate.score,
+        failing_crashes.len(),
+        trace_analyzer.crashes.len(),
+        misclassified.len() - failing_crashes.len(),
+        trace_analyzer.non_crashes.len()
     );
+    for m in misclassified.iter() {
+        let registers = m.register_values(&predicate);
+        println!(
+            "{} {}{}",
+            if m.crash { "crash" } else { "non-crash" },
+            m.trace.name,
+            match m.trace.instructions.contains_key(&address) {
+                false => " (address not visited)".to_string(),
+                true if registers.is_empty() => "".to_string(),
+                true => format!(": {}", registers.join(", ")),
+            }
+        );
+    }
+
+    if config.export_blacklist() {
+        let content: String = failing_crashes
+            .iter()
+            .map(|m| format!("{}\n", m.trace.name))
+            .collect();
+        fs::write(&config.blacklist_export_path, content).expect(&format!(
+            "Could not write file {}",
+            config.blacklist_export_path
+        ));
+        println!(
+            "exported {} crashes to {}",
+            failing_crashes.len(),
+            config.blacklist_export_path
+        );
+    }
 }
 
 fn write_traces_info(file: &mut File, traces: &[Trace], flag: &str) {
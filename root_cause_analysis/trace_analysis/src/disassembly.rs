@@ -0,0 +1,274 @@
+use crate::control_flow_graph::BasicBlock;
+use crate::predicates::SerializedPredicate;
+use crate::trace::REGISTERS;
+use crate::trace_analyzer::TraceAnalyzer;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::slice;
+
+/// Sub-registers of the general purpose registers, as they appear in Intel syntax.
+static REGISTER_ALIASES: [(&str, &[&str]); 22] = [
+    ("rax", &["rax", "eax", "ax", "al", "ah"]),
+    ("rbx", &["rbx", "ebx", "bx", "bl", "bh"]),
+    ("rcx", &["rcx", "ecx", "cx", "cl", "ch"]),
+    ("rdx", &["rdx", "edx", "dx", "dl", "dh"]),
+    ("rsi", &["rsi", "esi", "si", "sil"]),
+    ("rdi", &["rdi", "edi", "di", "dil"]),
+    ("rbp", &["rbp", "ebp", "bp", "bpl"]),
+    ("rsp", &["rsp", "esp", "sp", "spl"]),
+    ("r8", &["r8", "r8d", "r8w", "r8b"]),
+    ("r9", &["r9", "r9d", "r9w", "r9b"]),
+    ("r10", &["r10", "r10d", "r10w", "r10b"]),
+    ("r11", &["r11", "r11d", "r11w", "r11b"]),
+    ("r12", &["r12", "r12d", "r12w", "r12b"]),
+    ("r13", &["r13", "r13d", "r13w", "r13b"]),
+    ("r14", &["r14", "r14d", "r14w", "r14b"]),
+    ("r15", &["r15", "r15d", "r15w", "r15b"]),
+    ("seg_cs", &["cs"]),
+    ("seg_ss", &["ss"]),
+    ("seg_ds", &["ds"]),
+    ("seg_es", &["es"]),
+    ("seg_fs", &["fs"]),
+    ("seg_gs", &["gs"]),
+];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DisassembledInstruction {
+    pub address: usize,
+    pub mnemonic: String,
+    /// operands holding a register tested by the predicate
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub highlighted_operands: Vec<String>,
+}
+
+/// The instruction with its highlighted operands in braces.
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.highlighted_operands.is_empty() {
+            return write!(f, "{}", self.mnemonic);
+        }
+
+        let opcode = self.mnemonic.trim().split(' ').next().unwrap();
+        let operands: Vec<String> = operands(&self.mnemonic)
+            .into_iter()
+            .map(
+                |operand| match self.highlighted_operands.iter().any(|o| o == operand) {
+                    true => format!("{{{}}}", operand),
+                    false => operand.to_string(),
+                },
+            )
+            .collect();
+
+        write!(f, "{} {}", opcode, operands.join(", "))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DisassembledBlock {
+    pub start: usize,
+    pub instructions: Vec<DisassembledInstruction>,
+}
+
+/// Basic block of a predicate together with its predecessor and successor blocks.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlockContext {
+    pub address: usize,
+    pub block: DisassembledBlock,
+    pub predecessors: Vec<DisassembledBlock>,
+    pub successors: Vec<DisassembledBlock>,
+}
+
+/// Splits an instruction into its operands, e.g. `mov eax, dword ptr [rbp-0x48]` into `eax` and
+/// `dword ptr [rbp-0x48]`.
+pub fn operands(mnemonic: &str) -> Vec<&str> {
+    match mnemonic.trim().split_once(' ') {
+        Some((_, operands)) => operands.split(',').map(|o| o.trim()).collect(),
+        None => vec![],
+    }
+}
+
+/// Operands of `mnemonic` that read or write one of the registers named in the predicate.
+///
+/// `memory_address` and `memory_value` predicates refer to the memory operand.
+pub fn tested_operands(predicate_name: &str, mnemonic: &str) -> Vec<String> {
+    let registers: Vec<&str> = predicate_name
+        .split_whitespace()
+        .filter(|token| REGISTERS.contains(token))
+        .collect();
+
+    operands(mnemonic)
+        .into_iter()
+        .filter(|operand| {
+            let words: Vec<&str> = operand
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .collect();
+            registers.iter().any(|register| match *register {
+                "memory_address" | "memory_value" => operand.contains('['),
+                _ => REGISTER_ALIASES
+                    .iter()
+                    .filter(|(name, _)| name == register)
+                    .flat_map(|(_, aliases)| aliases.iter())
+                    .any(|alias| words.contains(alias)),
+            })
+        })
+        .map(|operand| operand.to_string())
+        .collect()
+}
+
+fn disassemble_block(
+    bb: &BasicBlock,
+    trace_analyzer: &TraceAnalyzer,
+    predicate: Option<&SerializedPredicate>,
+) -> DisassembledBlock {
+    DisassembledBlock {
+        start: bb.start(),
+        instructions: bb
+            .iter_addresses()
+            .map(|address| {
+                let mnemonic = trace_analyzer.get_any_mnemonic(*address);
+                let highlighted_operands = match predicate {
+                    Some(predicate) if predicate.address == *address => {
+                        tested_operands(&predicate.name, &mnemonic)
+                    }
+                    _ => vec![],
+                };
+                DisassembledInstruction {
+                    address: *address,
+                    mnemonic,
+                    highlighted_operands,
+                }
+            })
+            .collect(),
+    }
+}
+
+impl BlockContext {
+    /// Disassembles the blocks around a predicate from the traced mnemonics.
+    ///
+    /// Requires the CFG of the trace analyzer; returns `None` if the address is not in it.
+    pub fn new(
+        predicate: &SerializedPredicate,
+        trace_analyzer: &TraceAnalyzer,
+    ) -> Option<BlockContext> {
+        let cfg = &trace_analyzer.cfg;
+        if !cfg.contains(predicate.address) {
+            return None;
+        }
+
+        let disassemble_blocks = |mut blocks: Vec<&BasicBlock>| {
+            blocks.sort_by_key(|bb| bb.start());
+            blocks
+                .into_iter()
+                .map(|bb| disassemble_block(bb, trace_analyzer, None))
+                .collect()
+        };
+
+        Some(BlockContext {
+            address: predicate.address,
+            block: disassemble_block(
+                cfg.get_bb(predicate.address),
+                trace_analyzer,
+                Some(predicate),
+            ),
+            predecessors: disassemble_blocks(cfg.predecessor_bbs(predicate.address)),
+            successors: disassemble_blocks(cfg.successor_bbs(predicate.address)),
+        })
+    }
+}
+
+/// Lists the blocks one instruction per line; the predicate's instruction is marked with `>` and
+/// its tested operands with braces.
+impl fmt::Display for BlockContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (title, blocks) in [
+            ("predecessor", self.predecessors.as_slice()),
+            ("block", slice::from_ref(&self.block)),
+            ("successor", self.successors.as_slice()),
+        ]
+        .iter()
+        {
+            for block in blocks.iter() {
+                writeln!(f, "  {} {:#x}:", title, block.start)?;
+                for instruction in block.instructions.iter() {
+                    writeln!(
+                        f,
+                        "  {} {:#x}  {}",
+                        if instruction.address == self.address {
+                            ">"
+                        } else {
+                            " "
+                        },
+                        instruction.address,
+                        instruction
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOV: &str = "mov eax, dword ptr [rbp-0x48]";
+
+    fn instruction(address: usize, highlighted_operands: Vec<String>) -> DisassembledInstruction {
+        DisassembledInstruction {
+            address,
+            mnemonic: MOV.to_string(),
+            highlighted_operands,
+        }
+    }
+
+    #[test]
+    fn test_tested_operands() {
+        assert_eq!(operands(MOV), vec!["eax", "dword ptr [rbp-0x48]"]);
+        assert!(operands("ret").is_empty());
+
+        assert_eq!(
+            tested_operands("rax min_reg_val_less 0x10", MOV),
+            vec!["eax"]
+        );
+        assert_eq!(
+            tested_operands("rbp max_reg_val_less 0x10", MOV),
+            vec!["dword ptr [rbp-0x48]"]
+        );
+        assert_eq!(
+            tested_operands("memory_value last_reg_val_less 0x10", MOV),
+            vec!["dword ptr [rbp-0x48]"]
+        );
+        assert!(tested_operands("rcx min_reg_val_less 0x10", MOV).is_empty());
+    }
+
+    #[test]
+    fn test_highlighted_operands() {
+        assert_eq!(instruction(0x10, vec![]).to_string(), MOV);
+        assert_eq!(
+            instruction(0x10, vec!["eax".to_string()]).to_string(),
+            "mov {eax}, dword ptr [rbp-0x48]"
+        );
+
+        let context = BlockContext {
+            address: 0x14,
+            block: DisassembledBlock {
+                start: 0x10,
+                instructions: vec![
+                    instruction(0x10, vec![]),
+                    instruction(0x14, vec!["eax".to_string()]),
+                ],
+            },
+            predecessors: vec![],
+            successors: vec![],
+        };
+        assert_eq!(
+            context.to_string(),
+            format!(
+                "  block 0x10:\n    0x10  {}\n  > 0x14  mov {{eax}}, dword ptr [rbp-0x48]\n",
+                MOV
+            )
+        );
+    }
+}
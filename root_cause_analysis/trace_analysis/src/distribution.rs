@@ -0,0 +1,153 @@
+use crate::predicates::SerializedPredicate;
+use crate::trace::{Instruction, Selector, Trace, REGISTERS};
+use crate::trace_analyzer::TraceAnalyzer;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Values of the quantity a predicate compares, observed once per trace.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ValueDistribution {
+    pub address: usize,
+    /// compared quantity, e.g. `rax min` or `count`
+    pub quantity: String,
+    /// (value, number of traces), sorted by value
+    pub crashes: Vec<(u64, usize)>,
+    pub non_crashes: Vec<(u64, usize)>,
+}
+
+/// Selector and register of the value compared by a predicate.
+///
+/// Only predicates on a single value have a distribution; edge, successor, relational and
+/// combined predicates do not.
+pub fn compared_value(predicate_name: &str) -> Option<(Selector, Option<usize>)> {
+    if predicate_name.contains(" && ") || predicate_name.contains(" || ") {
+        return None;
+    }
+
+    let tokens: Vec<&str> = predicate_name.split_whitespace().collect();
+    let position = tokens.iter().position(|token| {
+        token.starts_with("ins_count_")
+            || token.contains("_reg_val_")
+            || token.ends_with("_flag_set")
+    })?;
+    let token = tokens[position];
+
+    if token.starts_with("ins_count_") {
+        return Some((Selector::InsCount, None));
+    }
+
+    let selector = if token.starts_with("max_min_diff_") {
+        Selector::RegMaxMinDiff
+    } else if token.starts_with("min_") {
+        Selector::RegMin
+    } else if token.starts_with("max_") {
+        Selector::RegMax
+    } else if token.starts_with("last_") {
+        Selector::RegLast
+    } else {
+        return None;
+    };
+    let register = match token.ends_with("_flag_set") {
+        true => "eflags",
+        false => tokens[..position].last()?,
+    };
+
+    Some((
+        selector,
+        Some(REGISTERS.iter().position(|r| *r == register)?),
+    ))
+}
+
+fn selected_value(
+    instruction: &Instruction,
+    selector: &Selector,
+    reg_index: Option<usize>,
+) -> Option<u64> {
+    match selector {
+        Selector::InsCount => Some(instruction.count as u64),
+        Selector::RegMaxMinDiff => {
+            let max = instruction.registers_max.get(reg_index?)?.value();
+            let min = instruction.registers_min.get(reg_index?)?.value();
+            Some(max - min)
+        }
+        _ => Some(instruction.registers(selector).get(reg_index?)?.value()),
+    }
+}
+
+fn histogram(values: impl Iterator<Item = u64>) -> Vec<(u64, usize)> {
+    let mut ret = BTreeMap::new();
+    for value in values {
+        *ret.entry(value).or_insert(0) += 1;
+    }
+
+    ret.into_iter().collect()
+}
+
+fn trace_histogram(
+    traces: &[Trace],
+    address: usize,
+    selector: &Selector,
+    reg_index: Option<usize>,
+) -> Vec<(u64, usize)> {
+    histogram(
+        traces.iter().filter_map(|trace| {
+            selected_value(trace.instructions.get(&address)?, selector, reg_index)
+        }),
+    )
+}
+
+impl ValueDistribution {
+    /// Collects the compared values from the traces or, if traces were streamed, the aggregates.
+    ///
+    /// Streamed aggregates do not keep max-min differences, so these predicates have no distribution.
+    pub fn of_predicate(
+        predicate: &SerializedPredicate,
+        trace_analyzer: &TraceAnalyzer,
+    ) -> Option<ValueDistribution> {
+        let (selector, reg_index) = compared_value(&predicate.name)?;
+        let quantity = match (&selector, reg_index) {
+            (Selector::InsCount, _) => "count".to_string(),
+            (Selector::RegMaxMinDiff, Some(index)) => format!("{} max-min", REGISTERS[index]),
+            (Selector::RegMin, Some(index)) => format!("{} min", REGISTERS[index]),
+            (Selector::RegMax, Some(index)) => format!("{} max", REGISTERS[index]),
+            (Selector::RegLast, Some(index)) => format!("{} last", REGISTERS[index]),
+            _ => return None,
+        };
+
+        let (crashes, non_crashes) = match &trace_analyzer.aggregates {
+            Some(aggregates) => {
+                let aggregate = aggregates.addresses.get(&predicate.address)?;
+                let values = match selector {
+                    Selector::InsCount => &aggregate.counts,
+                    Selector::RegMaxMinDiff => return None,
+                    _ => aggregate.registers(&selector).get(&reg_index?)?,
+                };
+                (
+                    histogram(values.labeled_values().filter(|v| v.1).map(|v| v.0)),
+                    histogram(values.labeled_values().filter(|v| !v.1).map(|v| v.0)),
+                )
+            }
+            None => (
+                trace_histogram(
+                    trace_analyzer.crashes.as_slice(),
+                    predicate.address,
+                    &selector,
+                    reg_index,
+                ),
+                trace_histogram(
+                    trace_analyzer.non_crashes.as_slice(),
+                    predicate.address,
+                    &selector,
+                    reg_index,
+                ),
+            ),
+        };
+
+        Some(ValueDistribution {
+            address: predicate.address,
+            quantity,
+            crashes,
+            non_crashes,
+        })
+    }
+}
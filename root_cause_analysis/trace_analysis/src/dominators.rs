@@ -0,0 +1,298 @@
+use crate::control_flow_graph::{BasicBlock, ControlFlowGraph};
+use std::collections::HashMap;
+
+/// Common root of all heads (or leaves for post-dominators), since traced CFGs may have several.
+const VIRTUAL_ROOT: usize = usize::MAX;
+
+/// Immediate dominators of the basic blocks reachable from a set of roots, keyed by block start.
+pub struct DominatorTree {
+    idom: HashMap<usize, usize>,
+}
+
+impl DominatorTree {
+    /// Dominators of the blocks reachable from the heads of the CFG.
+    pub fn dominators(cfg: &ControlFlowGraph) -> DominatorTree {
+        DominatorTree::compute(cfg.heads(), |start| cfg.successor_bbs(start))
+    }
+
+    /// Post-dominators of the blocks that reach a leaf of the CFG.
+    pub fn post_dominators(cfg: &ControlFlowGraph) -> DominatorTree {
+        DominatorTree::compute(cfg.leaves(), |start| cfg.predecessor_bbs(start))
+    }
+
+    /// Iterative algorithm of Cooper, Harvey and Kennedy ("A Simple, Fast Dominance Algorithm").
+    fn compute<'a>(
+        roots: Vec<usize>,
+        successor_bbs: impl Fn(usize) -> Vec<&'a BasicBlock>,
+    ) -> DominatorTree {
+        let mut successors: HashMap<usize, Vec<usize>> = HashMap::new();
+        successors.insert(VIRTUAL_ROOT, roots);
+        let mut predecessors: HashMap<usize, Vec<usize>> = HashMap::new();
+
+        // iterative depth-first search for the postorder
+        let mut postorder = vec![];
+        let mut stack = vec![(VIRTUAL_ROOT, 0)];
+        while let Some((node, index)) = stack.pop() {
+            let node_successors = successors.entry(node).or_insert_with(|| {
+                let mut starts: Vec<usize> =
+                    successor_bbs(node).iter().map(|bb| bb.start()).collect();
+                starts.sort_unstable();
+                starts
+            });
+
+            match node_successors.get(index).cloned() {
+                Some(succ) => {
+                    stack.push((node, index + 1));
+                    let visited = predecessors.contains_key(&succ);
+                    predecessors.entry(succ).or_default().push(node);
+                    if !visited && succ != VIRTUAL_ROOT {
+                        stack.push((succ, 0));
+                    }
+                }
+                None => postorder.push(node),
+            }
+        }
+
+        let order: HashMap<usize, usize> = postorder
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (*node, index))
+            .collect();
+        let mut idom: HashMap<usize, usize> = HashMap::new();
+        idom.insert(VIRTUAL_ROOT, VIRTUAL_ROOT);
+
+        let intersect = |idom: &HashMap<usize, usize>, mut b1: usize, mut b2: usize| {
+            while b1 != b2 {
+                while order[&b1] < order[&b2] {
+                    b1 = idom[&b1];
+                }
+                while order[&b2] < order[&b1] {
+                    b2 = idom[&b2];
+                }
+            }
+            b1
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for node in postorder.iter().rev().filter(|node| **node != VIRTUAL_ROOT) {
+                let new_idom = predecessors[node]
+                    .iter()
+                    .filter(|pred| idom.contains_key(pred))
+                    .fold(None, |new_idom, pred| match new_idom {
+                        None => Some(*pred),
+                        Some(new_idom) => Some(intersect(&idom, *pred, new_idom)),
+                    })
+                    .unwrap();
+                if idom.get(node) != Some(&new_idom) {
+                    idom.insert(*node, new_idom);
+                    changed = true;
+                }
+            }
+        }
+
+        DominatorTree { idom }
+    }
+
+    /// Immediate dominator of the block starting at `start`; `None` for roots and unknown blocks.
+    pub fn immediate_dominator(&self, start: usize) -> Option<usize> {
+        match self.idom.get(&start) {
+            Some(&idom) if idom != VIRTUAL_ROOT => Some(idom),
+            _ => None,
+        }
+    }
+
+    /// The block starting at `start` and its dominators, nearest first.
+    pub fn dominators_of(&self, start: usize) -> impl Iterator<Item = usize> + '_ {
+        let first = match self.idom.contains_key(&start) {
+            true => Some(start),
+            false => None,
+        };
+        std::iter::successors(first, move |start| self.immediate_dominator(*start))
+    }
+
+    /// Every path from a root to `b` passes through `a`; blocks dominate themselves.
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        self.dominators_of(b).any(|start| start == a)
+    }
+}
+
+/// Dominator and post-dominator trees of a CFG, queried on instruction addresses.
+pub struct Dominators<'a> {
+    cfg: &'a ControlFlowGraph,
+    pub dominators: DominatorTree,
+    pub post_dominators: DominatorTree,
+}
+
+impl<'a> Dominators<'a> {
+    pub fn new(cfg: &'a ControlFlowGraph) -> Dominators<'a> {
+        Dominators {
+            cfg,
+            dominators: DominatorTree::dominators(cfg),
+            post_dominators: DominatorTree::post_dominators(cfg),
+        }
+    }
+
+    fn position(bb: &BasicBlock, address: usize) -> usize {
+        bb.body.iter().position(|a| *a == address).unwrap()
+    }
+
+    /// Every path from the entry to instruction `b` passes through instruction `a`.
+    pub fn dominates(&self, a: usize, b: usize) -> bool {
+        if !self.cfg.contains(a) || !self.cfg.contains(b) {
+            return false;
+        }
+        let (bb_a, bb_b) = (self.cfg.get_bb(a), self.cfg.get_bb(b));
+
+        match bb_a.start() == bb_b.start() {
+            true => Dominators::position(bb_a, a) <= Dominators::position(bb_b, b),
+            false => self.dominators.dominates(bb_a.start(), bb_b.start()),
+        }
+    }
+
+    /// Every path from instruction `b` to an exit passes through instruction `a`.
+    pub fn post_dominates(&self, a: usize, b: usize) -> bool {
+        if !self.cfg.contains(a) || !self.cfg.contains(b) {
+            return false;
+        }
+        let (bb_a, bb_b) = (self.cfg.get_bb(a), self.cfg.get_bb(b));
+
+        match bb_a.start() == bb_b.start() {
+            true => Dominators::position(bb_a, a) >= Dominators::position(bb_b, b),
+            false => self.post_dominators.dominates(bb_a.start(), bb_b.start()),
+        }
+    }
+
+    /// Instructions dominating `address`, nearest first, excluding `address` itself.
+    pub fn dominating_instructions(&self, address: usize) -> impl Iterator<Item = usize> + '_ {
+        self.chain(&self.dominators, address, true)
+    }
+
+    /// Instructions post-dominating `address`, nearest first, excluding `address` itself.
+    pub fn post_dominating_instructions(&self, address: usize) -> impl Iterator<Item = usize> + '_ {
+        self.chain(&self.post_dominators, address, false)
+    }
+
+    /// Walks a tree upwards from `address`: the rest of its block, then the blocks of the tree.
+    fn chain<'b>(
+        &'b self,
+        tree: &'b DominatorTree,
+        address: usize,
+        backwards: bool,
+    ) -> impl Iterator<Item = usize> + 'b {
+        let bb = match self.cfg.contains(address) {
+            true => Some(self.cfg.get_bb(address)),
+            false => None,
+        };
+        let in_block: Vec<usize> = match bb {
+            Some(bb) => {
+                let position = Dominators::position(bb, address);
+                match backwards {
+                    true => bb.body[..position].iter().rev().cloned().collect(),
+                    false => bb.body[position + 1..].to_vec(),
+                }
+            }
+            None => vec![],
+        };
+        let blocks = bb
+            .into_iter()
+            .flat_map(move |bb| tree.dominators_of(bb.start()).skip(1))
+            .flat_map(move |start| {
+                let body = &self.cfg.get_bb(start).body;
+                let addresses: Vec<usize> = match backwards {
+                    true => body.iter().rev().cloned().collect(),
+                    false => body.clone(),
+                };
+                addresses
+            });
+
+        in_block.into_iter().chain(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control_flow_graph::CFGCollector;
+    use crate::trace::EdgeType;
+
+    /// Diamond from 0x10 to 0x40, followed by a loop through 0x50 and the exit 0x60.
+    fn cfg() -> ControlFlowGraph {
+        let mut collector = CFGCollector::new();
+        for (src, dst, edge_type) in [
+            (0x10, 0x11, EdgeType::Regular),
+            (0x11, 0x20, EdgeType::Conditional),
+            (0x11, 0x30, EdgeType::Conditional),
+            (0x20, 0x40, EdgeType::Direct),
+            (0x30, 0x31, EdgeType::Regular),
+            (0x31, 0x40, EdgeType::Regular),
+            (0x40, 0x41, EdgeType::Regular),
+            (0x41, 0x50, EdgeType::Conditional),
+            (0x41, 0x60, EdgeType::Conditional),
+            (0x50, 0x40, EdgeType::Direct),
+            (0x60, 0x61, EdgeType::Regular),
+        ]
+        .iter()
+        {
+            collector.add_edge(*src, *dst, *edge_type);
+        }
+
+        collector.construct_graph()
+    }
+
+    #[test]
+    fn test_dominator_tree() {
+        let cfg = cfg();
+        let tree = DominatorTree::dominators(&cfg);
+
+        assert_eq!(tree.immediate_dominator(0x10), None);
+        assert_eq!(tree.immediate_dominator(0x20), Some(0x10));
+        assert_eq!(tree.immediate_dominator(0x30), Some(0x10));
+        assert_eq!(tree.immediate_dominator(0x40), Some(0x10));
+        assert_eq!(tree.immediate_dominator(0x50), Some(0x40));
+        assert_eq!(tree.immediate_dominator(0x60), Some(0x40));
+        assert_eq!(
+            tree.dominators_of(0x50).collect::<Vec<_>>(),
+            vec![0x50, 0x40, 0x10]
+        );
+        assert!(!tree.dominates(0x20, 0x40));
+    }
+
+    #[test]
+    fn test_post_dominator_tree() {
+        let cfg = cfg();
+        let tree = DominatorTree::post_dominators(&cfg);
+
+        assert_eq!(tree.immediate_dominator(0x60), None);
+        assert_eq!(tree.immediate_dominator(0x10), Some(0x40));
+        assert_eq!(tree.immediate_dominator(0x20), Some(0x40));
+        assert_eq!(tree.immediate_dominator(0x50), Some(0x40));
+        assert_eq!(tree.immediate_dominator(0x40), Some(0x60));
+        assert!(!tree.dominates(0x50, 0x40));
+    }
+
+    #[test]
+    fn test_instruction_dominators() {
+        let cfg = cfg();
+        let dominators = Dominators::new(&cfg);
+
+        assert!(dominators.dominates(0x10, 0x31));
+        assert!(dominators.dominates(0x40, 0x41));
+        assert!(!dominators.dominates(0x11, 0x10));
+        assert!(!dominators.dominates(0x20, 0x40));
+        assert!(!dominators.dominates(0x10, 0x99));
+        assert!(dominators.post_dominates(0x61, 0x10));
+        assert!(!dominators.post_dominates(0x50, 0x40));
+        assert_eq!(
+            dominators.dominating_instructions(0x41).collect::<Vec<_>>(),
+            vec![0x40, 0x11, 0x10]
+        );
+        assert_eq!(
+            dominators
+                .post_dominating_instructions(0x30)
+                .collect::<Vec<_>>(),
+            vec![0x31, 0x40, 0x41, 0x60, 0x61]
+        );
+    }
+}
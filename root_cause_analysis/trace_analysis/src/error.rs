@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    Zip {
+        path: String,
+        source: zip::result::ZipError,
+    },
+    Json {
+        path: String,
+        source: serde_json::Error,
+    },
+    Binary {
+        path: String,
+        source: bincode::Error,
+    },
+    /// trace or binary trace written by an unsupported format version
+    FormatVersion {
+        path: String,
+        version: u32,
+    },
+    EmptyArchive {
+        path: String,
+    },
+}
+
+impl TraceError {
+    pub fn path(&self) -> &str {
+        match self {
+            TraceError::Io { path, .. }
+            | TraceError::Zip { path, .. }
+            | TraceError::Json { path, .. }
+            | TraceError::Binary { path, .. }
+            | TraceError::FormatVersion { path, .. }
+            | TraceError::EmptyArchive { path } => path,
+        }
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Io { path, source } => {
+                write!(f, "Could not access file {}: {}", path, source)
+            }
+            TraceError::Zip { path, source } => {
+                write!(f, "Could not read archive {}: {}", path, source)
+            }
+            TraceError::Json { path, source } => {
+                write!(f, "Could not deserialize file {}: {}", path, source)
+            }
+            TraceError::Binary { path, source } => {
+                write!(f, "Could not deserialize binary file {}: {}", path, source)
+            }
+            TraceError::FormatVersion { path, version } => write!(
+                f,
+                "Unsupported trace format version {} in file {}",
+                version, path
+            ),
+            TraceError::EmptyArchive { path } => write!(f, "Archive {} is empty", path),
+        }
+    }
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::Io { source, .. } => Some(source),
+            TraceError::Zip { source, .. } => Some(source),
+            TraceError::Json { source, .. } => Some(source),
+            TraceError::Binary { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SymbolizationError {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    Object {
+        path: String,
+        source: object::read::Error,
+    },
+    /// DWARF or symbol table that `addr2line` could not load
+    Debug {
+        path: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SymbolizationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolizationError::Io { path, source } => {
+                write!(f, "Could not access binary {}: {}", path, source)
+            }
+            SymbolizationError::Object { path, source } => {
+                write!(f, "Could not parse binary {}: {}", path, source)
+            }
+            SymbolizationError::Debug { path, message } => write!(
+                f,
+                "Could not load debug information of binary {}: {}",
+                path, message
+            ),
+        }
+    }
+}
+
+impl Error for SymbolizationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SymbolizationError::Io { source, .. } => Some(source),
+            SymbolizationError::Object { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
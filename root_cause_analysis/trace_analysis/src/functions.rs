@@ -0,0 +1,482 @@
+use crate::cfg_export::{escape, heat_color};
+use crate::config::Config;
+use crate::predicates::{Predicate, SerializedPredicate};
+use crate::scoring::cmp_scores;
+use crate::symbolization::Symbolizer;
+use crate::trace::EdgeType;
+use crate::trace_aggregates::LabeledCount;
+use crate::trace_analyzer::TraceAnalyzer;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+
+/// Maximum length of an x86 instruction; a return lands at most this far behind its call.
+const MAX_INSTRUCTION_LENGTH: usize = 15;
+/// Number of best predicates listed per function in `functions.json`.
+const REPORTED_PREDICATES: usize = 10;
+
+/// Basic block within a single function.
+pub struct FunctionBlock {
+    pub start: usize,
+    pub body: Vec<usize>,
+}
+
+impl FunctionBlock {
+    pub fn exit(&self) -> usize {
+        *self.body.last().unwrap()
+    }
+}
+
+/// Function recovered from the traced control flow.
+pub struct Function {
+    /// target of a call edge, an instruction without predecessors or the lowest address of code
+    /// not reachable from either
+    pub entry: usize,
+    /// blocks ordered by address; the first one starts at the entry
+    pub blocks: Vec<FunctionBlock>,
+    /// edges between the exit of a block and the start of the next one
+    pub edges: Vec<(usize, usize, EdgeType)>,
+}
+
+impl Function {
+    pub fn contains(&self, address: usize) -> bool {
+        self.blocks.iter().any(|bb| bb.body.contains(&address))
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = &usize> {
+        self.blocks.iter().flat_map(|bb| bb.body.iter())
+    }
+}
+
+pub fn is_call(mnemonic: &str) -> bool {
+    mnemonic.trim_start().starts_with("call")
+}
+
+/// Call of `callee` at `call_site` in the function `caller`.
+#[derive(Serialize, Deserialize)]
+pub struct Call {
+    pub call_site: usize,
+    pub caller: usize,
+    pub callee: usize,
+    /// number of crashes and non-crashes taking the call
+    pub visits: LabeledCount,
+}
+
+/// Functions and calls recovered from the traced control flow.
+pub struct CallGraph {
+    /// ordered by entry
+    pub functions: Vec<Function>,
+    /// ordered by call site
+    pub calls: Vec<Call>,
+}
+
+impl CallGraph {
+    /// Splits the traced control flow into functions.
+    ///
+    /// Call edges start functions, return edges are dropped. Each call is linked to the instruction
+    /// its callee returns to, so the code after a call stays in the calling function.
+    pub fn recover(trace_analyzer: &TraceAnalyzer) -> CallGraph {
+        let cfg = &trace_analyzer.cfg;
+        let calls: BTreeSet<usize> = cfg
+            .keys()
+            .filter(|address| is_call(&trace_analyzer.get_any_mnemonic(**address)))
+            .cloned()
+            .collect();
+
+        let mut entries = BTreeSet::new();
+        let mut call_edges = BTreeSet::new();
+        let mut successors: HashMap<usize, BTreeMap<usize, EdgeType>> = HashMap::new();
+        let mut predecessors: HashMap<usize, HashSet<usize>> = HashMap::new();
+        let mut add_edge = |src: usize, dst: usize, edge_type: EdgeType| {
+            successors.entry(src).or_default().insert(dst, edge_type);
+            predecessors.entry(dst).or_default().insert(src);
+        };
+
+        for (src, dst, edge_type) in cfg.edges() {
+            let return_site = |call: usize| dst > call && dst - call <= MAX_INSTRUCTION_LENGTH;
+            if calls.contains(&src) && return_site(src) {
+                // the callee was not traced
+                add_edge(src, dst, EdgeType::Regular);
+            } else if calls.contains(&src) {
+                entries.insert(dst);
+                call_edges.insert((src, dst));
+            } else if edge_type == EdgeType::Return {
+                if let Some(call) = calls.range(..dst).next_back().filter(|c| return_site(**c)) {
+                    add_edge(*call, dst, EdgeType::Regular);
+                }
+            } else {
+                add_edge(src, dst, edge_type);
+            }
+        }
+        entries.extend(
+            cfg.keys()
+                .filter(|address| !predecessors.contains_key(address)),
+        );
+
+        let mut unassigned: BTreeSet<usize> = cfg.keys().cloned().collect();
+        let mut functions = vec![];
+        for entry in entries.iter() {
+            let body = collect_body(*entry, &successors, &entries, &mut unassigned);
+            functions.push(build_function(*entry, &body, &successors, &predecessors));
+        }
+        // code only reached by untraced jumps or returns
+        while let Some(entry) = unassigned.iter().next().cloned() {
+            entries.insert(entry);
+            let body = collect_body(entry, &successors, &entries, &mut unassigned);
+            functions.push(build_function(entry, &body, &successors, &predecessors));
+        }
+        functions.sort_by_key(|function| function.entry);
+
+        let owners: HashMap<usize, usize> = functions
+            .iter()
+            .flat_map(|f| f.addresses().map(move |address| (*address, f.entry)))
+            .collect();
+        let calls = call_edges
+            .into_iter()
+            .map(|(call_site, callee)| Call {
+                call_site,
+                caller: owners[&call_site],
+                callee,
+                visits: trace_analyzer.edge_visits(call_site, callee),
+            })
+            .collect();
+
+        CallGraph { functions, calls }
+    }
+
+    pub fn function_at(&self, address: usize) -> Option<&Function> {
+        self.functions.iter().find(|f| f.contains(address))
+    }
+
+    /// Entries of the functions calling the function at `entry`.
+    pub fn callers(&self, entry: usize) -> Vec<usize> {
+        self.calls
+            .iter()
+            .filter(|call| call.callee == entry)
+            .map(|call| call.caller)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Entries of the functions called by the function at `entry`.
+    pub fn callees(&self, entry: usize) -> Vec<usize> {
+        self.calls
+            .iter()
+            .filter(|call| call.caller == entry)
+            .map(|call| call.callee)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Function with its best predicates, as listed in `functions.json`.
+#[derive(Serialize, Deserialize)]
+pub struct RankedFunction {
+    pub entry: usize,
+    pub name: Option<String>,
+    /// best score of the predicates in the function; none if no predicate was synthesized
+    pub suspiciousness: Option<f64>,
+    /// number of crashes and non-crashes entering the function
+    pub visits: LabeledCount,
+    pub blocks: usize,
+    pub instructions: usize,
+    pub callers: Vec<usize>,
+    pub callees: Vec<usize>,
+    /// best predicates in the function, best first
+    pub predicates: Vec<SerializedPredicate>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializedCallGraph {
+    /// ordered by suspiciousness, most suspicious first
+    pub functions: Vec<RankedFunction>,
+    pub calls: Vec<Call>,
+}
+
+impl CallGraph {
+    /// Ranks the functions by the best score of their predicates; ties are ordered by entry, and
+    /// functions without predicates come last.
+    pub fn rank_functions(
+        &self,
+        trace_analyzer: &TraceAnalyzer,
+        symbolizer: Option<&Symbolizer>,
+    ) -> Vec<RankedFunction> {
+        let mut ret: Vec<RankedFunction> = self
+            .functions
+            .iter()
+            .map(|function| {
+                let mut predicates: Vec<&Predicate> = function
+                    .addresses()
+                    .filter_map(|address| trace_analyzer.address_scores.get(address))
+                    .collect();
+                predicates.sort_by(|p1, p2| p1.rank(p2));
+                let mut predicates: Vec<SerializedPredicate> = predicates
+                    .into_iter()
+                    .take(REPORTED_PREDICATES)
+                    .map(|p| trace_analyzer.serialize_predicate(p))
+                    .collect();
+
+                let name = symbolizer.and_then(|symbolizer| {
+                    symbolizer.symbolize_predicates(predicates.iter_mut());
+                    symbolizer.symbolize(function.entry)?.function
+                });
+
+                RankedFunction {
+                    entry: function.entry,
+                    name,
+                    suspiciousness: predicates.first().map(|p| p.score),
+                    visits: trace_analyzer.visits(function.entry),
+                    blocks: function.blocks.len(),
+                    instructions: function.addresses().count(),
+                    callers: self.callers(function.entry),
+                    callees: self.callees(function.entry),
+                    predicates,
+                }
+            })
+            .collect();
+
+        ret.sort_by(|f1, f2| {
+            let suspiciousness = |f: &RankedFunction| f.suspiciousness.unwrap_or(f64::NEG_INFINITY);
+            cmp_scores(suspiciousness(f2), suspiciousness(f1)).then(f1.entry.cmp(&f2.entry))
+        });
+
+        ret
+    }
+
+    /// Graphviz call graph; functions are colored by suspiciousness and calls labeled with the
+    /// number of crashes and non-crashes taking them.
+    pub fn to_dot(&self, functions: &[RankedFunction]) -> String {
+        let mut ret = String::from(
+            "digraph \"call graph\" {\n  node [shape=box, style=filled, fontname=\"monospace\"];\n",
+        );
+
+        for function in functions.iter() {
+            let name = match &function.name {
+                Some(name) => format!("{}\\n{:#x}", escape(name), function.entry),
+                None => format!("{:#x}", function.entry),
+            };
+            let suspiciousness = match function.suspiciousness {
+                Some(score) => format!("\\nscore {}", score),
+                None => "".to_string(),
+            };
+            ret.push_str(&format!(
+                "  \"{:#x}\" [label=\"{}{}\", fillcolor=\"{}\"];\n",
+                function.entry,
+                name,
+                suspiciousness,
+                heat_color(function.suspiciousness)
+            ));
+        }
+
+        for call in self.calls.iter() {
+            ret.push_str(&format!(
+                "  \"{:#x}\" -> \"{:#x}\" [label=\"{:#x}\\ncrashes {}, non-crashes {}\"];\n",
+                call.caller,
+                call.callee,
+                call.call_site,
+                call.visits.crashes,
+                call.visits.non_crashes
+            ));
+        }
+        ret.push_str("}\n");
+
+        ret
+    }
+}
+
+/// Writes the ranked functions and calls to `functions.json` and the call graph to `call_graph.dot`
+/// in the output directory.
+pub fn dump_functions(
+    config: &Config,
+    trace_analyzer: &TraceAnalyzer,
+    symbolizer: Option<&Symbolizer>,
+) {
+    let call_graph = CallGraph::recover(trace_analyzer);
+    let functions = call_graph.rank_functions(trace_analyzer, symbolizer);
+
+    let file_path = format!("{}/call_graph.dot", config.output_directory);
+    fs::write(&file_path, call_graph.to_dot(&functions))
+        .expect(&format!("Could not write file {}", file_path));
+
+    let serialized = SerializedCallGraph {
+        functions,
+        calls: call_graph.calls,
+    };
+    let content = serde_json::to_string(&serialized).expect("Could not serialize call graph");
+    let file_path = format!("{}/functions.json", config.output_directory);
+    fs::write(&file_path, content).expect(&format!("Could not write file {}", file_path));
+}
+
+/// Instructions reachable from `entry` without entering another function or an assigned instruction.
+fn collect_body(
+    entry: usize,
+    successors: &HashMap<usize, BTreeMap<usize, EdgeType>>,
+    entries: &BTreeSet<usize>,
+    unassigned: &mut BTreeSet<usize>,
+) -> HashSet<usize> {
+    let mut body = HashSet::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(entry);
+    unassigned.remove(&entry);
+    while let Some(address) = queue.pop_front() {
+        body.insert(address);
+        for succ in successors.get(&address).into_iter().flat_map(|s| s.keys()) {
+            if !entries.contains(succ) && unassigned.remove(succ) {
+                queue.push_back(*succ);
+            }
+        }
+    }
+
+    body
+}
+
+fn build_function(
+    entry: usize,
+    body: &HashSet<usize>,
+    successors: &HashMap<usize, BTreeMap<usize, EdgeType>>,
+    predecessors: &HashMap<usize, HashSet<usize>>,
+) -> Function {
+    let inner_successors = |address: usize| -> Vec<(usize, EdgeType)> {
+        successors
+            .get(&address)
+            .into_iter()
+            .flatten()
+            .filter(|(succ, _)| body.contains(succ))
+            .map(|(succ, edge_type)| (*succ, *edge_type))
+            .collect()
+    };
+    let inner_predecessors = |address: usize| -> Vec<usize> {
+        predecessors
+            .get(&address)
+            .into_iter()
+            .flatten()
+            .filter(|pred| body.contains(pred))
+            .cloned()
+            .collect()
+    };
+
+    let is_leader = |address: usize| {
+        let preds = inner_predecessors(address);
+        address == entry || preds.len() != 1 || inner_successors(preds[0]).len() != 1
+    };
+
+    let mut blocks = vec![];
+    let mut edges = vec![];
+    let mut leaders: Vec<usize> = body.iter().cloned().filter(|a| is_leader(*a)).collect();
+    leaders.sort_by_key(|address| (*address != entry, *address));
+    for start in leaders {
+        let mut block = vec![start];
+        loop {
+            let succs = inner_successors(*block.last().unwrap());
+            match succs.as_slice() {
+                [(succ, _)] if !is_leader(*succ) => block.push(*succ),
+                _ => {
+                    for (succ, edge_type) in succs {
+                        edges.push((*block.last().unwrap(), succ, edge_type));
+                    }
+                    break;
+                }
+            }
+        }
+        blocks.push(FunctionBlock { start, body: block });
+    }
+
+    Function {
+        entry,
+        blocks,
+        edges,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::TraceSource;
+    use crate::trace::tests::trace;
+
+    /// `main` calls `f` and `g`, `g` calls `f`.
+    const PATH: &[(usize, &str)] = &[
+        (0x1000, "call 0x2000"),
+        (0x2000, "mov rbx, rax"),
+        (0x2003, "ret"),
+        (0x1005, "call 0x3000"),
+        (0x3000, "call 0x2000"),
+        (0x2000, "mov rbx, rax"),
+        (0x2003, "ret"),
+        (0x3005, "ret"),
+        (0x100a, "ret"),
+    ];
+
+    fn call_graph() -> CallGraph {
+        let trace_analyzer = TraceAnalyzer::builder(
+            TraceSource::Traces(vec![trace(PATH, |_| 0)]),
+            TraceSource::Traces(vec![trace(PATH, |_| 1)]),
+        )
+        .build()
+        .unwrap();
+
+        CallGraph::recover(&trace_analyzer)
+    }
+
+    #[test]
+    fn test_is_call() {
+        assert!(is_call("call 0x2000"));
+        assert!(is_call(" call rax"));
+        assert!(!is_call("jmp 0x2000"));
+        assert!(!is_call("ret"));
+    }
+
+    #[test]
+    fn test_recover_functions() {
+        let call_graph = call_graph();
+        let functions: Vec<(usize, Vec<usize>)> = call_graph
+            .functions
+            .iter()
+            .map(|f| (f.entry, f.addresses().cloned().collect()))
+            .collect();
+
+        assert_eq!(
+            functions,
+            vec![
+                (0x1000, vec![0x1000, 0x1005, 0x100a]),
+                (0x2000, vec![0x2000, 0x2003]),
+                (0x3000, vec![0x3000, 0x3005]),
+            ]
+        );
+        assert_eq!(call_graph.function_at(0x100a).unwrap().entry, 0x1000);
+        assert_eq!(call_graph.function_at(0x2003).unwrap().entry, 0x2000);
+        assert!(call_graph.function_at(0x4000).is_none());
+    }
+
+    #[test]
+    fn test_recover_calls() {
+        let call_graph = call_graph();
+        let calls: Vec<(usize, usize, usize, usize, usize)> = call_graph
+            .calls
+            .iter()
+            .map(|c| {
+                (
+                    c.call_site,
+                    c.caller,
+                    c.callee,
+                    c.visits.crashes,
+                    c.visits.non_crashes,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            calls,
+            vec![
+                (0x1000, 0x1000, 0x2000, 1, 1),
+                (0x1005, 0x1000, 0x3000, 1, 1),
+                (0x3000, 0x3000, 0x2000, 1, 1),
+            ]
+        );
+        assert_eq!(call_graph.callers(0x2000), vec![0x1000, 0x3000]);
+        assert_eq!(call_graph.callees(0x1000), vec![0x2000, 0x3000]);
+        assert!(call_graph.callees(0x2000).is_empty());
+    }
+}
@@ -1,10 +1,25 @@
+pub mod analysis_cache;
+pub mod builder;
+pub mod cfg_export;
+pub mod clustering;
 pub mod config;
 pub mod control_flow_graph;
 pub mod debug;
+pub mod disassembly;
+pub mod distribution;
+pub mod dominators;
+pub mod error;
+pub mod functions;
+pub mod memory_regions;
 pub mod predicate_analysis;
 pub mod predicate_builder;
 pub mod predicate_synthesizer;
 pub mod predicates;
+pub mod sampling;
+pub mod scoring;
+pub mod symbolization;
 pub mod trace;
+pub mod trace_aggregates;
 pub mod trace_analyzer;
 pub mod trace_integrity;
+pub mod validation;
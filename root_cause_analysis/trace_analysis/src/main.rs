@@ -1,14 +1,77 @@
+use std::process::exit;
 use structopt::StructOpt;
-use trace_analysis::config::Config;
+use trace_analysis::cfg_export::export_function_cfgs;
+use trace_analysis::clustering::analyze_clusters;
+use trace_analysis::config::{Command, Config};
 use trace_analysis::debug::{
     debug_predicate_at_address, diff_traces, diff_traces_at_address, dump_trace_info,
+    explain_predicate_at_address,
 };
+use trace_analysis::functions::dump_functions;
+use trace_analysis::symbolization::Symbolizer;
 use trace_analysis::trace_analyzer::TraceAnalyzer;
+use trace_analysis::trace_integrity::TraceIntegrityChecker;
+use trace_analysis::validation::ValidationReport;
+
+fn validate(trace_dir: &str, disabled_checks: &[String]) {
+    let report = ValidationReport::validate(trace_dir, disabled_checks);
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).expect("Could not serialize validation report")
+    );
+
+    if !report.is_valid() {
+        exit(1);
+    }
+}
 
 fn main() {
     let config = Config::from_args();
+    if let Some(Command::Validate {
+        trace_dir,
+        disabled_checks,
+    }) = &config.command
+    {
+        return validate(trace_dir, disabled_checks);
+    }
+
+    if let Err(error) = config.validate() {
+        eprintln!("{}", error);
+        exit(1);
+    }
 
-    let trace_analyzer = TraceAnalyzer::new(&config);
+    if config.cluster_crashes {
+        if let Err(error) = analyze_clusters(&config) {
+            eprintln!("{}", error);
+            exit(1);
+        }
+        return;
+    }
+
+    let trace_analyzer = match TraceAnalyzer::new(&config) {
+        Ok(trace_analyzer) => trace_analyzer,
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1);
+        }
+    };
+
+    if config.check_traces {
+        let report = TraceIntegrityChecker::new(&config.enabled_checks, &config.disabled_checks)
+            .check_traces(&trace_analyzer);
+        match config.check_format.as_str() {
+            "json" => println!(
+                "{}",
+                serde_json::to_string_pretty(&report).expect("Could not serialize check report")
+            ),
+            _ => print!("{}", report),
+        }
+        if report.errors() > 0 {
+            exit(1);
+        }
+        return;
+    }
 
     if config.dump_traces {
         println!("dumping traces");
@@ -30,11 +93,32 @@ fn main() {
             "dumping predicate at address 0x{:x}",
             config.predicate_address
         );
-        debug_predicate_at_address(config.predicate_address, &trace_analyzer);
+        debug_predicate_at_address(
+            config.predicate_address,
+            &trace_analyzer,
+            config.top_predicates,
+        );
+        if config.explain_predicate {
+            explain_predicate_at_address(&config, &trace_analyzer);
+        }
     }
 
     if config.dump_scores {
         println!("dumping linear scores");
         trace_analyzer.dump_scores(&config, false, false);
     }
+
+    if config.dump_functions || config.export_cfg() {
+        let symbolizer = Symbolizer::from_config(&config, trace_analyzer.image_base());
+
+        if config.dump_functions {
+            println!("dumping functions");
+            dump_functions(&config, &trace_analyzer, symbolizer.as_ref());
+        }
+
+        if config.export_cfg() {
+            println!("exporting function CFGs");
+            export_function_cfgs(&config, &trace_analyzer, symbolizer.as_ref());
+        }
+    }
 }
@@ -0,0 +1,212 @@
+use crate::trace::TraceVec;
+use crate::trace_analyzer::MemoryAddresses;
+use std::fmt;
+
+/// Default end of the region of values considered to point near NULL.
+pub const NULL_REGION_END: usize = 0x10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryRegion {
+    Null,
+    Heap,
+    Stack,
+    Image,
+    Unmapped,
+}
+
+/// Regions in the order values are classified, e.g., a heap overlapping the image takes precedence.
+const MAPPED_REGIONS: [MemoryRegion; 4] = [
+    MemoryRegion::Null,
+    MemoryRegion::Heap,
+    MemoryRegion::Stack,
+    MemoryRegion::Image,
+];
+
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MemoryRegion::Null => "null",
+            MemoryRegion::Heap => "heap",
+            MemoryRegion::Stack => "stack",
+            MemoryRegion::Image => "image",
+            MemoryRegion::Unmapped => "unmapped",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Address ranges of the memory regions; all ranges are [start, end).
+#[derive(Clone)]
+pub struct MemoryMap {
+    null: (usize, usize),
+    heap: (usize, usize),
+    stack: (usize, usize),
+    image: (usize, usize),
+}
+
+impl MemoryMap {
+    pub fn new(memory_addresses: &MemoryAddresses, traces: &[&TraceVec]) -> MemoryMap {
+        let image_start = traces
+            .iter()
+            .flat_map(|t| t.iter())
+            .map(|t| t.image_base)
+            .min()
+            .unwrap_or(0);
+        let image_end = traces
+            .iter()
+            .flat_map(|t| t.iter())
+            .map(|t| t.image_end)
+            .max()
+            .unwrap_or(0);
+
+        MemoryMap::with_image(memory_addresses, image_start, image_end)
+    }
+
+    pub fn with_image(
+        memory_addresses: &MemoryAddresses,
+        image_start: usize,
+        image_end: usize,
+    ) -> MemoryMap {
+        MemoryMap {
+            null: (0, memory_addresses.null_region_end),
+            heap: (memory_addresses.heap_start, memory_addresses.heap_end),
+            stack: (memory_addresses.stack_start, memory_addresses.stack_end),
+            image: (image_start, image_end),
+        }
+    }
+
+    pub fn image_base(&self) -> usize {
+        self.image.0
+    }
+
+    /// Returns the address ranges of a region; the unmapped ranges are the gaps between the
+    /// mapped regions.
+    pub fn ranges(&self, region: MemoryRegion) -> Vec<(usize, usize)> {
+        match region {
+            MemoryRegion::Null => vec![self.null],
+            MemoryRegion::Heap => vec![self.heap],
+            MemoryRegion::Stack => vec![self.stack],
+            MemoryRegion::Image => vec![self.image],
+            MemoryRegion::Unmapped => self.unmapped(),
+        }
+    }
+
+    fn unmapped(&self) -> Vec<(usize, usize)> {
+        let mut mapped: Vec<_> = MAPPED_REGIONS
+            .iter()
+            .flat_map(|region| self.ranges(*region))
+            .filter(|(start, end)| start < end)
+            .collect();
+        mapped.sort();
+
+        let mut ret = vec![];
+        let mut gap_start = 0;
+        for (start, end) in mapped {
+            if gap_start < start {
+                ret.push((gap_start, start));
+            }
+            gap_start = gap_start.max(end);
+        }
+        if gap_start < usize::MAX {
+            ret.push((gap_start, usize::MAX));
+        }
+
+        ret
+    }
+
+    /// Returns the region of `value` and the range containing it; `None` for `usize::MAX`, which
+    /// no [start, end) range contains.
+    pub fn locate(&self, value: u64) -> Option<(MemoryRegion, (usize, usize))> {
+        MAPPED_REGIONS
+            .iter()
+            .chain([MemoryRegion::Unmapped].iter())
+            .flat_map(|region| {
+                self.ranges(*region)
+                    .into_iter()
+                    .map(move |range| (*region, range))
+            })
+            .find(|(_, (start, end))| *start as u64 <= value && value < *end as u64)
+    }
+
+    pub fn classify(&self, value: u64) -> MemoryRegion {
+        MAPPED_REGIONS
+            .iter()
+            .find(|region| {
+                self.ranges(**region)
+                    .iter()
+                    .any(|(start, end)| *start as u64 <= value && value < *end as u64)
+            })
+            .cloned()
+            .unwrap_or(MemoryRegion::Unmapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_map() -> MemoryMap {
+        let memory_addresses = MemoryAddresses {
+            heap_start: 0x1000_0000,
+            heap_end: 0x2000_0000,
+            stack_start: 0x7000_0000,
+            stack_end: 0x8000_0000,
+            ..MemoryAddresses::default()
+        };
+
+        MemoryMap::with_image(&memory_addresses, 0x40_0000, 0x50_0000)
+    }
+
+    #[test]
+    fn test_classify_end_exclusive() {
+        let memory_map = memory_map();
+
+        assert_eq!(memory_map.classify(0), MemoryRegion::Null);
+        assert_eq!(memory_map.classify(0xffff), MemoryRegion::Null);
+        assert_eq!(memory_map.classify(0x10000), MemoryRegion::Unmapped);
+        assert_eq!(memory_map.classify(0x1000_0000), MemoryRegion::Heap);
+        assert_eq!(memory_map.classify(0x2000_0000), MemoryRegion::Unmapped);
+        assert_eq!(memory_map.classify(0x7fff_ffff), MemoryRegion::Stack);
+        assert_eq!(memory_map.classify(0x40_0000), MemoryRegion::Image);
+    }
+
+    #[test]
+    fn test_unmapped_ranges() {
+        let memory_map = memory_map();
+
+        assert_eq!(
+            memory_map.ranges(MemoryRegion::Unmapped),
+            vec![
+                (0x10000, 0x40_0000),
+                (0x50_0000, 0x1000_0000),
+                (0x2000_0000, 0x7000_0000),
+                (0x8000_0000, usize::MAX),
+            ]
+        );
+        assert_eq!(
+            memory_map.locate(0x3000_0000),
+            Some((MemoryRegion::Unmapped, (0x2000_0000, 0x7000_0000)))
+        );
+        assert_eq!(
+            memory_map.locate(0x1800_0000),
+            Some((MemoryRegion::Heap, (0x1000_0000, 0x2000_0000)))
+        );
+    }
+
+    #[test]
+    fn test_null_region_end() {
+        let memory_addresses = MemoryAddresses {
+            null_region_end: 0x1000,
+            ..MemoryAddresses::default()
+        };
+        let memory_map = MemoryMap::with_image(&memory_addresses, 0, 0);
+
+        assert_eq!(memory_map.classify(0xfff), MemoryRegion::Null);
+        assert_eq!(memory_map.classify(0x1000), MemoryRegion::Unmapped);
+        assert_eq!(
+            memory_map.ranges(MemoryRegion::Unmapped),
+            vec![(0x1000, usize::MAX)]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::predicate_builder::PredicateBuilder;
 use crate::predicates::Predicate;
+use crate::scoring::ConfusionMatrix;
+use crate::trace_aggregates::LabeledCount;
 
 use crate::trace_analyzer::TraceAnalyzer;
 use rayon::prelude::*;
@@ -7,45 +9,198 @@ use rayon::prelude::*;
 pub struct PredicateAnalyzer {}
 
 impl PredicateAnalyzer {
-    pub fn evaluate_best_predicate_at_address(
+    /// Returns the `top_k` best predicates at `address`, best first.
+    pub fn evaluate_top_predicates_at_address(
         address: usize,
         trace_analyzer: &TraceAnalyzer,
-    ) -> Predicate {
+        top_k: usize,
+    ) -> Vec<Predicate> {
         let predicates = PredicateBuilder::gen_predicates(address, trace_analyzer);
 
-        if predicates.is_empty() {
-            return Predicate::gen_empty(address);
+        let ret: Vec<Predicate> = predicates
+            .into_par_iter()
+            .filter_map(|p| PredicateAnalyzer::evaluate_predicate(trace_analyzer, p))
+            .collect();
+
+        if ret.is_empty() {
+            return vec![Predicate::gen_empty(address)];
         }
 
-        let mut ret: Vec<Predicate> = predicates
+        Predicate::top_k(ret, top_k)
+    }
+
+    pub fn evaluate_top_combined_predicates_at_address(
+        address: usize,
+        trace_analyzer: &TraceAnalyzer,
+        k: usize,
+        top_k: usize,
+    ) -> Vec<Predicate> {
+        let predicates = PredicateBuilder::gen_predicates(address, trace_analyzer);
+
+        let mut atomic: Vec<Predicate> = predicates
             .into_par_iter()
-            .map(|p| PredicateAnalyzer::evaluate_predicate(trace_analyzer, p))
+            .filter_map(|p| PredicateAnalyzer::evaluate_predicate(trace_analyzer, p))
             .collect();
 
-        ret.sort_by(|p1, p2| p1.score.partial_cmp(&p2.score).unwrap());
-        ret.pop().unwrap()
+        if atomic.is_empty() {
+            return vec![Predicate::gen_empty(address)];
+        }
+        atomic.sort_by(Predicate::rank);
+
+        let best = &atomic[..k.min(atomic.len())];
+        let mut combined = vec![];
+        for (i, p1) in best.iter().enumerate() {
+            for p2 in best[i + 1..].iter().filter(|p2| p1.can_combine(p2)) {
+                combined.push(p1.and(p2));
+                combined.push(p1.or(p2));
+            }
+        }
+
+        let mut ret: Vec<Predicate> = combined
+            .into_par_iter()
+            .filter_map(|p| PredicateAnalyzer::evaluate_predicate(trace_analyzer, p))
+            .collect();
+        ret.extend(atomic);
+
+        Predicate::top_k(ret, top_k)
+    }
+
+    /// Scores `predicate`; `None` if the traces were streamed and the aggregates cannot count it.
+    fn evaluate_predicate(
+        trace_analyzer: &TraceAnalyzer,
+        mut predicate: Predicate,
+    ) -> Option<Predicate> {
+        predicate.matrix = PredicateAnalyzer::matrix(trace_analyzer, &predicate)?;
+        predicate.score = trace_analyzer.scoring.score(&predicate.matrix);
+
+        Some(predicate)
+    }
+
+    /// Scores `predicate` given the number of crashes and non-crashes satisfying it.
+    pub fn scored(
+        trace_analyzer: &TraceAnalyzer,
+        mut predicate: Predicate,
+        satisfied: LabeledCount,
+    ) -> Predicate {
+        predicate.matrix = PredicateAnalyzer::confusion_matrix(trace_analyzer, satisfied);
+        predicate.score = trace_analyzer.scoring.score(&predicate.matrix);
+
+        predicate
+    }
+
+    fn confusion_matrix(
+        trace_analyzer: &TraceAnalyzer,
+        satisfied: LabeledCount,
+    ) -> ConfusionMatrix {
+        let counts = trace_analyzer.trace_counts();
+        ConfusionMatrix::new(
+            satisfied.crashes,
+            counts.non_crashes - satisfied.non_crashes,
+            counts.crashes,
+            counts.non_crashes,
+        )
     }
 
-    fn evaluate_predicate(trace_analyzer: &TraceAnalyzer, mut predicate: Predicate) -> Predicate {
+    /// Confusion matrix of `predicate` on the traces or, if the traces were streamed, on the
+    /// aggregates of its address; `None` if the aggregates cannot count it.
+    pub fn matrix(
+        trace_analyzer: &TraceAnalyzer,
+        predicate: &Predicate,
+    ) -> Option<ConfusionMatrix> {
+        if let Some(aggregates) = &trace_analyzer.aggregates {
+            let satisfied = match aggregates.addresses.get(&predicate.address) {
+                Some(aggregate) => predicate.count_aggregate(aggregate)?,
+                None => LabeledCount::default(),
+            };
+            return Some(PredicateAnalyzer::confusion_matrix(
+                trace_analyzer,
+                satisfied,
+            ));
+        }
+
         let true_positives = trace_analyzer
             .crashes
             .as_slice()
             .par_iter()
             .map(|t| t.instructions.get(&predicate.address))
             .filter(|i| predicate.execute(i))
-            .count() as f64
-            / trace_analyzer.crashes.len() as f64;
+            .count();
         let true_negatives = trace_analyzer
             .non_crashes
             .as_slice()
             .par_iter()
             .map(|t| t.instructions.get(&predicate.address))
             .filter(|i| !predicate.execute(i))
-            .count() as f64
-            / trace_analyzer.non_crashes.len() as f64;
+            .count();
 
-        predicate.score = (true_positives + true_negatives) / 2.0;
+        Some(ConfusionMatrix::new(
+            true_positives,
+            true_negatives,
+            trace_analyzer.crashes.len(),
+            trace_analyzer.non_crashes.len(),
+        ))
+    }
+}
 
-        predicate
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::TraceSource;
+    use crate::predicates::empty;
+    use crate::trace::tests::trace;
+    use crate::trace::Trace;
+
+    fn predicate(name: &str) -> Predicate {
+        Predicate::new(name, 0x1000, empty, None, None)
+    }
+
+    #[test]
+    fn test_can_combine() {
+        let min = predicate("rax min_reg_val_less 0x10");
+        let last = predicate("rax last_reg_val_less 0x10");
+        let count_less = predicate("ins_count_less 5");
+        let count_greater_or_equal = predicate("ins_count_greater_or_equal 5");
+        let edge_count = predicate("0x1000 edge_count_greater_or_equal 0x1004 2");
+
+        assert!(min.can_combine(&count_greater_or_equal));
+        assert!(last.can_combine(&count_less));
+        assert!(!min.can_combine(&last));
+        assert!(!min.can_combine(&count_less));
+        assert!(!count_greater_or_equal.can_combine(&edge_count));
+    }
+
+    #[test]
+    fn test_combined_predicates_can_be_monitored() {
+        let path = [(0x1000, "mov rbx, rax"), (0x1004, "ret")];
+        let traces = |values: &[u64]| -> Vec<Trace> {
+            values
+                .iter()
+                .map(|value| trace(&path, |_| *value))
+                .collect()
+        };
+        let trace_analyzer = TraceAnalyzer::builder(
+            TraceSource::Traces(traces(&[1, 2, 3])),
+            TraceSource::Traces(traces(&[7, 8, 9])),
+        )
+        .build()
+        .unwrap();
+
+        let predicates = PredicateAnalyzer::evaluate_top_combined_predicates_at_address(
+            0x1000,
+            &trace_analyzer,
+            20,
+            1000,
+        );
+        let combined: Vec<&Predicate> = predicates
+            .iter()
+            .filter(|p| p.name.contains(" && ") || p.name.contains(" || "))
+            .collect();
+
+        assert!(!combined.is_empty());
+        for p in combined {
+            let names: Vec<&str> = p.name.split(['&', '|']).collect();
+            let (p1, p2) = (names[0].trim(), names[2].trim());
+            assert!(predicate(p1).can_combine(&predicate(p2)), "{}", p.name);
+        }
     }
 }
@@ -1,33 +1,50 @@
 use crate::control_flow_graph::ControlFlowGraph;
+use crate::memory_regions::MemoryRegion;
 use crate::predicate_synthesizer::{gen_reg_val_name, PredicateSynthesizer};
 use crate::predicates::*;
 use crate::trace::Instruction;
 use crate::trace::{Selector, REGISTERS};
+use crate::trace_aggregates::{AddressAggregate, EdgeAggregate, LabeledCount};
 use crate::trace_analyzer::TraceAnalyzer;
+use std::collections::HashSet;
+
+/// EFLAGS bits and their names.
+static FLAGS: [(&str, u64); 9] = [
+    ("carry", 0),
+    ("parity", 2),
+    ("adjust", 4),
+    ("zero", 6),
+    ("sign", 7),
+    ("trap", 8),
+    ("interrupt", 9),
+    ("direction", 10),
+    ("overflow", 11),
+];
 
 pub struct PredicateBuilder {}
 
 impl PredicateBuilder {
     fn gen_visited(address: usize) -> Vec<Predicate> {
-        vec![Predicate::new(
-            "is_visited",
-            address,
-            is_visited,
-            None,
-            None,
-        )]
+        vec![
+            Predicate::new("is_visited", address, is_visited, None, None)
+                .with_aggregate(|aggregate| aggregate.visits),
+        ]
     }
     fn gen_all_edge_from_to_predicates(
         address: usize,
         cfg: &ControlFlowGraph,
         pred_name: &str,
         func: fn(&Instruction, Option<usize>, Option<usize>) -> bool,
+        count: fn(&EdgeAggregate) -> LabeledCount,
     ) -> Vec<Predicate> {
         cfg.get_instruction_successors(address)
             .iter()
             .map(|to| {
+                let to = *to;
                 let pred_name = format!("0x{:x} {} 0x{:x}", address, pred_name, to);
-                Predicate::new(&pred_name, address, func, Some(*to), None)
+                Predicate::new(&pred_name, address, func, Some(to), None).with_aggregate(
+                    move |aggregate| aggregate.edges.get(&to).map(count).unwrap_or_default(),
+                )
             })
             .collect()
     }
@@ -37,10 +54,12 @@ impl PredicateBuilder {
         pred_name: &str,
         value: usize,
         func: fn(&Instruction, Option<usize>, Option<usize>) -> bool,
+        count: fn(&AddressAggregate, usize) -> LabeledCount,
     ) -> Predicate {
         let pred_name = format!("{} {}", pred_name, value);
 
         Predicate::new(&pred_name, address, func, Some(value), None)
+            .with_aggregate(move |aggregate| count(aggregate, value))
     }
 
     pub fn gen_flag_predicates(address: usize, trace_analyzer: &TraceAnalyzer) -> Vec<Predicate> {
@@ -48,100 +67,35 @@ impl PredicateBuilder {
             return vec![];
         }
 
-        vec![
-            // min
-            Predicate::new(
-                "min_carry_flag_set",
-                address,
-                min_carry_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "min_parity_flag_set",
-                address,
-                min_parity_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "min_adjust_flag_set",
-                address,
-                min_adjust_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new("min_zero_flag_set", address, min_zero_flag_set, None, None),
-            Predicate::new("min_sign_flag_set", address, min_sign_flag_set, None, None),
-            Predicate::new("min_trap_flag_set", address, min_trap_flag_set, None, None),
-            Predicate::new(
-                "min_interrupt_flag_set",
-                address,
-                min_interrupt_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "min_direction_flag_set",
-                address,
-                min_direction_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "min_overflow_flag_set",
-                address,
-                min_overflow_flag_set,
-                None,
-                None,
-            ),
-            // max
-            Predicate::new(
-                "max_carry_flag_set",
-                address,
-                max_carry_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "max_parity_flag_set",
-                address,
-                max_parity_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "max_adjust_flag_set",
-                address,
-                max_adjust_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new("max_zero_flag_set", address, max_zero_flag_set, None, None),
-            Predicate::new("max_sign_flag_set", address, max_sign_flag_set, None, None),
-            Predicate::new("max_trap_flag_set", address, max_trap_flag_set, None, None),
-            Predicate::new(
-                "max_interrupt_flag_set",
-                address,
-                max_interrupt_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "max_direction_flag_set",
-                address,
-                max_direction_flag_set,
-                None,
-                None,
-            ),
-            Predicate::new(
-                "max_overflow_flag_set",
-                address,
-                max_overflow_flag_set,
-                None,
-                None,
-            ),
+        let mut ret = vec![];
+        for (prefix, selector) in [
+            ("min", Selector::RegMin),
+            ("max", Selector::RegMax),
+            ("last", Selector::RegLast),
         ]
+        .iter()
+        {
+            let selector = *selector;
+            for (flag, pos) in FLAGS.iter() {
+                let pos = *pos;
+                ret.push(
+                    Predicate::from_closure(
+                        &format!("{}_{}_flag_set", prefix, flag),
+                        address,
+                        move |instruction| is_flag_bit_set(instruction, selector, pos),
+                    )
+                    .with_aggregate(move |aggregate| {
+                        aggregate
+                            .registers(&selector)
+                            .get(&22)
+                            .map(|values| values.count_where(|v| v & (1 << pos) != 0))
+                            .unwrap_or_default()
+                    }),
+                );
+            }
+        }
+
+        ret
     }
 
     pub fn gen_cfg_predicates(address: usize, cfg: &ControlFlowGraph) -> Vec<Predicate> {
@@ -152,12 +106,18 @@ impl PredicateBuilder {
             return ret;
         }
 
+        // unconditional edges always take the same successor
+        if cfg.is_unconditional(address) {
+            return ret;
+        }
+
         // #successors > 0
         ret.push(PredicateBuilder::gen_all_edge_val_predicates(
             address,
             "num_successors_greater",
             0,
             num_successors_greater,
+            AddressAggregate::num_successors_greater,
         ));
         // #successors > 1
         ret.push(PredicateBuilder::gen_all_edge_val_predicates(
@@ -165,6 +125,7 @@ impl PredicateBuilder {
             "num_successors_greater",
             1,
             num_successors_greater,
+            AddressAggregate::num_successors_greater,
         ));
         // #successors > 2
         ret.push(PredicateBuilder::gen_all_edge_val_predicates(
@@ -172,6 +133,7 @@ impl PredicateBuilder {
             "num_successors_greater",
             2,
             num_successors_greater,
+            AddressAggregate::num_successors_greater,
         ));
 
         // #successors == 0
@@ -180,6 +142,7 @@ impl PredicateBuilder {
             "num_successors_equal",
             0,
             num_successors_equal,
+            AddressAggregate::num_successors_equal,
         ));
         // #successors == 1
         ret.push(PredicateBuilder::gen_all_edge_val_predicates(
@@ -187,6 +150,7 @@ impl PredicateBuilder {
             "num_successors_equal",
             1,
             num_successors_equal,
+            AddressAggregate::num_successors_equal,
         ));
         // #successors == 2
         ret.push(PredicateBuilder::gen_all_edge_val_predicates(
@@ -194,6 +158,7 @@ impl PredicateBuilder {
             "num_successors_equal",
             2,
             num_successors_equal,
+            AddressAggregate::num_successors_equal,
         ));
         // edge addr -> x cfg edges exists
         ret.extend(PredicateBuilder::gen_all_edge_from_to_predicates(
@@ -201,12 +166,22 @@ impl PredicateBuilder {
             cfg,
             "has_edge_to",
             has_edge_to,
+            |edge| edge.taken,
         ));
         ret.extend(PredicateBuilder::gen_all_edge_from_to_predicates(
             address,
             cfg,
             "edge_only_taken_to",
             edge_only_taken_to,
+            |edge| edge.only_taken,
+        ));
+        // last edge addr -> x
+        ret.extend(PredicateBuilder::gen_all_edge_from_to_predicates(
+            address,
+            cfg,
+            "last_edge_to",
+            last_edge_to,
+            |edge| edge.last_taken,
         ));
         ret
     }
@@ -234,6 +209,7 @@ impl PredicateBuilder {
                     selector_val_less_name(selector),
                     value as u64,
                 );
+                let selector = *selector;
                 Predicate::new(
                     &pred_name,
                     address,
@@ -241,10 +217,85 @@ impl PredicateBuilder {
                     Some(reg_index),
                     Some(value),
                 )
+                .with_aggregate(move |aggregate| {
+                    aggregate
+                        .values(&selector, Some(reg_index))
+                        .map(|values| values.less(value as u64))
+                        .unwrap_or_default()
+                })
+            })
+            .collect()
+    }
+
+    fn gen_all_reg_region_predicates(
+        address: usize,
+        trace_analyzer: &TraceAnalyzer,
+        selector: Selector,
+    ) -> Vec<Predicate> {
+        (0..REGISTERS.len())
+            .into_iter()
+            .filter(|reg_index| {
+                trace_analyzer.any_instruction_at_address_contains_reg(address, *reg_index)
+            })
+            /* skip RSP */
+            .filter(|reg_index| *reg_index != 7)
+            /* skip segment registers and EFLAGS */
+            .filter(|reg_index| !(16..=22).contains(reg_index))
+            .flat_map(|reg_index| {
+                // an unmapped value yields a predicate on the gap between mapped regions containing it
+                let regions: HashSet<(MemoryRegion, (usize, usize))> = trace_analyzer
+                    .values_at_address(address, &selector, Some(reg_index))
+                    .into_iter()
+                    .filter_map(|v| trace_analyzer.memory_map.locate(v))
+                    .collect();
+
+                regions
+                    .into_iter()
+                    .map(|(region, (start, end))| {
+                        let pred_name = format!(
+                            "{} {} 0x{:x} 0x{:x}",
+                            REGISTERS[reg_index],
+                            selector_val_in_region_name(&selector, region),
+                            start,
+                            end
+                        );
+                        Predicate::from_closure(&pred_name, address, move |instruction| {
+                            reg_val_in_range(instruction, &selector, reg_index, start, end)
+                        })
+                        .with_aggregate(move |aggregate| {
+                            aggregate
+                                .values(&selector, Some(reg_index))
+                                .map(|values| values.in_range(start as u64, end as u64))
+                                .unwrap_or_default()
+                        })
+                    })
+                    .collect::<Vec<_>>()
             })
             .collect()
     }
 
+    pub fn gen_region_predicates(address: usize, trace_analyzer: &TraceAnalyzer) -> Vec<Predicate> {
+        let mut ret = vec![];
+
+        ret.extend(PredicateBuilder::gen_all_reg_region_predicates(
+            address,
+            trace_analyzer,
+            Selector::RegMin,
+        ));
+        ret.extend(PredicateBuilder::gen_all_reg_region_predicates(
+            address,
+            trace_analyzer,
+            Selector::RegMax,
+        ));
+        ret.extend(PredicateBuilder::gen_all_reg_region_predicates(
+            address,
+            trace_analyzer,
+            Selector::RegLast,
+        ));
+
+        ret
+    }
+
     pub fn gen_register_predicates(
         address: usize,
         trace_analyzer: &TraceAnalyzer,
@@ -301,6 +352,31 @@ impl PredicateBuilder {
             0xff,
         ));
 
+        ret.extend(PredicateBuilder::gen_all_reg_val_predicates(
+            address,
+            trace_analyzer,
+            &Selector::RegLast,
+            0xffffffffffffffff,
+        ));
+        ret.extend(PredicateBuilder::gen_all_reg_val_predicates(
+            address,
+            trace_analyzer,
+            &Selector::RegLast,
+            0xffffffff,
+        ));
+        ret.extend(PredicateBuilder::gen_all_reg_val_predicates(
+            address,
+            trace_analyzer,
+            &Selector::RegLast,
+            0xffff,
+        ));
+        ret.extend(PredicateBuilder::gen_all_reg_val_predicates(
+            address,
+            trace_analyzer,
+            &Selector::RegLast,
+            0xff,
+        ));
+
         ret
     }
 
@@ -312,16 +388,34 @@ impl PredicateBuilder {
 
         ret.extend(PredicateBuilder::gen_visited(address));
 
+        ret.extend(PredicateSynthesizer::count_predicates_at_address(
+            address,
+            trace_analyzer,
+        ));
+
         if !skip_register_predicates {
             ret.extend(PredicateSynthesizer::constant_predicates_at_address(
                 address,
                 trace_analyzer,
             ));
 
+            // streamed traces do not keep the values of different registers in the same trace
+            if trace_analyzer.aggregates.is_none() {
+                ret.extend(PredicateSynthesizer::relational_predicates_at_address(
+                    address,
+                    trace_analyzer,
+                ));
+            }
+
             ret.extend(PredicateBuilder::gen_register_predicates(
                 address,
                 &trace_analyzer,
             ));
+
+            ret.extend(PredicateBuilder::gen_region_predicates(
+                address,
+                &trace_analyzer,
+            ));
         }
 
         ret.extend(PredicateBuilder::gen_cfg_predicates(
@@ -329,6 +423,11 @@ impl PredicateBuilder {
             &trace_analyzer.cfg,
         ));
 
+        ret.extend(PredicateSynthesizer::edge_count_predicates_at_address(
+            address,
+            trace_analyzer,
+        ));
+
         if !skip_register_predicates {
             ret.extend(PredicateBuilder::gen_flag_predicates(
                 address,
@@ -339,7 +438,7 @@ impl PredicateBuilder {
         ret
     }
 
-    fn skip_register_mnemonic(mnemonic: String) -> bool {
+    pub fn skip_register_mnemonic(mnemonic: String) -> bool {
         match mnemonic.as_str() {
             // leave instruction
             _ if mnemonic.contains("leave") => true,
@@ -1,7 +1,14 @@
+use crate::disassembly::tested_operands;
+use crate::memory_regions::MemoryRegion;
+use crate::predicate_analysis::PredicateAnalyzer;
 use crate::predicates::*;
+use crate::scoring::{cmp_scores, ConfusionMatrix, ScoringFunction};
 use crate::trace::{Selector, REGISTERS};
+use crate::trace_aggregates::LabeledCount;
 use crate::trace_analyzer::TraceAnalyzer;
 use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 
 pub struct PredicateSynthesizer {}
 
@@ -17,6 +24,42 @@ pub fn gen_reg_val_name(reg_index: Option<usize>, pred_name: String, value: u64)
     }
 }
 
+/// Thresholds `(v1, v2)` of the best `value >= v1` and `value < v2` predicates.
+///
+/// Each candidate split at one of the sorted unique `values` is scored in both directions, since
+/// only symmetric scoring functions such as balanced accuracy score `>=` as the inverse of `<`.
+/// `matrices` returns the confusion matrices of `value >= v` and `value < v`.
+pub fn best_thresholds(
+    values: &Vec<u64>,
+    scoring: &dyn ScoringFunction,
+    matrices: impl Fn(u64) -> (ConfusionMatrix, ConfusionMatrix) + Sync,
+) -> Option<(u64, u64)> {
+    let scores: Vec<(u64, f64, f64)> = values
+        .par_iter()
+        .map(|v| {
+            let (greater_or_equal, less) = matrices(*v);
+            (*v, scoring.score(&greater_or_equal), scoring.score(&less))
+        })
+        .collect();
+
+    // ties prefer the lowest `>=` and the highest `<` threshold
+    let (first, scores) = scores.split_first()?;
+    let (mut best_greater_or_equal, mut best_less) = ((first.0, first.1), (first.0, first.2));
+    for (v, greater_or_equal, less) in scores.iter().cloned() {
+        if cmp_scores(greater_or_equal, best_greater_or_equal.1) == Ordering::Greater {
+            best_greater_or_equal = (v, greater_or_equal);
+        }
+        if cmp_scores(less, best_less.1) != Ordering::Less {
+            best_less = (v, less);
+        }
+    }
+
+    Some((
+        PredicateSynthesizer::arithmetic_mean(best_greater_or_equal.0, values),
+        PredicateSynthesizer::arithmetic_mean(best_less.0, values),
+    ))
+}
+
 impl PredicateSynthesizer {
     pub fn constant_predicates_at_address(
         address: usize,
@@ -38,10 +81,182 @@ impl PredicateSynthesizer {
                 &Selector::RegMin,
             ),
         );
+        predicates.extend(
+            PredicateSynthesizer::register_constant_predicates_at_address(
+                address,
+                trace_analyzer,
+                &Selector::RegLast,
+            ),
+        );
 
         predicates
     }
 
+    /// Compares the last values of two registers the instruction uses as operands.
+    ///
+    /// Minima and maxima are not compared, since they can stem from different executions of the
+    /// instruction.
+    pub fn relational_predicates_at_address(
+        address: usize,
+        trace_analyzer: &TraceAnalyzer,
+    ) -> Vec<Predicate> {
+        let mnemonic = trace_analyzer.get_any_mnemonic(address);
+        let registers: Vec<usize> = (0..REGISTERS.len())
+            .into_iter()
+            .filter(|reg_index| {
+                trace_analyzer.any_instruction_at_address_contains_reg(address, *reg_index)
+            })
+            /* skip RSP */
+            .filter(|reg_index| *reg_index != 7)
+            /* skip segment registers and EFLAGS */
+            .filter(|reg_index| !(16..=22).contains(reg_index))
+            .filter(|reg_index| !tested_operands(REGISTERS[*reg_index], &mnemonic).is_empty())
+            .collect();
+
+        let selector = Selector::RegLast;
+        let mut predicates = vec![];
+        for reg_index1 in registers.iter().cloned() {
+            for reg_index2 in registers.iter().cloned() {
+                if reg_index1 == reg_index2 {
+                    continue;
+                }
+
+                let pred_name = format!(
+                    "{} {} {}",
+                    REGISTERS[reg_index1],
+                    selector_reg_less_reg_name(&selector),
+                    REGISTERS[reg_index2]
+                );
+                predicates.push(Predicate::from_closure(&pred_name, address, move |i| {
+                    reg_less_reg(i, &selector, reg_index1, reg_index2)
+                }));
+
+                // equality is symmetric
+                if reg_index1 < reg_index2 {
+                    let pred_name = format!(
+                        "{} {} {}",
+                        REGISTERS[reg_index1],
+                        selector_reg_equal_reg_name(&selector),
+                        REGISTERS[reg_index2]
+                    );
+                    predicates.push(Predicate::from_closure(&pred_name, address, move |i| {
+                        reg_equal_reg(i, &selector, reg_index1, reg_index2)
+                    }));
+                }
+            }
+        }
+
+        predicates
+    }
+
+    pub fn count_predicates_at_address(
+        address: usize,
+        trace_analyzer: &TraceAnalyzer,
+    ) -> Vec<Predicate> {
+        PredicateSynthesizer::synthesize_constant_predicates(
+            address,
+            trace_analyzer,
+            &Selector::InsCount,
+            None,
+        )
+    }
+
+    pub fn edge_count_predicates_at_address(
+        address: usize,
+        trace_analyzer: &TraceAnalyzer,
+    ) -> Vec<Predicate> {
+        if !trace_analyzer.cfg.is_bb_end(address) || trace_analyzer.cfg.is_unconditional(address) {
+            return vec![];
+        }
+
+        trace_analyzer
+            .cfg
+            .get_instruction_successors(address)
+            .into_par_iter()
+            .flat_map(|destination| {
+                PredicateSynthesizer::synthesize_edge_count_predicates(
+                    address,
+                    trace_analyzer,
+                    destination,
+                )
+            })
+            .collect()
+    }
+
+    fn synthesize_edge_count_predicates(
+        address: usize,
+        trace_analyzer: &TraceAnalyzer,
+        destination: usize,
+    ) -> Vec<Predicate> {
+        let mut values: Vec<_> = trace_analyzer
+            .edge_counts_at_address(address, destination)
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if values.is_empty() {
+            return vec![];
+        }
+        values.sort();
+
+        let (v1, v2) = match best_thresholds(&values, trace_analyzer.scoring.as_ref(), |v| {
+            (
+                PredicateSynthesizer::matrix(
+                    trace_analyzer,
+                    &PredicateSynthesizer::edge_count_predicate(address, destination, true, v),
+                ),
+                PredicateSynthesizer::matrix(
+                    trace_analyzer,
+                    &PredicateSynthesizer::edge_count_predicate(address, destination, false, v),
+                ),
+            )
+        }) {
+            Some(thresholds) => thresholds,
+            None => return vec![],
+        };
+
+        vec![
+            PredicateSynthesizer::edge_count_predicate(address, destination, true, v1),
+            PredicateSynthesizer::edge_count_predicate(address, destination, false, v2),
+        ]
+    }
+
+    /// `edge_count >= value` if `greater_or_equal` is set, `edge_count < value` otherwise.
+    fn edge_count_predicate(
+        address: usize,
+        destination: usize,
+        greater_or_equal: bool,
+        value: u64,
+    ) -> Predicate {
+        let predicate = |pred_name: &str, function| {
+            Predicate::new(
+                &format!(
+                    "0x{:x} {} 0x{:x} {}",
+                    address, pred_name, destination, value
+                ),
+                address,
+                function,
+                Some(destination),
+                Some(value as usize),
+            )
+        };
+
+        match greater_or_equal {
+            true => predicate("edge_count_greater_or_equal", edge_count_greater_or_equal),
+            false => predicate("edge_count_less", edge_count_less),
+        }
+        .with_aggregate(move |aggregate| {
+            let counts = match aggregate.edges.get(&destination) {
+                Some(edge) => &edge.counts,
+                None => return LabeledCount::default(),
+            };
+            match greater_or_equal {
+                true => counts.greater_or_equal(value),
+                false => counts.less(value),
+            }
+        })
+    }
+
     fn register_constant_predicates_at_address(
         address: usize,
         trace_analyzer: &TraceAnalyzer,
@@ -63,20 +278,14 @@ impl PredicateSynthesizer {
                 !trace_analyzer
                     .values_at_address(address, selector, Some(*reg_index))
                     .into_iter()
-                    .all(|v: u64| {
-                        trace_analyzer.memory_addresses.heap_start <= v as usize
-                            && v as usize <= trace_analyzer.memory_addresses.heap_end
-                    })
+                    .all(|v: u64| trace_analyzer.memory_map.classify(v) == MemoryRegion::Heap)
             })
             /* skip all stack addresses */
             .filter(|reg_index| {
                 !trace_analyzer
                     .values_at_address(address, selector, Some(*reg_index))
                     .into_iter()
-                    .all(|v: u64| {
-                        trace_analyzer.memory_addresses.stack_start <= v as usize
-                            && v as usize <= trace_analyzer.memory_addresses.stack_end
-                    })
+                    .all(|v: u64| trace_analyzer.memory_map.classify(v) == MemoryRegion::Stack)
             })
             .flat_map(|reg_index| {
                 PredicateSynthesizer::synthesize_constant_predicates(
@@ -100,130 +309,167 @@ impl PredicateSynthesizer {
             return vec![];
         }
 
-        let mut f: Vec<_> = values
-            .par_iter()
-            .map(|v| {
-                (
-                    v,
-                    PredicateSynthesizer::evaluate_value_at_address(
-                        address,
-                        trace_analyzer,
-                        selector,
-                        reg_index,
-                        *v,
+        match best_thresholds(&values, trace_analyzer.scoring.as_ref(), |v| {
+            (
+                PredicateSynthesizer::matrix(
+                    trace_analyzer,
+                    &PredicateSynthesizer::constant_predicate(
+                        address, selector, reg_index, true, v,
                     ),
-                )
-            })
-            .collect();
-
-        f.sort_by(|(_, f1), (_, f2)| f1.partial_cmp(&f2).unwrap());
-
-        PredicateSynthesizer::build_constant_predicates(
-            address,
-            selector,
-            reg_index,
-            PredicateSynthesizer::arithmetic_mean(*f.first().unwrap().0, &values),
-            PredicateSynthesizer::arithmetic_mean(*f.last().unwrap().0, &values),
-        )
+                ),
+                PredicateSynthesizer::matrix(
+                    trace_analyzer,
+                    &PredicateSynthesizer::constant_predicate(
+                        address, selector, reg_index, false, v,
+                    ),
+                ),
+            )
+        }) {
+            Some((v1, v2)) => vec![
+                PredicateSynthesizer::constant_predicate(address, selector, reg_index, true, v1),
+                PredicateSynthesizer::constant_predicate(address, selector, reg_index, false, v2),
+            ],
+            None => vec![],
+        }
     }
 
-    fn arithmetic_mean(v1: u64, values: &Vec<u64>) -> u64 {
+    pub fn arithmetic_mean(v1: u64, values: &Vec<u64>) -> u64 {
         match values.iter().filter(|v| *v < &v1).max() {
             Some(v2) => ((v1 as f64 + *v2 as f64) / 2.0).round() as u64,
             None => v1,
         }
     }
 
-    fn build_constant_predicates(
+    /// `value >= threshold` if `greater_or_equal` is set, `value < threshold` otherwise.
+    fn constant_predicate(
         address: usize,
         selector: &Selector,
         reg_index: Option<usize>,
-        v1: u64,
-        v2: u64,
-    ) -> Vec<Predicate> {
-        let pred_name1 =
-            gen_reg_val_name(reg_index, selector_val_greater_or_equal_name(selector), v1);
-        let pred_name2 = gen_reg_val_name(reg_index, selector_val_less_name(selector), v2);
-
-        vec![
-            Predicate::new(
-                &pred_name1,
-                address,
-                selector_val_greater_or_equal(selector),
-                reg_index,
-                Some(v1 as usize),
+        greater_or_equal: bool,
+        threshold: u64,
+    ) -> Predicate {
+        let selector = *selector;
+        let (pred_name, function) = match greater_or_equal {
+            true => (
+                selector_val_greater_or_equal_name(&selector),
+                selector_val_greater_or_equal(&selector),
             ),
-            Predicate::new(
-                &pred_name2,
-                address,
-                selector_val_less(selector),
-                reg_index,
-                Some(v2 as usize),
+            false => (
+                selector_val_less_name(&selector),
+                selector_val_less(&selector),
             ),
-        ]
-    }
-
-    fn evaluate_value_at_address(
-        address: usize,
-        trace_analyzer: &TraceAnalyzer,
-        selector: &Selector,
-        reg_index: Option<usize>,
-        val: u64,
-    ) -> f64 {
-        let pred_name = format!(
-            "{:?} {} {}",
-            reg_index,
-            selector_val_less_name(selector),
-            val
-        );
+        };
 
-        let predicate = Predicate::new(
-            &pred_name,
+        Predicate::new(
+            &gen_reg_val_name(reg_index, pred_name, threshold),
             address,
-            selector_val_less(selector),
+            function,
             reg_index,
-            Some(val as usize),
+            Some(threshold as usize),
+        )
+        .with_aggregate(move |aggregate| {
+            let values = match aggregate.values(&selector, reg_index) {
+                Some(values) => values,
+                None => return LabeledCount::default(),
+            };
+            match greater_or_equal {
+                true => values.greater_or_equal(threshold),
+                false => values.less(threshold),
+            }
+        })
+    }
+
+    /// Confusion matrix of a synthesized predicate, which can always be evaluated on aggregates.
+    fn matrix(trace_analyzer: &TraceAnalyzer, predicate: &Predicate) -> ConfusionMatrix {
+        PredicateAnalyzer::matrix(trace_analyzer, predicate)
+            .expect("synthesized predicates count aggregates")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scoring::{scoring_function, SCORING_FUNCTIONS};
+
+    /// Splits crashes with the values 5 and 6 from non-crashes with the values 1 to 4.
+    fn thresholds(scoring: &str) -> Option<(u64, u64)> {
+        let crashes = [5, 6];
+        let non_crashes = [1, 2, 3, 4];
+        let values: Vec<u64> = (1..=6).collect();
+        let matrix = |satisfies: &dyn Fn(u64) -> bool| {
+            ConfusionMatrix::new(
+                crashes.iter().filter(|v| satisfies(**v)).count(),
+                non_crashes.iter().filter(|v| !satisfies(**v)).count(),
+                crashes.len(),
+                non_crashes.len(),
+            )
+        };
+
+        best_thresholds(&values, scoring_function(scoring).unwrap().as_ref(), |t| {
+            (matrix(&|v| v >= t), matrix(&|v| v < t))
+        })
+    }
+
+    #[test]
+    fn test_best_thresholds_direction() {
+        for scoring in SCORING_FUNCTIONS.iter() {
+            let (greater_or_equal, _) = thresholds(scoring).unwrap();
+            assert_eq!(greater_or_equal, 5, "{}", scoring);
+        }
+    }
+
+    #[test]
+    fn test_best_thresholds_less() {
+        // no `<` split separates the crashes; the best one includes the fewest non-crashes
+        assert_eq!(thresholds("f1"), Some((5, 6)));
+        assert_eq!(thresholds("balanced-accuracy").unwrap().0, 5);
+    }
+
+    #[test]
+    fn test_best_thresholds_empty() {
+        let scoring = scoring_function("balanced-accuracy").unwrap();
+        assert_eq!(
+            best_thresholds(&vec![], scoring.as_ref(), |_| {
+                (ConfusionMatrix::default(), ConfusionMatrix::default())
+            }),
+            None
         );
+    }
 
-        PredicateSynthesizer::evaluate_predicate_with_reachability(
-            address,
-            trace_analyzer,
-            &predicate,
-        )
+    /// Balanced accuracy, but NaN for predicates satisfied by all non-crashes.
+    struct NanScoring;
+
+    impl ScoringFunction for NanScoring {
+        fn name(&self) -> &'static str {
+            "nan"
+        }
+
+        fn score(&self, matrix: &ConfusionMatrix) -> f64 {
+            match matrix.true_negatives {
+                0 => f64::NAN,
+                _ => scoring_function("balanced-accuracy").unwrap().score(matrix),
+            }
+        }
     }
 
-    pub fn evaluate_predicate_with_reachability(
-        address: usize,
-        trace_analyzer: &TraceAnalyzer,
-        predicate: &Predicate,
-    ) -> f64 {
-        let true_positives = trace_analyzer
-            .crashes
-            .as_slice()
-            .par_iter()
-            .filter(|t| t.instructions.get(&address).is_some())
-            .map(|t| t.instructions.get(&address))
-            .filter(|i| predicate.execute(i))
-            .count() as f64
-            / trace_analyzer.crashes.len() as f64;
-        let true_negatives = (trace_analyzer
-            .non_crashes
-            .as_slice()
-            .par_iter()
-            .filter(|t| t.instructions.get(&address).is_some())
-            .map(|t| t.instructions.get(&address))
-            .filter(|i| !predicate.execute(i))
-            .count() as f64
-            + trace_analyzer
-                .non_crashes
-                .as_slice()
-                .par_iter()
-                .filter(|t| t.instructions.get(&address).is_none())
-                .count() as f64)
-            / trace_analyzer.non_crashes.len() as f64;
-
-        let score = (true_positives + true_negatives) / 2.0;
-
-        score
+    #[test]
+    fn test_best_thresholds_nan() {
+        let crashes = [5, 6];
+        let values: Vec<u64> = (1..=6).collect();
+        let matrix = |satisfies: &dyn Fn(u64) -> bool| {
+            ConfusionMatrix::new(
+                crashes.iter().filter(|v| satisfies(**v)).count(),
+                (1..=4).filter(|v| !satisfies(*v)).count(),
+                2,
+                4,
+            )
+        };
+
+        // the first candidate `>= 1` scores NaN and must not block better ones
+        let (greater_or_equal, _) = best_thresholds(&values, &NanScoring, |t| {
+            (matrix(&|v| v >= t), matrix(&|v| v < t))
+        })
+        .unwrap();
+        assert_eq!(greater_or_equal, 5);
     }
 }
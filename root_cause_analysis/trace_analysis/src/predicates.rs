@@ -1,11 +1,24 @@
+use crate::memory_regions::MemoryRegion;
+use crate::scoring::{cmp_scores, ConfusionMatrix, PredicateStatistics, ScoringFunction};
+use crate::symbolization::SourceLocation;
 use crate::trace::{Instruction, Register, Selector};
+use crate::trace_aggregates::{AddressAggregate, LabeledCount};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SerializedPredicate {
     pub name: String,
     pub score: f64,
     pub address: usize,
+    #[serde(default)]
+    pub statistics: Option<PredicateStatistics>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SourceLocation>,
+    /// addresses of equivalent predicates collapsed into this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_addresses: Vec<usize>,
 }
 
 impl SerializedPredicate {
@@ -14,6 +27,17 @@ impl SerializedPredicate {
             name,
             score,
             address,
+            statistics: None,
+            location: None,
+            merged_addresses: vec![],
+        }
+    }
+
+    /// Lower confidence bound of the score, or the score itself without statistics.
+    pub fn lower_bound(&self) -> f64 {
+        match &self.statistics {
+            Some(statistics) => statistics.lower_bound(),
+            None => self.score,
         }
     }
 
@@ -29,14 +53,17 @@ impl SerializedPredicate {
     }
 }
 
+type AggregateFunction = Arc<dyn Fn(&AddressAggregate) -> LabeledCount + Send + Sync>;
+
 #[derive(Clone)]
 pub struct Predicate {
     pub name: String,
-    p1: Option<usize>,
-    p2: Option<usize>,
-    function: fn(&Instruction, Option<usize>, Option<usize>) -> bool,
+    function: Arc<dyn Fn(&Instruction) -> bool + Send + Sync>,
+    /// counts the traces satisfying the predicate on the aggregates of its address, if possible
+    aggregate_function: Option<AggregateFunction>,
     pub score: f64,
     pub address: usize,
+    pub matrix: ConfusionMatrix,
 }
 
 impl Predicate {
@@ -46,37 +73,136 @@ impl Predicate {
         function: fn(&Instruction, Option<usize>, Option<usize>) -> bool,
         p1: Option<usize>,
         p2: Option<usize>,
+    ) -> Predicate {
+        Predicate::from_closure(name, address, move |instruction| {
+            function(instruction, p1, p2)
+        })
+    }
+
+    pub fn from_closure(
+        name: &str,
+        address: usize,
+        function: impl Fn(&Instruction) -> bool + Send + Sync + 'static,
     ) -> Predicate {
         Predicate {
             name: name.to_string(),
             address,
-            p1,
-            p2,
-            function,
+            function: Arc::new(function),
+            aggregate_function: None,
             score: 0.0,
+            matrix: ConfusionMatrix::default(),
         }
     }
 
+    /// Lets the predicate be evaluated on streamed traces, which only keep per-address aggregates.
+    pub fn with_aggregate(
+        mut self,
+        aggregate_function: impl Fn(&AddressAggregate) -> LabeledCount + Send + Sync + 'static,
+    ) -> Predicate {
+        self.aggregate_function = Some(Arc::new(aggregate_function));
+        self
+    }
+
+    /// Number of crashes and non-crashes satisfying the predicate according to the aggregates;
+    /// `None` if the predicate depends on values the aggregates do not keep.
+    pub fn count_aggregate(&self, aggregate: &AddressAggregate) -> Option<LabeledCount> {
+        self.aggregate_function
+            .as_ref()
+            .map(|aggregate_function| aggregate_function(aggregate))
+    }
+
     pub fn serialize(&self) -> String {
         let serialized = SerializedPredicate::new(self.name.to_string(), self.address, self.score);
         serde_json::to_string(&serialized).unwrap()
     }
 
-    pub fn to_serialzed(&self) -> SerializedPredicate {
-        SerializedPredicate::new(self.name.to_string(), self.address, self.score)
+    pub fn to_serialzed(
+        &self,
+        scoring: &dyn ScoringFunction,
+        ln_factorials: &[f64],
+    ) -> SerializedPredicate {
+        let mut serialized =
+            SerializedPredicate::new(self.name.to_string(), self.address, self.score);
+        serialized.statistics = Some(PredicateStatistics::new(
+            &self.matrix,
+            scoring,
+            ln_factorials,
+        ));
+
+        serialized
     }
 
     pub fn execute(&self, instruction_option: &Option<&Instruction>) -> bool {
         match instruction_option {
-            Some(instruction) => (self.function)(instruction, self.p1, self.p2),
+            Some(instruction) => (self.function)(instruction),
             None => false,
         }
     }
 
+    pub fn and(&self, other: &Predicate) -> Predicate {
+        let (p1, p2) = (self.clone(), other.clone());
+        Predicate::from_closure(
+            &format!("{} && {}", self.name, other.name),
+            self.address,
+            move |instruction| p1.execute(&Some(instruction)) && p2.execute(&Some(instruction)),
+        )
+    }
+
+    pub fn or(&self, other: &Predicate) -> Predicate {
+        let (p1, p2) = (self.clone(), other.clone());
+        Predicate::from_closure(
+            &format!("{} || {}", self.name, other.name),
+            self.address,
+            move |instruction| p1.execute(&Some(instruction)) || p2.execute(&Some(instruction)),
+        )
+    }
+
+    /// Whether the predicate monitor can combine the two atomic predicates.
+    ///
+    /// The monitor only tracks a single hit counter per candidate, and it decides deferred
+    /// predicates (`last_*` and the `*_count_less` ones) on the last execution, the others on any
+    /// execution.
+    pub fn can_combine(&self, other: &Predicate) -> bool {
+        let (f1, f2) = (self.function_name(), other.function_name());
+        let is_count = |f: &str| f.contains("ins_count") || f.contains("edge_count");
+        let is_deferred =
+            |f: &str| f.starts_with("last_") || f == "ins_count_less" || f == "edge_count_less";
+
+        !(is_count(f1) && is_count(f2)) && is_deferred(f1) == is_deferred(f2)
+    }
+
+    /// Function of an atomic predicate, e.g. `min_reg_val_less` for `rax min_reg_val_less 0x10`.
+    fn function_name(&self) -> &str {
+        let parts: Vec<&str> = self.name.split(' ').collect();
+        match parts.len() {
+            1 | 2 => parts[0],
+            _ => parts[1],
+        }
+    }
+
     pub fn gen_empty(address: usize) -> Predicate {
         Predicate::new("empty", address, empty, None, None)
     }
 
+    /// Orders predicates best first: by score, then atomic before combined, then by name.
+    pub fn rank(&self, other: &Predicate) -> Ordering {
+        cmp_scores(other.score, self.score)
+            .then_with(|| self.num_operands().cmp(&other.num_operands()))
+            .then_with(|| self.name.cmp(&other.name))
+    }
+
+    fn num_operands(&self) -> usize {
+        1 + self.name.matches(" && ").count() + self.name.matches(" || ").count()
+    }
+
+    /// Keeps the `k` best predicates, ranked by `Predicate::rank`.
+    pub fn top_k(mut predicates: Vec<Predicate>, k: usize) -> Vec<Predicate> {
+        predicates.sort_by(Predicate::rank);
+        predicates.truncate(k.max(1));
+
+        predicates
+    }
+
     pub fn to_string(&self) -> String {
         format!("{}", self.name)
     }
@@ -94,9 +220,9 @@ pub fn selector_val_less_name(selector: &Selector) -> String {
     match selector {
         Selector::RegMin => format!("min_reg_val_less"),
         Selector::RegMax => format!("max_reg_val_less"),
+        Selector::RegLast => format!("last_reg_val_less"),
         Selector::RegMaxMinDiff => format!("max_min_diff_reg_val_less"),
         Selector::InsCount => format!("ins_count_less"),
-        _ => unreachable!(),
     }
 }
 
@@ -106,9 +232,9 @@ pub fn selector_val_less(
     match selector {
         Selector::RegMin => min_reg_val_less,
         Selector::RegMax => max_reg_val_less,
+        Selector::RegLast => last_reg_val_less,
         Selector::RegMaxMinDiff => max_min_diff_reg_val_less,
-        //        Selector::InsCount => ins_count_less,
-        _ => unreachable!(),
+        Selector::InsCount => ins_count_less,
     }
 }
 
@@ -134,6 +260,17 @@ pub fn max_reg_val_less(
     }
 }
 
+pub fn last_reg_val_less(
+    instruction: &Instruction,
+    reg_index: Option<usize>,
+    value: Option<usize>,
+) -> bool {
+    match instruction.registers_last.get(reg_index.unwrap()) {
+        Some(reg) => reg.value() < value.unwrap() as u64,
+        None => false,
+    }
+}
+
 pub fn max_min_diff_reg_val_less(
     instruction: &Instruction,
     reg_index: Option<usize>,
@@ -148,13 +285,17 @@ pub fn max_min_diff_reg_val_less(
     }
 }
 
+pub fn ins_count_less(instruction: &Instruction, _: Option<usize>, value: Option<usize>) -> bool {
+    instruction.count < value.unwrap()
+}
+
 pub fn selector_val_greater_or_equal_name(selector: &Selector) -> String {
     match selector {
         Selector::RegMin => format!("min_reg_val_greater_or_equal"),
         Selector::RegMax => format!("max_reg_val_greater_or_equal"),
+        Selector::RegLast => format!("last_reg_val_greater_or_equal"),
         Selector::RegMaxMinDiff => format!("max_min_diff_reg_val_greater_or_equal"),
         Selector::InsCount => format!("ins_count_greater_or_equal"),
-        _ => unreachable!(),
     }
 }
 
@@ -164,9 +305,9 @@ pub fn selector_val_greater_or_equal(
     match selector {
         Selector::RegMin => min_reg_val_greater_or_equal,
         Selector::RegMax => max_reg_val_greater_or_equal,
+        Selector::RegLast => last_reg_val_greater_or_equal,
         Selector::RegMaxMinDiff => max_min_diff_reg_val_greater_or_equal,
-        //        Selector::InsCount => ins_count_greater_or_equal,
-        _ => unreachable!(),
+        Selector::InsCount => ins_count_greater_or_equal,
     }
 }
 
@@ -192,6 +333,17 @@ pub fn max_reg_val_greater_or_equal(
     }
 }
 
+pub fn last_reg_val_greater_or_equal(
+    instruction: &Instruction,
+    reg_index: Option<usize>,
+    value: Option<usize>,
+) -> bool {
+    match instruction.registers_last.get(reg_index.unwrap()) {
+        Some(reg) => reg.value() >= value.unwrap() as u64,
+        None => false,
+    }
+}
+
 pub fn max_min_diff_reg_val_greater_or_equal(
     instruction: &Instruction,
     reg_index: Option<usize>,
@@ -208,11 +360,81 @@ pub fn max_min_diff_reg_val_greater_or_equal(
     }
 }
 
-fn is_flag_bit_set(instruction: &Instruction, reg_type: Selector, pos: u64) -> bool {
+pub fn ins_count_greater_or_equal(
+    instruction: &Instruction,
+    _: Option<usize>,
+    value: Option<usize>,
+) -> bool {
+    instruction.count >= value.unwrap()
+}
+
+pub fn selector_val_in_region_name(selector: &Selector, region: MemoryRegion) -> String {
+    match selector {
+        Selector::RegMin => format!("min_reg_val_in_{}", region),
+        Selector::RegMax => format!("max_reg_val_in_{}", region),
+        Selector::RegLast => format!("last_reg_val_in_{}", region),
+        _ => unreachable!(),
+    }
+}
+
+pub fn reg_val_in_range(
+    instruction: &Instruction,
+    selector: &Selector,
+    reg_index: usize,
+    start: usize,
+    end: usize,
+) -> bool {
+    match instruction.registers(selector).get(reg_index) {
+        Some(reg) => start as u64 <= reg.value() && reg.value() < end as u64,
+        None => false,
+    }
+}
+
+pub fn selector_reg_less_reg_name(selector: &Selector) -> String {
+    match selector {
+        Selector::RegLast => format!("last_reg_less_reg"),
+        _ => unreachable!(),
+    }
+}
+
+pub fn selector_reg_equal_reg_name(selector: &Selector) -> String {
+    match selector {
+        Selector::RegLast => format!("last_reg_equal_reg"),
+        _ => unreachable!(),
+    }
+}
+
+pub fn reg_less_reg(
+    instruction: &Instruction,
+    selector: &Selector,
+    reg_index1: usize,
+    reg_index2: usize,
+) -> bool {
+    let registers = instruction.registers(selector);
+    match (registers.get(reg_index1), registers.get(reg_index2)) {
+        (Some(reg1), Some(reg2)) => reg1.value() < reg2.value(),
+        _ => false,
+    }
+}
+
+pub fn reg_equal_reg(
+    instruction: &Instruction,
+    selector: &Selector,
+    reg_index1: usize,
+    reg_index2: usize,
+) -> bool {
+    let registers = instruction.registers(selector);
+    match (registers.get(reg_index1), registers.get(reg_index2)) {
+        (Some(reg1), Some(reg2)) => reg1.value() == reg2.value(),
+        _ => false,
+    }
+}
+
+pub fn is_flag_bit_set(instruction: &Instruction, reg_type: Selector, pos: u64) -> bool {
     match reg_type {
         Selector::RegMin => is_reg_bit_set(instruction.registers_min.get(22), pos),
         Selector::RegMax => is_reg_bit_set(instruction.registers_max.get(22), pos),
-        //        Selector::RegLast => is_reg_bit_set(instruction.registers_last.get(22), pos),
+        Selector::RegLast => is_reg_bit_set(instruction.registers_last.get(22), pos),
         _ => unreachable!(),
     }
 }
@@ -323,6 +545,54 @@ pub fn max_overflow_flag_set(
     is_flag_bit_set(instruction, Selector::RegMax, 11)
 }
 
+pub fn last_carry_flag_set(instruction: &Instruction, _: Option<usize>, _: Option<usize>) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 0)
+}
+
+pub fn last_parity_flag_set(instruction: &Instruction, _: Option<usize>, _: Option<usize>) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 2)
+}
+
+pub fn last_adjust_flag_set(instruction: &Instruction, _: Option<usize>, _: Option<usize>) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 4)
+}
+
+pub fn last_zero_flag_set(instruction: &Instruction, _: Option<usize>, _: Option<usize>) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 6)
+}
+
+pub fn last_sign_flag_set(instruction: &Instruction, _: Option<usize>, _: Option<usize>) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 7)
+}
+
+pub fn last_trap_flag_set(instruction: &Instruction, _: Option<usize>, _: Option<usize>) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 8)
+}
+
+pub fn last_interrupt_flag_set(
+    instruction: &Instruction,
+    _: Option<usize>,
+    _: Option<usize>,
+) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 9)
+}
+
+pub fn last_direction_flag_set(
+    instruction: &Instruction,
+    _: Option<usize>,
+    _: Option<usize>,
+) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 10)
+}
+
+pub fn last_overflow_flag_set(
+    instruction: &Instruction,
+    _: Option<usize>,
+    _: Option<usize>,
+) -> bool {
+    is_flag_bit_set(instruction, Selector::RegLast, 11)
+}
+
 pub fn num_successors_greater(
     instruction: &Instruction,
     n: Option<usize>,
@@ -353,3 +623,30 @@ pub fn edge_only_taken_to(
         .any(|s| s.address == address.unwrap())
         && instruction.successors.len() == 1
 }
+
+pub fn last_edge_to(instruction: &Instruction, address: Option<usize>, _: Option<usize>) -> bool {
+    instruction.last_successor == address.unwrap()
+}
+
+pub fn edge_count(instruction: &Instruction, address: usize) -> usize {
+    match instruction.successors.iter().find(|s| s.address == address) {
+        Some(successor) => successor.count,
+        None => 0,
+    }
+}
+
+pub fn edge_count_less(
+    instruction: &Instruction,
+    address: Option<usize>,
+    value: Option<usize>,
+) -> bool {
+    edge_count(instruction, address.unwrap()) < value.unwrap()
+}
+
+pub fn edge_count_greater_or_equal(
+    instruction: &Instruction,
+    address: Option<usize>,
+    value: Option<usize>,
+) -> bool {
+    edge_count(instruction, address.unwrap()) >= value.unwrap()
+}
@@ -0,0 +1,98 @@
+use crate::error::TraceError;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// Traces selected for an analysis run, together with the seed they were drawn with.
+#[derive(Serialize, Deserialize)]
+pub struct TraceSample {
+    pub seed: Option<u64>,
+    pub crashes: Vec<String>,
+    pub non_crashes: Vec<String>,
+}
+
+impl TraceSample {
+    /// Draws `num_crashes` crashes and `num_non_crashes` non-crashes before any trace is parsed.
+    ///
+    /// If there are not enough traces of one kind, both sizes are scaled down to keep the ratio.
+    pub fn draw(
+        mut crashes: Vec<String>,
+        mut non_crashes: Vec<String>,
+        num_crashes: usize,
+        num_non_crashes: usize,
+        seed: u64,
+    ) -> TraceSample {
+        let scale = [
+            (crashes.len(), num_crashes),
+            (non_crashes.len(), num_non_crashes),
+        ]
+        .iter()
+        .filter(|(_, wanted)| *wanted > 0)
+        .map(|(available, wanted)| *available as f64 / *wanted as f64)
+        .fold(1.0, f64::min);
+
+        // sort first, so the sample does not depend on the order of the directory listing
+        crashes.sort();
+        non_crashes.sort();
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        crashes.shuffle(&mut rng);
+        non_crashes.shuffle(&mut rng);
+        crashes.truncate((num_crashes as f64 * scale).round() as usize);
+        non_crashes.truncate((num_non_crashes as f64 * scale).round() as usize);
+
+        TraceSample {
+            seed: Some(seed),
+            crashes,
+            non_crashes,
+        }
+    }
+
+    pub fn read_from_file(file_path: &str) -> Result<TraceSample, TraceError> {
+        let content = fs::read_to_string(file_path).map_err(|source| TraceError::Io {
+            path: file_path.to_string(),
+            source,
+        })?;
+        serde_json::from_str(&content).map_err(|source| TraceError::Json {
+            path: file_path.to_string(),
+            source,
+        })
+    }
+
+    pub fn write_to_file(&self, file_path: &str) {
+        let content = serde_json::to_string_pretty(self).expect("Could not serialize trace sample");
+        fs::write(file_path, content).expect(&format!("Could not write file {}", file_path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(prefix: &str, n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("{}{}", prefix, i)).collect()
+    }
+
+    #[test]
+    fn test_draw_seeded() {
+        let sample = TraceSample::draw(paths("c", 10), paths("n", 10), 3, 4, 42);
+        assert_eq!(sample.seed, Some(42));
+        assert_eq!((sample.crashes.len(), sample.non_crashes.len()), (3, 4));
+
+        // the order of the directory listing does not matter
+        let mut crashes = paths("c", 10);
+        crashes.reverse();
+        let again = TraceSample::draw(crashes, paths("n", 10), 3, 4, 42);
+        assert_eq!(again.crashes, sample.crashes);
+        assert_eq!(again.non_crashes, sample.non_crashes);
+    }
+
+    #[test]
+    fn test_draw_scaled() {
+        // only 2 of 4 crashes available, so the non-crashes are halved as well
+        let sample = TraceSample::draw(paths("c", 2), paths("n", 10), 4, 8, 1);
+        assert_eq!((sample.crashes.len(), sample.non_crashes.len()), (2, 4));
+    }
+}
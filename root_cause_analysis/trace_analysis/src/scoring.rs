@@ -0,0 +1,392 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Names of the scoring functions selectable via `--scoring`.
+pub const SCORING_FUNCTIONS: &[&str] = &[
+    "balanced-accuracy",
+    "f1",
+    "mcc",
+    "information-gain",
+    "ochiai",
+    "tarantula",
+];
+
+/// Outcome of a predicate on all traces; a crash satisfying the predicate is a true positive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ConfusionMatrix {
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub true_negatives: usize,
+    pub false_negatives: usize,
+}
+
+impl ConfusionMatrix {
+    pub fn new(
+        true_positives: usize,
+        true_negatives: usize,
+        num_crashes: usize,
+        num_non_crashes: usize,
+    ) -> ConfusionMatrix {
+        ConfusionMatrix {
+            true_positives,
+            false_positives: num_non_crashes - true_negatives,
+            true_negatives,
+            false_negatives: num_crashes - true_positives,
+        }
+    }
+
+    pub fn crashes(&self) -> f64 {
+        (self.true_positives + self.false_negatives) as f64
+    }
+
+    pub fn non_crashes(&self) -> f64 {
+        (self.true_negatives + self.false_positives) as f64
+    }
+
+    pub fn total(&self) -> f64 {
+        self.crashes() + self.non_crashes()
+    }
+}
+
+pub trait ScoringFunction: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn score(&self, matrix: &ConfusionMatrix) -> f64;
+
+    /// Best score a predicate can reach on the given number of crashes and non-crashes.
+    fn max_score(&self, _num_crashes: usize, _num_non_crashes: usize) -> f64 {
+        1.0
+    }
+}
+
+/// Scoring function named like in `SCORING_FUNCTIONS`; `None` for unknown names.
+pub fn scoring_function(name: &str) -> Option<Box<dyn ScoringFunction>> {
+    let ret: Box<dyn ScoringFunction> = match name {
+        "balanced-accuracy" => Box::new(BalancedAccuracy),
+        "f1" => Box::new(F1Score),
+        "mcc" => Box::new(MatthewsCorrelation),
+        "information-gain" => Box::new(InformationGain),
+        "ochiai" => Box::new(Ochiai),
+        "tarantula" => Box::new(Tarantula),
+        _ => return None,
+    };
+
+    Some(ret)
+}
+
+/// Orders scores ascending; NaN scores are lower than any other score instead of panicking.
+pub fn cmp_scores(s1: f64, s2: f64) -> Ordering {
+    let key = |score: f64| match score.is_nan() {
+        true => f64::NEG_INFINITY,
+        false => score,
+    };
+
+    key(s1).total_cmp(&key(s2))
+}
+
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    match denominator > 0.0 {
+        true => numerator / denominator,
+        false => 0.0,
+    }
+}
+
+pub struct BalancedAccuracy;
+
+impl ScoringFunction for BalancedAccuracy {
+    fn name(&self) -> &'static str {
+        "balanced-accuracy"
+    }
+
+    fn score(&self, matrix: &ConfusionMatrix) -> f64 {
+        (ratio(matrix.true_positives as f64, matrix.crashes())
+            + ratio(matrix.true_negatives as f64, matrix.non_crashes()))
+            / 2.0
+    }
+}
+
+pub struct F1Score;
+
+impl ScoringFunction for F1Score {
+    fn name(&self) -> &'static str {
+        "f1"
+    }
+
+    fn score(&self, matrix: &ConfusionMatrix) -> f64 {
+        let true_positives = matrix.true_positives as f64;
+        ratio(
+            2.0 * true_positives,
+            2.0 * true_positives + (matrix.false_positives + matrix.false_negatives) as f64,
+        )
+    }
+}
+
+pub struct MatthewsCorrelation;
+
+impl ScoringFunction for MatthewsCorrelation {
+    fn name(&self) -> &'static str {
+        "mcc"
+    }
+
+    fn score(&self, matrix: &ConfusionMatrix) -> f64 {
+        let tp = matrix.true_positives as f64;
+        let fp = matrix.false_positives as f64;
+        let tn = matrix.true_negatives as f64;
+        let fn_ = matrix.false_negatives as f64;
+
+        ratio(
+            tp * tn - fp * fn_,
+            ((tp + fp) * (tp + fn_) * (tn + fp) * (tn + fn_)).sqrt(),
+        )
+    }
+}
+
+/// Reduction of the crash/non-crash entropy (in bits) by knowing the predicate's outcome.
+///
+/// Predicates satisfied more often by non-crashes than by crashes score 0, since information gain
+/// alone does not tell which outcome is related to crashing.
+pub struct InformationGain;
+
+fn entropy(counts: &[f64]) -> f64 {
+    let total: f64 = counts.iter().sum();
+    counts
+        .iter()
+        .map(|count| ratio(*count, total))
+        .filter(|p| *p > 0.0)
+        .map(|p| -p * p.log2())
+        .sum()
+}
+
+impl ScoringFunction for InformationGain {
+    fn name(&self) -> &'static str {
+        "information-gain"
+    }
+
+    fn score(&self, matrix: &ConfusionMatrix) -> f64 {
+        let tp = matrix.true_positives as f64;
+        let fp = matrix.false_positives as f64;
+        let tn = matrix.true_negatives as f64;
+        let fn_ = matrix.false_negatives as f64;
+
+        if ratio(tp, matrix.crashes()) < ratio(fp, matrix.non_crashes()) {
+            return 0.0;
+        }
+
+        let satisfied = tp + fp;
+        let not_satisfied = tn + fn_;
+        let conditional_entropy = ratio(satisfied, matrix.total()) * entropy(&[tp, fp])
+            + ratio(not_satisfied, matrix.total()) * entropy(&[fn_, tn]);
+
+        entropy(&[matrix.crashes(), matrix.non_crashes()]) - conditional_entropy
+    }
+
+    /// Entropy of crashing, reached by a predicate satisfied by exactly the crashes.
+    fn max_score(&self, num_crashes: usize, num_non_crashes: usize) -> f64 {
+        entropy(&[num_crashes as f64, num_non_crashes as f64])
+    }
+}
+
+pub struct Ochiai;
+
+impl ScoringFunction for Ochiai {
+    fn name(&self) -> &'static str {
+        "ochiai"
+    }
+
+    fn score(&self, matrix: &ConfusionMatrix) -> f64 {
+        let tp = matrix.true_positives as f64;
+        ratio(
+            tp,
+            (matrix.crashes() * (tp + matrix.false_positives as f64)).sqrt(),
+        )
+    }
+}
+
+pub struct Tarantula;
+
+impl ScoringFunction for Tarantula {
+    fn name(&self) -> &'static str {
+        "tarantula"
+    }
+
+    fn score(&self, matrix: &ConfusionMatrix) -> f64 {
+        let crash_ratio = ratio(matrix.true_positives as f64, matrix.crashes());
+        let non_crash_ratio = ratio(matrix.false_positives as f64, matrix.non_crashes());
+        ratio(crash_ratio, crash_ratio + non_crash_ratio)
+    }
+}
+
+/// z-score of the two-sided 95% confidence level.
+const CONFIDENCE_Z: f64 = 1.96;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PredicateStatistics {
+    pub counts: ConfusionMatrix,
+    /// 95% confidence interval of the score
+    pub confidence_interval: (f64, f64),
+    /// one-sided p-value of Fisher's exact test that crashes satisfy the predicate more often
+    pub p_value: f64,
+}
+
+impl PredicateStatistics {
+    /// `ln_factorials` has to cover the number of traces, see `ln_factorials`.
+    pub fn new(
+        matrix: &ConfusionMatrix,
+        scoring: &dyn ScoringFunction,
+        ln_factorials: &[f64],
+    ) -> PredicateStatistics {
+        PredicateStatistics {
+            counts: *matrix,
+            confidence_interval: confidence_interval(matrix, scoring),
+            p_value: fisher_exact_test(matrix, ln_factorials),
+        }
+    }
+
+    pub fn lower_bound(&self) -> f64 {
+        self.confidence_interval.0
+    }
+}
+
+/// Wilson score interval of `successes` out of `n` trials.
+fn wilson_interval(successes: usize, n: usize) -> (f64, f64) {
+    if n == 0 {
+        return (0.0, 1.0);
+    }
+
+    let n = n as f64;
+    let p = successes as f64 / n;
+    let z2 = CONFIDENCE_Z * CONFIDENCE_Z;
+
+    let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half_width =
+        CONFIDENCE_Z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+
+    (
+        (center - half_width).max(0.0),
+        (center + half_width).min(1.0),
+    )
+}
+
+/// Propagates the Wilson intervals of the true positive and true negative rates through the scoring function.
+fn confidence_interval(matrix: &ConfusionMatrix, scoring: &dyn ScoringFunction) -> (f64, f64) {
+    let num_crashes = matrix.true_positives + matrix.false_negatives;
+    let num_non_crashes = matrix.true_negatives + matrix.false_positives;
+    let (tpr_low, tpr_high) = wilson_interval(matrix.true_positives, num_crashes);
+    let (tnr_low, tnr_high) = wilson_interval(matrix.true_negatives, num_non_crashes);
+
+    let pessimistic = ConfusionMatrix::new(
+        (tpr_low * num_crashes as f64).floor() as usize,
+        (tnr_low * num_non_crashes as f64).floor() as usize,
+        num_crashes,
+        num_non_crashes,
+    );
+    let optimistic = ConfusionMatrix::new(
+        ((tpr_high * num_crashes as f64).ceil() as usize).min(num_crashes),
+        ((tnr_high * num_non_crashes as f64).ceil() as usize).min(num_non_crashes),
+        num_crashes,
+        num_non_crashes,
+    );
+
+    // not every scoring function is monotonic in both rates
+    let scores = [
+        scoring.score(&pessimistic),
+        scoring.score(matrix),
+        scoring.score(&optimistic),
+    ];
+
+    (
+        scores.iter().cloned().fold(f64::INFINITY, f64::min),
+        scores.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+    )
+}
+
+/// Natural logarithms of 0! to n!; computed once per analysis for Fisher's exact test.
+pub fn ln_factorials(n: usize) -> Vec<f64> {
+    let mut ret = Vec::with_capacity(n + 1);
+    ret.push(0.0);
+    for i in 1..=n {
+        ret.push(ret[i - 1] + (i as f64).ln());
+    }
+
+    ret
+}
+
+/// Probability of observing at least as many satisfying crashes if the predicate was independent of crashing.
+fn fisher_exact_test(matrix: &ConfusionMatrix, ln_fact: &[f64]) -> f64 {
+    let num_crashes = matrix.true_positives + matrix.false_negatives;
+    let num_satisfied = matrix.true_positives + matrix.false_positives;
+    let total = num_crashes + matrix.true_negatives + matrix.false_positives;
+
+    let ln_binomial = |n: usize, k: usize| ln_fact[n] - ln_fact[k] - ln_fact[n - k];
+
+    let p_value: f64 = (matrix.true_positives..=num_crashes.min(num_satisfied))
+        .filter(|x| num_satisfied - x <= total - num_crashes)
+        .map(|x| {
+            (ln_binomial(num_crashes, x) + ln_binomial(total - num_crashes, num_satisfied - x)
+                - ln_binomial(total, num_satisfied))
+            .exp()
+        })
+        .sum();
+
+    p_value.min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scoring_function_names() {
+        for name in SCORING_FUNCTIONS.iter() {
+            assert_eq!(scoring_function(name).unwrap().name(), *name);
+        }
+        assert!(scoring_function("accuracy").is_none());
+    }
+
+    #[test]
+    fn test_information_gain_direction() {
+        let scoring = InformationGain;
+        // 1 of 10 traces crashes
+        let crashing = ConfusionMatrix::new(1, 9, 1, 9);
+        let not_crashing = ConfusionMatrix::new(0, 0, 1, 9);
+
+        assert!((scoring.score(&crashing) - scoring.max_score(1, 9)).abs() < 1e-9);
+        assert_eq!(scoring.score(&not_crashing), 0.0);
+        assert!(scoring.max_score(1, 9) < 0.9);
+    }
+
+    #[test]
+    fn test_wilson_interval() {
+        assert_eq!(wilson_interval(0, 0), (0.0, 1.0));
+
+        let (low, high) = wilson_interval(5, 10);
+        assert!((low - 0.2366).abs() < 1e-4);
+        assert!((high - 0.7634).abs() < 1e-4);
+
+        let (low, high) = wilson_interval(10, 10);
+        assert!(low > 0.69 && low < 0.73);
+        assert_eq!(high, 1.0);
+    }
+
+    #[test]
+    fn test_fisher_exact_test() {
+        let ln_fact = ln_factorials(6);
+
+        // all 3 crashes and none of the 3 non-crashes satisfy the predicate: 1 of C(6, 3) tables
+        let perfect = ConfusionMatrix::new(3, 3, 3, 3);
+        assert!((fisher_exact_test(&perfect, &ln_fact) - 0.05).abs() < 1e-9);
+
+        // no crash satisfies the predicate, every table is at least as extreme
+        let none = ConfusionMatrix::new(0, 0, 3, 3);
+        assert!((fisher_exact_test(&none, &ln_fact) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cmp_scores_nan() {
+        let mut scores = [0.5, f64::NAN, 1.0, 0.0];
+        scores.sort_by(|s1, s2| cmp_scores(*s1, *s2));
+
+        assert!(scores[0].is_nan());
+        assert_eq!(scores[1..], [0.0, 0.5, 1.0]);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::config::Config;
+use crate::error::SymbolizationError;
+use crate::predicates::SerializedPredicate;
+use addr2line::{demangle_auto, Loader};
+use object::{Object, ObjectSegment};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Source location of an address, resolved via DWARF or, for stripped binaries, the symbol table.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// functions the location is inlined into, innermost first
+    #[serde(default)]
+    pub inlined_by: Vec<InlinedFrame>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InlinedFrame {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+fn format_frame(function: &Option<String>, file: &Option<String>, line: &Option<u32>) -> String {
+    format!(
+        "{} at {}:{}",
+        function.as_deref().unwrap_or("??"),
+        file.as_deref()
+            .map(|file| {
+                Path::new(file)
+                    .file_name()
+                    .map_or(Cow::from(file), |name| name.to_string_lossy())
+            })
+            .unwrap_or(Cow::from("??")),
+        line.map(|line| line.to_string())
+            .unwrap_or_else(|| "?".to_string())
+    )
+}
+
+/// Formats the location like `addr2line -f -C -s -i -p`, on a single line.
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            format_frame(&self.function, &self.file, &self.line)
+        )?;
+        for frame in self.inlined_by.iter() {
+            write!(
+                f,
+                " (inlined by) {}",
+                format_frame(&frame.function, &frame.file, &frame.line)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps runtime addresses of the traced image to source locations of its binary.
+pub struct Symbolizer {
+    loader: Loader,
+    /// difference between runtime and link-time addresses
+    load_bias: usize,
+}
+
+impl Symbolizer {
+    /// PIE images are linked at 0 and loaded at `image_base`, non-PIE images are loaded at their
+    /// link-time address; subtracting the lowest segment address handles both.
+    pub fn new(binary_path: &str, image_base: usize) -> Result<Symbolizer, SymbolizationError> {
+        let content = fs::read(binary_path).map_err(|source| SymbolizationError::Io {
+            path: binary_path.to_string(),
+            source,
+        })?;
+        let binary =
+            object::File::parse(&*content).map_err(|source| SymbolizationError::Object {
+                path: binary_path.to_string(),
+                source,
+            })?;
+        let link_base = binary
+            .segments()
+            .map(|segment| segment.address())
+            .min()
+            .unwrap_or(0) as usize;
+
+        let loader = Loader::new(binary_path).map_err(|error| SymbolizationError::Debug {
+            path: binary_path.to_string(),
+            message: error.to_string(),
+        })?;
+
+        Ok(Symbolizer {
+            loader,
+            load_bias: image_base.saturating_sub(link_base),
+        })
+    }
+
+    /// Symbolizer for the binary given by `--binary`, if any and if it can be loaded.
+    pub fn from_config(config: &Config, image_base: usize) -> Option<Symbolizer> {
+        if !config.symbolize() {
+            return None;
+        }
+
+        match Symbolizer::new(&config.binary_path, image_base) {
+            Ok(symbolizer) => Some(symbolizer),
+            Err(error) => {
+                eprintln!("skipping symbolization: {}", error);
+                None
+            }
+        }
+    }
+
+    pub fn symbolize(&self, address: usize) -> Option<SourceLocation> {
+        let probe = address.checked_sub(self.load_bias)? as u64;
+
+        let mut frames = vec![];
+        if let Ok(mut iter) = self.loader.find_frames(probe) {
+            while let Ok(Some(frame)) = iter.next() {
+                frames.push(InlinedFrame {
+                    function: frame
+                        .function
+                        .as_ref()
+                        .and_then(|function| function.demangle().ok())
+                        .map(|function| function.to_string()),
+                    file: frame
+                        .location
+                        .as_ref()
+                        .and_then(|location| location.file)
+                        .map(|file| file.to_string()),
+                    line: frame.location.as_ref().and_then(|location| location.line),
+                });
+            }
+        }
+
+        if frames.is_empty() {
+            // stripped binary: the symbol table only knows the function
+            let symbol = self.loader.find_symbol(probe)?;
+            return Some(SourceLocation {
+                function: Some(demangle_auto(Cow::from(symbol), None).to_string()),
+                ..SourceLocation::default()
+            });
+        }
+
+        let innermost = frames.remove(0);
+        Some(SourceLocation {
+            function: innermost.function.or_else(|| {
+                self.loader
+                    .find_symbol(probe)
+                    .map(|symbol| demangle_auto(Cow::from(symbol), None).to_string())
+            }),
+            file: innermost.file,
+            line: innermost.line,
+            inlined_by: frames,
+        })
+    }
+
+    /// Symbolizes each address once, even if several predicates share it.
+    pub fn symbolize_predicates<'a>(
+        &self,
+        predicates: impl Iterator<Item = &'a mut SerializedPredicate>,
+    ) {
+        let mut locations: HashMap<usize, Option<SourceLocation>> = HashMap::new();
+        for predicate in predicates {
+            predicate.location = locations
+                .entry(predicate.address)
+                .or_insert_with(|| self.symbolize(predicate.address))
+                .clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_frame() {
+        let function = Some("main".to_string());
+
+        assert_eq!(
+            format_frame(&function, &Some("/src/main.c".to_string()), &Some(3)),
+            "main at main.c:3"
+        );
+        assert_eq!(
+            format_frame(&function, &Some("..".to_string()), &Some(3)),
+            "main at ..:3"
+        );
+        assert_eq!(format_frame(&None, &None, &None), "?? at ??:?");
+    }
+
+    #[test]
+    fn test_display_inlined_location() {
+        let location = SourceLocation {
+            function: Some("parse".to_string()),
+            file: Some("/src/parse.c".to_string()),
+            line: Some(7),
+            inlined_by: vec![InlinedFrame {
+                function: Some("main".to_string()),
+                file: Some("/src/main.c".to_string()),
+                line: Some(3),
+            }],
+        };
+
+        assert_eq!(
+            location.to_string(),
+            "parse at parse.c:7 (inlined by) main at main.c:3"
+        );
+    }
+
+    #[test]
+    fn test_from_config_missing_binary() {
+        let mut config = Config::default(&"".to_string(), &None, &None);
+        config.binary_path = "/nonexistent/binary".to_string();
+
+        assert!(Symbolizer::from_config(&config, 0x1000).is_none());
+    }
+}
@@ -1,8 +1,10 @@
+use crate::error::TraceError;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Keys;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::path::Path;
 
 pub static REGISTERS: [&str; 25] = [
     "rax",
@@ -32,6 +34,34 @@ pub static REGISTERS: [&str; 25] = [
     "memory_value",
 ];
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EdgeType {
+    Direct,
+    Indirect,
+    Conditional,
+    Syscall,
+    Return,
+    Regular,
+    Unknown,
+}
+
+impl EdgeType {
+    /// Unconditional edges always lead to the same successor.
+    pub fn is_unconditional(&self) -> bool {
+        match self {
+            EdgeType::Direct | EdgeType::Syscall | EdgeType::Regular => true,
+            _ => false,
+        }
+    }
+}
+
+impl Default for EdgeType {
+    fn default() -> Self {
+        EdgeType::Unknown
+    }
+}
+
+#[derive(Clone, Copy)]
 pub enum Selector {
     RegMin,
     RegMax,
@@ -87,6 +117,20 @@ impl Registers {
         self.0.values()
     }
 
+    /// Maps indices of a foreign register table to `REGISTERS`, dropping unknown registers.
+    fn remap(&self, registers: &[String]) -> Registers {
+        Registers(
+            self.0
+                .iter()
+                .filter_map(|(index, reg)| {
+                    let name = registers.get(*index)?;
+                    let index = REGISTERS.iter().position(|r| r == name)?;
+                    Some((index, reg.clone()))
+                })
+                .collect(),
+        )
+    }
+
     pub fn to_string(&self) -> String {
         self.0
             .values()
@@ -125,14 +169,27 @@ pub struct Instruction {
     pub mnemonic: String,
     pub registers_min: Registers,
     pub registers_max: Registers,
+    pub registers_last: Registers,
     pub successors: Vec<Successor>,
+    pub last_successor: usize,
+    pub count: usize,
 }
 
 impl Instruction {
+    pub fn registers(&self, selector: &Selector) -> &Registers {
+        match selector {
+            Selector::RegMin => &self.registers_min,
+            Selector::RegMax => &self.registers_max,
+            Selector::RegLast => &self.registers_last,
+            _ => unreachable!(),
+        }
+    }
+
     pub fn to_string(&self) -> String {
         let mut ret = String::new();
         ret.push_str(&format!("{:#018x};", self.address));
         ret.push_str(&format!("{};", self.mnemonic));
+        ret.push_str(&format!("count: {};", self.count));
 
         for index in 0..REGISTERS.len() {
             if let Some(register) = self.registers_min.get(index) {
@@ -149,6 +206,13 @@ impl Instruction {
                     register.to_string_extended()
                 ));
             }
+            if let Some(register) = self.registers_last.get(index) {
+                ret.push_str(&format!(
+                    "{}: {};",
+                    REGISTERS[index],
+                    register.to_string_extended()
+                ));
+            }
         }
 
         for successor in self.successors.iter() {
@@ -191,14 +255,17 @@ impl SerializedInstruction {
     }
 
     pub fn to_instruction(&self) -> Instruction {
-        let (registers_min, registers_max, _) = self.add_mem_to_registers();
+        let (registers_min, registers_max, registers_last) = self.add_mem_to_registers();
 
         Instruction {
             address: self.address,
             mnemonic: self.mnemonic.to_string(),
             registers_min,
             registers_max,
+            registers_last,
             successors: vec![],
+            last_successor: self.last_successor,
+            count: self.count,
         }
     }
 }
@@ -208,18 +275,152 @@ struct SerializedEdge {
     from: usize,
     to: usize,
     count: usize,
+    #[serde(default)]
+    edge_type: EdgeType,
 }
 
+/// Version of the trace format written by the tracer, see `tracing/trace_schema.json`.
+pub const TRACE_FORMAT_VERSION: u32 = 1;
+
 #[derive(Clone, Serialize, Deserialize)]
 struct SerializedTrace {
+    /// traces written before the format was versioned have version 0
+    #[serde(default)]
+    pub version: u32,
     pub instructions: Vec<SerializedInstruction>,
     pub edges: Vec<SerializedEdge>,
     pub first_address: usize,
     pub last_address: usize,
     pub image_base: usize,
+    #[serde(default)]
+    pub image_end: usize,
+}
+
+/// Magic bytes of binary traces, followed by the little-endian format version.
+const BINARY_TRACE_MAGIC: &[u8; 4] = b"AURT";
+pub const BINARY_TRACE_VERSION: u32 = 2;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct TraceHeader {
+    /// trace format version of the converted trace
+    pub version: u32,
+    pub image_base: usize,
+    pub image_end: usize,
+    pub first_address: usize,
+    pub last_address: usize,
+    /// register names of the indices used in the trace
+    pub registers: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct BinaryTrace {
+    pub header: TraceHeader,
+    pub instructions: Vec<SerializedInstruction>,
+    pub edges: Vec<SerializedEdge>,
+}
+
+impl BinaryTrace {
+    fn from_serialized(serialized: SerializedTrace) -> BinaryTrace {
+        BinaryTrace {
+            header: TraceHeader {
+                version: serialized.version,
+                image_base: serialized.image_base,
+                image_end: serialized.image_end,
+                first_address: serialized.first_address,
+                last_address: serialized.last_address,
+                registers: REGISTERS.iter().map(|r| r.to_string()).collect(),
+            },
+            instructions: serialized.instructions,
+            edges: serialized.edges,
+        }
+    }
+
+    fn to_serialized(self) -> SerializedTrace {
+        let registers = &self.header.registers;
+        let remap = registers
+            .iter()
+            .map(|r| r.as_str())
+            .ne(REGISTERS.iter().cloned());
+
+        SerializedTrace {
+            version: self.header.version,
+            instructions: self
+                .instructions
+                .into_iter()
+                .map(|mut instr| {
+                    if remap {
+                        instr.registers_min = instr.registers_min.remap(registers);
+                        instr.registers_max = instr.registers_max.remap(registers);
+                        instr.registers_last = instr.registers_last.remap(registers);
+                    }
+                    instr
+                })
+                .collect(),
+            edges: self.edges,
+            first_address: self.header.first_address,
+            last_address: self.header.last_address,
+            image_base: self.header.image_base,
+            image_end: self.header.image_end,
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut ret = BINARY_TRACE_MAGIC.to_vec();
+        ret.extend_from_slice(&BINARY_TRACE_VERSION.to_le_bytes());
+        ret.extend(bincode::serialize(self).expect("Could not serialize binary trace"));
+
+        ret
+    }
+
+    fn deserialize(file_path: &str, content: &[u8]) -> Result<BinaryTrace, TraceError> {
+        let mut version = [0u8; 4];
+        version.copy_from_slice(&content[4..8]);
+        let version = u32::from_le_bytes(version);
+        if version != BINARY_TRACE_VERSION {
+            return Err(TraceError::FormatVersion {
+                path: file_path.to_string(),
+                version,
+            });
+        }
+
+        bincode::deserialize(&content[8..]).map_err(|source| TraceError::Binary {
+            path: file_path.to_string(),
+            source,
+        })
+    }
+}
+
+fn read_file(file_path: &str) -> Result<Vec<u8>, TraceError> {
+    fs::read(file_path).map_err(|source| TraceError::Io {
+        path: file_path.to_string(),
+        source,
+    })
+}
+
+fn is_binary_trace(content: &[u8]) -> bool {
+    content.len() >= 8 && content.starts_with(BINARY_TRACE_MAGIC)
 }
 
 impl SerializedTrace {
+    fn from_content(file_path: &str, content: &[u8]) -> Result<SerializedTrace, TraceError> {
+        let serialized: SerializedTrace = match is_binary_trace(content) {
+            true => BinaryTrace::deserialize(file_path, content)?.to_serialized(),
+            false => serde_json::from_slice(content).map_err(|source| TraceError::Json {
+                path: file_path.to_string(),
+                source,
+            })?,
+        };
+
+        if serialized.version > TRACE_FORMAT_VERSION {
+            return Err(TraceError::FormatVersion {
+                path: file_path.to_string(),
+                version: serialized.version,
+            });
+        }
+
+        Ok(serialized)
+    }
+
     pub fn to_trace(name: String, serialized: SerializedTrace) -> Trace {
         let mut instructions: HashMap<usize, Instruction> = serialized
             .instructions
@@ -228,17 +429,29 @@ impl SerializedTrace {
             .collect();
         for edge in &serialized.edges {
             if let Some(entry) = instructions.get_mut(&edge.from) {
-                entry.successors.push(Successor { address: edge.to });
+                entry.successors.push(Successor {
+                    address: edge.to,
+                    count: edge.count,
+                    edge_type: edge.edge_type,
+                });
             }
         }
         for v in instructions.values_mut() {
             v.successors.sort_by(|a, b| a.address.cmp(&b.address))
         }
 
+        // the tracer reports the last address of the image; older traces do not contain it
+        let image_end = match serialized.image_end {
+            0 => instructions.keys().max().map_or(0, |address| address + 1),
+            image_end => image_end + 1,
+        };
+
         Trace {
             name,
+            version: serialized.version,
             instructions,
             image_base: serialized.image_base,
+            image_end,
             first_address: serialized.first_address,
             last_address: serialized.last_address,
         }
@@ -248,51 +461,108 @@ impl SerializedTrace {
 #[derive(Clone, Serialize, Deserialize, Copy)]
 pub struct Successor {
     pub address: usize,
+    pub count: usize,
+    pub edge_type: EdgeType,
 }
 
 impl Successor {
     pub fn to_string(&self) -> String {
-        format!("{:#018x}", self.address)
+        format!(
+            "{:#018x} ({:?}, {})",
+            self.address, self.edge_type, self.count
+        )
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Trace {
     pub name: String,
+    pub version: u32,
     pub image_base: usize,
+    pub image_end: usize,
     pub instructions: HashMap<usize, Instruction>,
     pub first_address: usize,
     pub last_address: usize,
 }
 
 impl Trace {
-    pub fn from_trace_file(file_path: String) -> Trace {
-        let content =
-            fs::read_to_string(&file_path).expect(&format!("File {} not found!", &file_path));
-        Trace::from_file(file_path, content)
+    pub fn from_trace_file(file_path: String) -> Result<Trace, TraceError> {
+        let content = read_file(&file_path)?;
+        let serialized_trace = SerializedTrace::from_content(&file_path, &content)?;
+        Ok(SerializedTrace::to_trace(file_path, serialized_trace))
+    }
+
+    pub fn from_zip_file(file_path: String) -> Result<Trace, TraceError> {
+        let (trace_file_path, trace_content) = Trace::read_zip_file(&file_path)?;
+        let serialized_trace = SerializedTrace::from_content(&file_path, &trace_content)?;
+        Ok(SerializedTrace::to_trace(trace_file_path, serialized_trace))
     }
 
-    pub fn from_zip_file(file_path: String) -> Trace {
-        let zip_file =
-            fs::File::open(&file_path).expect(&format!("Could not open file {}", &file_path));
-        let mut zip_archive = zip::ZipArchive::new(zip_file)
-            .expect(&format!("Could not open archive {}", &file_path));
+    fn read_zip_file(file_path: &str) -> Result<(String, Vec<u8>), TraceError> {
+        let zip_error = |source| TraceError::Zip {
+            path: file_path.to_string(),
+            source,
+        };
+
+        let zip_file = fs::File::open(file_path).map_err(|source| TraceError::Io {
+            path: file_path.to_string(),
+            source,
+        })?;
+        let mut zip_archive = zip::ZipArchive::new(zip_file).map_err(zip_error)?;
+        if zip_archive.len() == 0 {
+            return Err(TraceError::EmptyArchive {
+                path: file_path.to_string(),
+            });
+        }
 
-        let mut trace_file = zip_archive.by_index(0).unwrap();
+        let mut trace_file = zip_archive.by_index(0).map_err(zip_error)?;
         let trace_file_path = trace_file.sanitized_name().to_str().unwrap().to_string();
 
-        let mut trace_content = String::new();
+        let mut trace_content = vec![];
         trace_file
-            .read_to_string(&mut trace_content)
-            .expect(&format!("Could not read unzipped file {}", trace_file_path));
+            .read_to_end(&mut trace_content)
+            .map_err(|source| TraceError::Io {
+                path: file_path.to_string(),
+                source,
+            })?;
 
-        Trace::from_file(trace_file_path, trace_content)
+        Ok((trace_file_path, trace_content))
     }
 
-    fn from_file(file_path: String, content: String) -> Trace {
-        let serialized_trace: SerializedTrace = serde_json::from_str(&content)
-            .expect(&format!("Could not deserialize file {}", &file_path));
-        SerializedTrace::to_trace(file_path, serialized_trace)
+    /// Converts a JSON trace (zipped or plain, detected by the extension) into a zipped binary trace.
+    pub fn convert_to_binary(file_path: &str, output_path: &str) -> Result<(), TraceError> {
+        let (trace_file_path, content) = match file_path.ends_with(".zip") {
+            true => Trace::read_zip_file(file_path)?,
+            false => (file_path.to_string(), read_file(file_path)?),
+        };
+
+        let serialized_trace = SerializedTrace::from_content(file_path, &content)?;
+        let binary_trace = BinaryTrace::from_serialized(serialized_trace).serialize();
+
+        let io_error = |source| TraceError::Io {
+            path: output_path.to_string(),
+            source,
+        };
+        let zip_error = |source| TraceError::Zip {
+            path: output_path.to_string(),
+            source,
+        };
+
+        let trace_file_name = Path::new(&trace_file_path)
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let zip_file = fs::File::create(output_path).map_err(io_error)?;
+        let mut zip_archive = zip::ZipWriter::new(zip_file);
+        zip_archive
+            .start_file(trace_file_name, zip::write::FileOptions::default())
+            .map_err(zip_error)?;
+        zip_archive.write_all(&binary_trace).map_err(io_error)?;
+        zip_archive.finish().map_err(zip_error)?;
+
+        Ok(())
     }
 
     pub fn visited_addresses(&self) -> HashSet<usize> {
@@ -339,3 +609,132 @@ impl TraceVec {
         self.0.as_slice()
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn edge_type(mnemonic: &str) -> EdgeType {
+        match mnemonic.split(' ').next().unwrap() {
+            "call" | "jmp" => EdgeType::Direct,
+            "ret" => EdgeType::Return,
+            opcode if opcode.starts_with('j') => EdgeType::Conditional,
+            _ => EdgeType::Regular,
+        }
+    }
+
+    /// Trace executing the `(address, mnemonic)` pairs of `path` in order, with `rax(address)` in
+    /// the registers of each instruction.
+    pub fn trace(path: &[(usize, &str)], rax: impl Fn(usize) -> u64) -> Trace {
+        SerializedTrace::to_trace("test".to_string(), serialized_trace(path, rax))
+    }
+
+    fn serialized_trace(path: &[(usize, &str)], rax: impl Fn(usize) -> u64) -> SerializedTrace {
+        let mut instructions: Vec<serde_json::Value> = vec![];
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        for (address, mnemonic) in path.iter() {
+            *counts.entry(*address).or_default() += 1;
+            if counts[address] == 1 {
+                let registers = json!({ "0": { "value": rax(*address) } });
+                instructions.push(json!({
+                    "address": address,
+                    "mnemonic": mnemonic,
+                    "registers_min": registers,
+                    "registers_max": registers,
+                    "registers_last": registers,
+                    "last_successor": 0,
+                    "count": 0,
+                    "memory": null,
+                }));
+            }
+        }
+        for instruction in instructions.iter_mut() {
+            let address = instruction["address"].as_u64().unwrap() as usize;
+            instruction["count"] = json!(counts[&address]);
+        }
+
+        let mut edges: Vec<((usize, usize), usize, EdgeType)> = vec![];
+        for window in path.windows(2) {
+            let (from, to) = (window[0].0, window[1].0);
+            match edges.iter_mut().find(|(edge, _, _)| *edge == (from, to)) {
+                Some((_, count, _)) => *count += 1,
+                None => edges.push(((from, to), 1, edge_type(window[0].1))),
+            }
+        }
+
+        serde_json::from_value(json!({
+            "version": TRACE_FORMAT_VERSION,
+            "instructions": instructions,
+            "edges": edges
+                .iter()
+                .map(|((from, to), count, edge_type)| json!({
+                    "from": from,
+                    "to": to,
+                    "count": count,
+                    "edge_type": edge_type,
+                }))
+                .collect::<Vec<_>>(),
+            "first_address": path.first().unwrap().0,
+            "last_address": path.last().unwrap().0,
+            "image_base": 0x1000,
+            "image_end": 0x2000,
+        }))
+        .unwrap()
+    }
+
+    fn empty_trace(version: u32) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "version": version,
+            "instructions": [],
+            "edges": [],
+            "first_address": 0,
+            "last_address": 0,
+            "image_base": 0,
+            "image_end": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_format_version() {
+        assert!(SerializedTrace::from_content("trace", &empty_trace(0)).is_ok());
+        assert!(SerializedTrace::from_content("trace", &empty_trace(TRACE_FORMAT_VERSION)).is_ok());
+
+        match SerializedTrace::from_content("trace", &empty_trace(TRACE_FORMAT_VERSION + 1)) {
+            Err(TraceError::FormatVersion { version, .. }) => {
+                assert_eq!(version, TRACE_FORMAT_VERSION + 1)
+            }
+            _ => panic!("newer format version accepted"),
+        }
+    }
+
+    #[test]
+    fn test_binary_trace_round_trip() {
+        let serialized =
+            serialized_trace(&[(0x10, "mov"), (0x14, "jz 0x10"), (0x10, "mov")], |a| {
+                a as u64 * 2
+            });
+        let expected = serde_json::to_value(&serialized).unwrap();
+
+        let binary = BinaryTrace::from_serialized(serialized).serialize();
+        assert!(is_binary_trace(&binary));
+
+        let converted = SerializedTrace::from_content("trace", &binary).unwrap();
+        assert_eq!(serde_json::to_value(&converted).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_binary_trace_version() {
+        let serialized = serialized_trace(&[(0x10, "ret")], |_| 0);
+        let mut binary = BinaryTrace::from_serialized(serialized).serialize();
+        binary[4..8].copy_from_slice(&(BINARY_TRACE_VERSION + 1).to_le_bytes());
+
+        match SerializedTrace::from_content("trace", &binary) {
+            Err(TraceError::FormatVersion { version, .. }) => {
+                assert_eq!(version, BINARY_TRACE_VERSION + 1)
+            }
+            _ => panic!("unsupported binary trace version accepted"),
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use crate::trace::{EdgeType, Instruction, Selector, Trace};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Number of crashing and non-crashing traces with some property.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LabeledCount {
+    pub crashes: usize,
+    pub non_crashes: usize,
+}
+
+impl LabeledCount {
+    fn add(&mut self, crash: bool) {
+        match crash {
+            true => self.crashes += 1,
+            false => self.non_crashes += 1,
+        }
+    }
+}
+
+/// Values observed once per trace, labeled by whether the trace crashed.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct LabeledValues {
+    values: Vec<(u64, bool)>,
+    /// number of crashes among the first n sorted values
+    crash_prefix: Vec<usize>,
+}
+
+impl LabeledValues {
+    fn push(&mut self, value: u64, crash: bool) {
+        self.values.push((value, crash));
+    }
+
+    fn finalize(&mut self) {
+        self.values.sort();
+        self.crash_prefix = Vec::with_capacity(self.values.len() + 1);
+        self.crash_prefix.push(0);
+        for (_, crash) in self.values.iter() {
+            let last = *self.crash_prefix.last().unwrap();
+            self.crash_prefix.push(last + *crash as usize);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = u64> + '_ {
+        self.values.iter().map(|(value, _)| *value)
+    }
+
+    /// Values with whether their trace crashed, sorted by value.
+    pub fn labeled_values(&self) -> impl Iterator<Item = (u64, bool)> + '_ {
+        self.values.iter().copied()
+    }
+
+    pub fn unique_values(&self) -> Vec<u64> {
+        let mut ret: Vec<_> = self.values().collect();
+        ret.dedup();
+
+        ret
+    }
+
+    pub fn total(&self) -> LabeledCount {
+        self.count_first(self.values.len())
+    }
+
+    /// Counts the traces whose value is less than `threshold`.
+    pub fn less(&self, threshold: u64) -> LabeledCount {
+        self.count_first(self.values.partition_point(|(value, _)| *value < threshold))
+    }
+
+    pub fn greater_or_equal(&self, threshold: u64) -> LabeledCount {
+        let (total, less) = (self.total(), self.less(threshold));
+        LabeledCount {
+            crashes: total.crashes - less.crashes,
+            non_crashes: total.non_crashes - less.non_crashes,
+        }
+    }
+
+    /// Counts the traces whose value is in [start, end).
+    pub fn in_range(&self, start: u64, end: u64) -> LabeledCount {
+        let (less_end, less_start) = (self.less(end), self.less(start));
+        LabeledCount {
+            crashes: less_end.crashes - less_start.crashes,
+            non_crashes: less_end.non_crashes - less_start.non_crashes,
+        }
+    }
+
+    pub fn count_where(&self, f: impl Fn(u64) -> bool) -> LabeledCount {
+        let mut ret = LabeledCount::default();
+        for (value, crash) in self.values.iter() {
+            if f(*value) {
+                ret.add(*crash);
+            }
+        }
+
+        ret
+    }
+
+    fn count_first(&self, n: usize) -> LabeledCount {
+        let crashes = self.crash_prefix[n];
+        LabeledCount {
+            crashes,
+            non_crashes: n - crashes,
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct EdgeAggregate {
+    pub edge_type: EdgeType,
+    pub taken: LabeledCount,
+    pub only_taken: LabeledCount,
+    pub last_taken: LabeledCount,
+    /// edge counts of all traces visiting the source, including zeros
+    pub counts: LabeledValues,
+    /// zeros added to `counts` so far
+    padded: LabeledCount,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AddressAggregate {
+    pub mnemonic: String,
+    pub visits: LabeledCount,
+    pub registers_min: HashMap<usize, LabeledValues>,
+    pub registers_max: HashMap<usize, LabeledValues>,
+    pub registers_last: HashMap<usize, LabeledValues>,
+    pub counts: LabeledValues,
+    /// histogram of the number of successors
+    pub num_successors: HashMap<usize, LabeledCount>,
+    pub edges: HashMap<usize, EdgeAggregate>,
+    /// traces were added since the last `finalize`
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl AddressAggregate {
+    fn add(&mut self, instruction: &Instruction, crash: bool) {
+        if self.mnemonic.is_empty() {
+            self.mnemonic = instruction.mnemonic.to_string();
+        }
+        self.visits.add(crash);
+        self.dirty = true;
+
+        for (registers, aggregated) in [
+            (&instruction.registers_min, &mut self.registers_min),
+            (&instruction.registers_max, &mut self.registers_max),
+            (&instruction.registers_last, &mut self.registers_last),
+        ]
+        .iter_mut()
+        {
+            for reg_index in registers.keys() {
+                aggregated
+                    .entry(*reg_index)
+                    .or_default()
+                    .push(registers.get(*reg_index).unwrap().value(), crash);
+            }
+        }
+        self.counts.push(instruction.count as u64, crash);
+
+        self.num_successors
+            .entry(instruction.successors.len())
+            .or_default()
+            .add(crash);
+        for successor in instruction.successors.iter() {
+            let edge = self.edges.entry(successor.address).or_default();
+            edge.edge_type = successor.edge_type;
+            edge.taken.add(crash);
+            if instruction.successors.len() == 1 {
+                edge.only_taken.add(crash);
+            }
+            edge.counts.push(successor.count as u64, crash);
+        }
+        if let Some(edge) = self.edges.get_mut(&instruction.last_successor) {
+            edge.last_taken.add(crash);
+        }
+    }
+
+    fn finalize(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        for edge in self.edges.values_mut() {
+            // traces visiting the source without taking the edge have a count of zero
+            for _ in edge.taken.crashes + edge.padded.crashes..self.visits.crashes {
+                edge.counts.push(0, true);
+            }
+            for _ in edge.taken.non_crashes + edge.padded.non_crashes..self.visits.non_crashes {
+                edge.counts.push(0, false);
+            }
+            edge.padded = LabeledCount {
+                crashes: self.visits.crashes - edge.taken.crashes,
+                non_crashes: self.visits.non_crashes - edge.taken.non_crashes,
+            };
+            edge.counts.finalize();
+        }
+
+        for values in self
+            .registers_min
+            .values_mut()
+            .chain(self.registers_max.values_mut())
+            .chain(self.registers_last.values_mut())
+        {
+            values.finalize();
+        }
+        self.counts.finalize();
+    }
+
+    pub fn registers(&self, selector: &Selector) -> &HashMap<usize, LabeledValues> {
+        match selector {
+            Selector::RegMin => &self.registers_min,
+            Selector::RegMax => &self.registers_max,
+            Selector::RegLast => &self.registers_last,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Values of a register, or the instruction counts for `Selector::InsCount`.
+    pub fn values(&self, selector: &Selector, reg_index: Option<usize>) -> Option<&LabeledValues> {
+        match selector {
+            Selector::InsCount => Some(&self.counts),
+            _ => self.registers(selector).get(&reg_index?),
+        }
+    }
+
+    /// Counts the traces with more than `n` successors.
+    pub fn num_successors_greater(&self, n: usize) -> LabeledCount {
+        let mut ret = LabeledCount::default();
+        for (num_successors, count) in self.num_successors.iter() {
+            if *num_successors > n {
+                ret.crashes += count.crashes;
+                ret.non_crashes += count.non_crashes;
+            }
+        }
+
+        ret
+    }
+
+    pub fn num_successors_equal(&self, n: usize) -> LabeledCount {
+        self.num_successors.get(&n).cloned().unwrap_or_default()
+    }
+}
+
+/// Per-address aggregates of all traces; built while reading traces, so that single traces can be dropped.
+///
+/// Traces can still be added after `finalize`; the next `finalize` only updates the visited addresses.
+#[derive(Default, Serialize, Deserialize)]
+pub struct TraceAggregates {
+    pub addresses: HashMap<usize, AddressAggregate>,
+    pub num_crashes: usize,
+    pub num_non_crashes: usize,
+    pub crashing_addresses: HashSet<usize>,
+    pub image_start: usize,
+    pub image_end: usize,
+}
+
+impl TraceAggregates {
+    pub fn new() -> TraceAggregates {
+        TraceAggregates {
+            image_start: usize::MAX,
+            ..TraceAggregates::default()
+        }
+    }
+
+    pub fn add_trace(&mut self, trace: &Trace, crash: bool) {
+        match crash {
+            true => {
+                self.num_crashes += 1;
+                self.crashing_addresses.insert(trace.last_address);
+            }
+            false => self.num_non_crashes += 1,
+        }
+        self.image_start = self.image_start.min(trace.image_base);
+        self.image_end = self.image_end.max(trace.image_end);
+
+        for (address, instruction) in trace.instructions.iter() {
+            self.addresses
+                .entry(*address)
+                .or_default()
+                .add(instruction, crash);
+        }
+    }
+
+    pub fn finalize(&mut self) {
+        if self.image_start > self.image_end {
+            self.image_start = 0;
+        }
+
+        self.addresses
+            .par_iter_mut()
+            .for_each(|(_, aggregate)| aggregate.finalize());
+    }
+
+    pub fn crash_non_crash_intersection(&self) -> HashSet<usize> {
+        self.addresses
+            .iter()
+            .filter(|(_, aggregate)| {
+                aggregate.visits.crashes > 0 && aggregate.visits.non_crashes > 0
+            })
+            .map(|(address, _)| *address)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labeled_values(values: &[(u64, bool)]) -> LabeledValues {
+        let mut ret = LabeledValues::default();
+        for (value, crash) in values.iter() {
+            ret.push(*value, *crash);
+        }
+        ret.finalize();
+
+        ret
+    }
+
+    fn counts(count: LabeledCount) -> (usize, usize) {
+        (count.crashes, count.non_crashes)
+    }
+
+    #[test]
+    fn test_labeled_value_counts() {
+        let values = labeled_values(&[(5, true), (1, false), (3, true), (3, false), (8, false)]);
+
+        assert_eq!(values.unique_values(), vec![1, 3, 5, 8]);
+        assert_eq!(counts(values.total()), (2, 3));
+        assert_eq!(counts(values.less(3)), (0, 1));
+        assert_eq!(counts(values.less(4)), (1, 2));
+        assert_eq!(counts(values.greater_or_equal(3)), (2, 2));
+        assert_eq!(counts(values.greater_or_equal(9)), (0, 0));
+        assert_eq!(counts(values.in_range(3, 8)), (2, 1));
+        assert_eq!(counts(values.in_range(3, 3)), (0, 0));
+        assert_eq!(counts(values.count_where(|v| v % 2 == 1)), (2, 2));
+    }
+
+    #[test]
+    fn test_empty_labeled_values() {
+        let values = labeled_values(&[]);
+
+        assert!(values.is_empty());
+        assert!(values.unique_values().is_empty());
+        assert_eq!(counts(values.total()), (0, 0));
+        assert_eq!(counts(values.less(u64::MAX)), (0, 0));
+        assert_eq!(counts(values.greater_or_equal(0)), (0, 0));
+    }
+}
@@ -1,60 +1,131 @@
+use crate::analysis_cache::AnalysisCache;
+use crate::builder::{TraceAnalyzerBuilder, TraceSource};
 use crate::config::Config;
 use crate::control_flow_graph::{CFGCollector, ControlFlowGraph};
+use crate::dominators::Dominators;
+use crate::error::TraceError;
+use crate::memory_regions::{MemoryMap, NULL_REGION_END};
 use crate::predicate_analysis::PredicateAnalyzer;
-use crate::predicates::{Predicate, SerializedPredicate};
+use crate::predicates::{edge_count, Predicate, SerializedPredicate};
+use crate::sampling::TraceSample;
+use crate::scoring::{cmp_scores, scoring_function, ConfusionMatrix, ScoringFunction};
+use crate::symbolization::Symbolizer;
 use crate::trace::{Instruction, Selector, Trace, TraceVec};
-use crate::trace_integrity::TraceIntegrityChecker;
+use crate::trace_aggregates::{LabeledCount, TraceAggregates};
 use glob::glob;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::{read_to_string, File};
 use std::io::Write;
-use std::process::exit;
 
 pub struct TraceAnalyzer {
     pub crashes: TraceVec,
     pub non_crashes: TraceVec,
     pub address_scores: HashMap<usize, Predicate>,
+    /// best predicates per address, best first; the first one is in `address_scores`
+    pub address_predicates: HashMap<usize, Vec<Predicate>>,
     pub cfg: ControlFlowGraph,
     pub memory_addresses: MemoryAddresses,
+    pub memory_map: MemoryMap,
+    pub scoring: Box<dyn ScoringFunction>,
+    /// `ln(n!)` up to the number of traces, for the statistics of serialized predicates
+    pub ln_factorials: Vec<f64>,
+    /// per-address aggregates if traces are streamed instead of kept in memory
+    pub aggregates: Option<TraceAggregates>,
+    pub rejected_traces: Vec<RejectedTrace>,
+    pub cache: Option<AnalysisCache>,
+    /// addresses whose predicates were collapsed into the predicate at the key address
+    pub merged_addresses: HashMap<usize, Vec<usize>>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Trace that could not be loaded and was skipped.
+#[derive(Serialize, Deserialize)]
+pub struct RejectedTrace {
+    pub path: String,
+    pub crash: bool,
+    pub error: String,
+}
+
+impl RejectedTrace {
+    pub fn new(error: TraceError, crash: bool) -> RejectedTrace {
+        RejectedTrace {
+            path: error.path().to_string(),
+            crash,
+            error: error.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializedScores {
+    pub metric: String,
+    pub predicates: Vec<SerializedPredicate>,
+    /// best predicates per address, in the order of `predicates`
+    #[serde(default)]
+    pub ranked_predicates: Vec<RankedPredicates>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RankedPredicates {
+    pub address: usize,
+    pub predicates: Vec<SerializedPredicate>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct MemoryAddresses {
     pub heap_start: usize,
     pub heap_end: usize,
     pub stack_start: usize,
     pub stack_end: usize,
+    /// values below are considered to point near NULL
+    #[serde(default = "default_null_region_end")]
+    pub null_region_end: usize,
 }
 
-impl MemoryAddresses {
-    pub fn read_from_file(config: &Config) -> MemoryAddresses {
-        let file_path = format!("{}/addresses.json", config.output_directory);
-        let content =
-            fs::read_to_string(&file_path).expect(&format!("File {} not found!", &file_path));
-        serde_json::from_str(&content).expect(&format!("Could not deserialize file {}", &file_path))
+fn default_null_region_end() -> usize {
+    NULL_REGION_END
+}
+
+impl Default for MemoryAddresses {
+    fn default() -> MemoryAddresses {
+        MemoryAddresses {
+            heap_start: 0,
+            heap_end: 0,
+            stack_start: 0,
+            stack_end: 0,
+            null_region_end: NULL_REGION_END,
+        }
     }
 }
 
-fn store_trace(trace: &Trace, must_have: &Option<HashSet<usize>>) -> bool {
-    match must_have {
-        Some(addresses) => addresses.iter().any(|k| trace.instructions.contains_key(k)),
-        None => true,
+impl MemoryAddresses {
+    pub fn read_from_file(config: &Config) -> Result<MemoryAddresses, TraceError> {
+        let file_path = format!("{}/addresses.json", config.output_directory);
+        let content = fs::read_to_string(&file_path).map_err(|source| TraceError::Io {
+            path: file_path.to_string(),
+            source,
+        })?;
+        serde_json::from_str(&content).map_err(|source| TraceError::Json {
+            path: file_path,
+            source,
+        })
     }
 }
 
 pub fn read_crash_blacklist(
     blacklist_crashes: bool,
     crash_blacklist_path: &String,
-) -> Option<Vec<String>> {
+) -> Result<Option<Vec<String>>, TraceError> {
     if blacklist_crashes {
-        Some(
+        Ok(Some(
             read_to_string(crash_blacklist_path)
-                .expect("Could not read crash blacklist")
+                .map_err(|source| TraceError::Io {
+                    path: crash_blacklist_path.to_string(),
+                    source,
+                })?
                 .split("\n")
                 .map(|s| {
                     s.split("/")
@@ -64,9 +135,9 @@ pub fn read_crash_blacklist(
                 })
                 .filter(|s| !s.is_empty())
                 .collect(),
-        )
+        ))
     } else {
-        None
+        Ok(None)
     }
 }
 
@@ -78,133 +149,177 @@ pub fn blacklist_path(path: &String, blacklist: &Option<Vec<String>>) -> bool {
         .any(|p| path.contains(p))
 }
 
-fn parse_traces(
+pub fn trace_paths(
     path: &String,
     config: &Config,
-    must_include: Option<HashSet<usize>>,
-    blacklist_paths: Option<Vec<String>>,
-) -> TraceVec {
+    blacklist_paths: &Option<Vec<String>>,
+) -> Vec<String> {
     let pattern = match config.zipped {
         false => format!("{}/*trace", path),
         true => format!("{}/*.zip", path),
     };
 
-    let mut paths: Vec<String> = glob(&pattern)
+    glob(&pattern)
         .unwrap()
         .map(|p| p.unwrap().to_str().unwrap().to_string())
-        .filter(|p| !blacklist_path(&p, &blacklist_paths))
-        .collect();
-
-    if config.random_traces() {
-        paths.shuffle(&mut thread_rng());
-    }
-
-    match config.zipped {
-        false => TraceVec::from_vec(
-            paths
-                .into_par_iter()
-                .map(|s| Trace::from_trace_file(s))
-                .take(if config.random_traces() {
-                    config.random_traces
-                } else {
-                    0xffff_ffff_ffff_ffff
-                })
-                .filter(|t| store_trace(&t, &must_include))
-                .collect(),
-        ),
-        true => TraceVec::from_vec(
-            paths
-                .into_par_iter()
-                .map(|s| Trace::from_zip_file(s))
-                .take(if config.random_traces() {
-                    config.random_traces
-                } else {
-                    0xffff_ffff_ffff_ffff
-                })
-                .filter(|t| store_trace(&t, &must_include))
-                .collect(),
-        ),
+        .filter(|p| !blacklist_path(&p, blacklist_paths))
+        .collect()
+}
+
+/// Selects the traces to analyze and records them in `sampled_traces.json` of the output directory.
+///
+/// All traces are analyzed unless `--random` or `--replay-sample` is given.
+pub fn sample_traces(config: &Config) -> Result<TraceSample, TraceError> {
+    let sample = if config.replay_sample() {
+        TraceSample::read_from_file(&config.replay_sample_path)?
+    } else {
+        let crash_blacklist =
+            read_crash_blacklist(config.blacklist_crashes(), &config.crash_blacklist_path)?;
+        let crashes = trace_paths(&config.path_to_crashes, config, &crash_blacklist);
+        let non_crashes = trace_paths(&config.path_to_non_crashes, config, &None);
+
+        if !config.random_traces() {
+            return Ok(TraceSample {
+                seed: None,
+                crashes,
+                non_crashes,
+            });
+        }
+
+        let (num_crashes, num_non_crashes) = config.sample_sizes();
+        let seed = config.seed.unwrap_or_else(|| thread_rng().gen());
+        TraceSample::draw(crashes, non_crashes, num_crashes, num_non_crashes, seed)
+    };
+
+    sample.write_to_file(&format!("{}/sampled_traces.json", config.output_directory));
+
+    Ok(sample)
+}
+
+/// Loads a trace file, zipped if its extension is `.zip`.
+pub fn load_trace(path: String) -> Result<Trace, TraceError> {
+    match path.ends_with(".zip") {
+        false => Trace::from_trace_file(path),
+        true => Trace::from_zip_file(path),
     }
 }
 
 impl TraceAnalyzer {
-    pub fn new(config: &Config) -> TraceAnalyzer {
-        println!("reading crashes");
-        let crash_blacklist =
-            read_crash_blacklist(config.blacklist_crashes(), &config.crash_blacklist_path);
-        let crashes = parse_traces(&config.path_to_crashes, config, None, crash_blacklist);
-        let crashing_addresses: Option<HashSet<usize>> = match config.filter_non_crashes {
-            true => Some(crashes.iter().map(|t| t.last_address).collect()),
-            false => None,
-        };
+    pub fn builder(crashes: TraceSource, non_crashes: TraceSource) -> TraceAnalyzerBuilder {
+        TraceAnalyzerBuilder::new(crashes, non_crashes)
+    }
+
+    /// Analyzes the traces given on the command line and prints the progress.
+    pub fn new(config: &Config) -> Result<TraceAnalyzer, TraceError> {
+        let sample = sample_traces(config)?;
 
-        println!("reading non-crashes");
-        let non_crashes = parse_traces(
-            &config.path_to_non_crashes,
+        let trace_analyzer = TraceAnalyzer::configured_builder(
             config,
-            crashing_addresses,
-            None,
-        );
+            TraceSource::Paths(sample.crashes),
+            TraceSource::Paths(sample.non_crashes),
+        )?
+        .build()?;
 
-        println!(
-            "{} crashes and {} non-crashes",
-            crashes.len(),
-            non_crashes.len()
-        );
+        trace_analyzer.dump_rejected_traces(config);
 
-        let mut trace_analyzer = TraceAnalyzer {
-            crashes,
-            non_crashes,
-            address_scores: HashMap::new(),
-            cfg: ControlFlowGraph::new(),
-            memory_addresses: MemoryAddresses::read_from_file(config),
-        };
+        Ok(trace_analyzer)
+    }
 
-        if config.check_traces || config.dump_scores || config.debug_predicate() {
-            let mut cfg_collector = CFGCollector::new();
-            println!("filling cfg");
-            trace_analyzer.fill_cfg(&mut cfg_collector);
+    /// Builder set up by the command line options that prints the progress.
+    pub fn configured_builder(
+        config: &Config,
+        crashes: TraceSource,
+        non_crashes: TraceSource,
+    ) -> Result<TraceAnalyzerBuilder, TraceError> {
+        // keep stdout parseable for machine-readable check reports
+        let quiet = config.check_traces && config.check_format == "json";
+
+        let mut memory_addresses = MemoryAddresses::read_from_file(config)?;
+        if let Some(null_region_end) = config.null_region_end {
+            memory_addresses.null_region_end = null_region_end;
         }
 
-        if config.check_traces {
-            println!("checking traces");
-            TraceIntegrityChecker::check_traces(&trace_analyzer);
-            exit(0);
-        }
+        Ok(TraceAnalyzer::builder(crashes, non_crashes)
+            .memory_addresses(memory_addresses)
+            .scoring(
+                // validated by the `--scoring` option
+                scoring_function(&config.scoring).expect("unknown scoring function"),
+            )
+            .stream_traces(config.stream_traces)
+            .cache_file(match config.cache_analysis {
+                true => Some(format!("{}/analysis_cache.bin", config.output_directory)),
+                false => None,
+            })
+            .filter_non_crashes(config.filter_non_crashes)
+            .fail_on_trace_error(config.fail_on_trace_error())
+            .combine_predicates(config.combine_predicates)
+            .collapse_predicates(config.collapse_predicates)
+            .top_predicates(config.top_predicates)
+            .fill_cfg(
+                config.check_traces
+                    || config.dump_scores
+                    || config.debug_predicate()
+                    || config.export_cfg()
+                    || config.dump_functions,
+            )
+            .fill_scores(
+                (config.dump_scores || config.export_cfg() || config.dump_functions)
+                    && !config.check_traces,
+            )
+            .progress(move |progress| {
+                if !quiet {
+                    println!("{}", progress)
+                }
+            }))
+    }
 
-        if config.dump_scores {
-            println!("calculating scores");
-            trace_analyzer.fill_address_scores();
+    pub fn fill_cfg(&mut self, cfg_collector: &mut CFGCollector) {
+        if let Some(aggregates) = &self.aggregates {
+            for (address, aggregate) in aggregates.addresses.iter() {
+                for (to, edge) in aggregate.edges.iter() {
+                    cfg_collector.add_edge(*address, *to, edge.edge_type);
+                }
+            }
         }
 
-        trace_analyzer
-    }
-
-    fn fill_cfg(&mut self, cfg_collector: &mut CFGCollector) {
         for instruction in self
             .crashes
             .iter_all_instructions()
             .chain(self.non_crashes.iter_all_instructions())
         {
             for succ in &instruction.successors {
-                cfg_collector.add_edge(instruction.address, succ.address);
+                cfg_collector.add_edge(instruction.address, succ.address, succ.edge_type);
             }
         }
 
         self.cfg = cfg_collector.construct_graph();
     }
 
-    fn fill_address_scores(&mut self) {
+    pub fn fill_address_scores(&mut self, combine_predicates: usize, top_k: usize) {
         let addresses = self.crash_non_crash_intersection();
-        self.address_scores = addresses
+        self.address_predicates = addresses
             .into_par_iter()
             .map(|address| {
-                (
-                    address,
-                    PredicateAnalyzer::evaluate_best_predicate_at_address(address, self),
-                )
+                let cached = self
+                    .cache
+                    .as_ref()
+                    .and_then(|cache| cache.cached_predicates(address, self));
+                let predicates = cached.unwrap_or_else(|| match combine_predicates {
+                    0 => {
+                        PredicateAnalyzer::evaluate_top_predicates_at_address(address, self, top_k)
+                    }
+                    k => PredicateAnalyzer::evaluate_top_combined_predicates_at_address(
+                        address, self, k, top_k,
+                    ),
+                });
+                (address, predicates)
             })
             .collect();
+        self.address_scores = self
+            .address_predicates
+            .iter()
+            .map(|(address, predicates)| (*address, predicates[0].clone()))
+            .collect();
     }
 
     pub fn address_union(&self) -> HashSet<usize> {
@@ -249,6 +364,10 @@ impl TraceAnalyzer {
     }
 
     pub fn crash_non_crash_intersection(&self) -> HashSet<usize> {
+        if let Some(aggregates) = &self.aggregates {
+            return aggregates.crash_non_crash_intersection();
+        }
+
         let crash_union = TraceAnalyzer::trace_union(&self.crashes);
         let non_crash_union = TraceAnalyzer::trace_union(&self.non_crashes);
         crash_union
@@ -263,6 +382,15 @@ impl TraceAnalyzer {
         selector: &Selector,
         reg_index: Option<usize>,
     ) -> Vec<u64> {
+        if let Some(aggregates) = &self.aggregates {
+            return aggregates
+                .addresses
+                .get(&address)
+                .and_then(|aggregate| aggregate.values(selector, reg_index))
+                .map(|values| values.values().collect())
+                .unwrap_or_default();
+        }
+
         let ret: Vec<_> = match selector {
             Selector::RegMin => self
                 .iter_all_instructions_at_address(address)
@@ -274,12 +402,36 @@ impl TraceAnalyzer {
                 .filter(|i| i.registers_max.get(reg_index.unwrap()).is_some())
                 .map(|i| i.registers_max.get(reg_index.unwrap()).unwrap().value())
                 .collect(),
+            Selector::RegLast => self
+                .iter_all_instructions_at_address(address)
+                .filter(|i| i.registers_last.get(reg_index.unwrap()).is_some())
+                .map(|i| i.registers_last.get(reg_index.unwrap()).unwrap().value())
+                .collect(),
+            Selector::InsCount => self
+                .iter_all_instructions_at_address(address)
+                .map(|i| i.count as u64)
+                .collect(),
             _ => unreachable!(),
         };
 
         ret
     }
 
+    pub fn edge_counts_at_address(&self, address: usize, destination: usize) -> Vec<u64> {
+        if let Some(aggregates) = &self.aggregates {
+            return aggregates
+                .addresses
+                .get(&address)
+                .and_then(|aggregate| aggregate.edges.get(&destination))
+                .map(|edge| edge.counts.values().collect())
+                .unwrap_or_default();
+        }
+
+        self.iter_all_instructions_at_address(address)
+            .map(|i| edge_count(i, destination) as u64)
+            .collect()
+    }
+
     pub fn unique_values_at_address(
         &self,
         address: usize,
@@ -305,7 +457,7 @@ impl TraceAnalyzer {
             .map(|(_, p)| (p.clone()))
             .collect();
 
-        ret.par_sort_by(|p1, p2| p1.score.partial_cmp(&p2.score).unwrap());
+        ret.par_sort_by(|p1, p2| cmp_scores(p1.score, p2.score).then(p1.address.cmp(&p2.address)));
 
         ret
     }
@@ -338,11 +490,36 @@ impl TraceAnalyzer {
             TraceAnalyzer::print_scores(&scores, filter_scores);
         }
 
-        TraceAnalyzer::dump_for_serialization(config, &scores)
+        self.dump_for_serialization(config, &scores)
     }
 
-    fn dump_for_serialization(config: &Config, scores: &Vec<Predicate>) {
-        let scores: Vec<_> = scores.iter().map(|p| p.to_serialzed()).collect();
+    fn dump_for_serialization(&self, config: &Config, scores: &Vec<Predicate>) {
+        let mut scores = SerializedScores {
+            metric: self.scoring.name().to_string(),
+            predicates: scores.iter().map(|p| self.serialize_predicate(p)).collect(),
+            ranked_predicates: scores
+                .iter()
+                .map(|p| RankedPredicates {
+                    address: p.address,
+                    predicates: self.address_predicates[&p.address]
+                        .iter()
+                        .map(|p| p.to_serialzed(self.scoring.as_ref(), &self.ln_factorials))
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        if let Some(symbolizer) = Symbolizer::from_config(config, self.image_base()) {
+            symbolizer.symbolize_predicates(
+                scores.predicates.iter_mut().chain(
+                    scores
+                        .ranked_predicates
+                        .iter_mut()
+                        .flat_map(|ranked| ranked.predicates.iter_mut()),
+                ),
+            );
+        }
+
         let serialized_string = serde_json::to_string(&scores).unwrap();
 
         let file_path = format!("{}/scores_linear_serialized.json", config.output_directory);
@@ -351,11 +528,34 @@ impl TraceAnalyzer {
             .expect(&format!("Could not write file {}", file_path));
     }
 
-    pub fn get_predicates_better_than(&self, min_score: f64) -> Vec<SerializedPredicate> {
+    pub fn dump_rejected_traces(&self, config: &Config) {
+        let serialized_string = serde_json::to_string(&self.rejected_traces).unwrap();
+
+        let file_path = format!("{}/rejected_traces.json", config.output_directory);
+
+        fs::write(&file_path, serialized_string)
+            .expect(&format!("Could not write file {}", file_path));
+    }
+
+    /// Best score the scoring function can reach on the analyzed traces.
+    pub fn max_score(&self) -> f64 {
+        let counts = self.trace_counts();
+        self.scoring.max_score(counts.crashes, counts.non_crashes)
+    }
+
+    /// Filters on the lower confidence bound of the score if `use_lower_bound` is set.
+    pub fn get_predicates_better_than(
+        &self,
+        min_score: f64,
+        use_lower_bound: bool,
+    ) -> Vec<SerializedPredicate> {
         self.address_scores
             .values()
-            .filter(|p| p.score > min_score)
-            .map(|p| p.to_serialzed())
+            .map(|p| self.serialize_predicate(p))
+            .filter(|p| match use_lower_bound {
+                true => p.lower_bound() > min_score,
+                false => p.score > min_score,
+            })
             .collect()
     }
 
@@ -378,6 +578,15 @@ impl TraceAnalyzer {
         address: usize,
         reg_index: usize,
     ) -> bool {
+        if let Some(aggregates) = &self.aggregates {
+            return aggregates
+                .addresses
+                .get(&address)
+                .map_or(false, |aggregate| {
+                    aggregate.registers_min.contains_key(&reg_index)
+                });
+        }
+
         self.crashes
             .0
             .par_iter()
@@ -388,7 +597,193 @@ impl TraceAnalyzer {
             })
     }
 
+    /// Serialized predicate with its statistics and the addresses collapsed into it.
+    pub fn serialize_predicate(&self, predicate: &Predicate) -> SerializedPredicate {
+        let mut serialized = predicate.to_serialzed(self.scoring.as_ref(), &self.ln_factorials);
+        if let Some(merged_addresses) = self.merged_addresses.get(&predicate.address) {
+            serialized.merged_addresses = merged_addresses.clone();
+        }
+
+        serialized
+    }
+
+    /// Whether each trace satisfies the best predicate at `address`, crashes first.
+    fn satisfying_traces(&self, address: usize) -> Vec<bool> {
+        let predicate = &self.address_scores[&address];
+        self.iter_all_traces()
+            .map(|trace| predicate.execute(&trace.instructions.get(&address)))
+            .collect()
+    }
+
+    /// Collapses best predicates that are equivalent to a predicate at a dominating or
+    /// post-dominating address, e.g. the `is_visited` predicates of all instructions in a block only
+    /// reached by crashes.
+    ///
+    /// Two predicates are equivalent if exactly the same traces satisfy them. Each predicate is
+    /// linked to the nearest equivalent predicate on its dominator chain or, if there is none, on
+    /// its post-dominator chain. Of each group of linked predicates, the earliest one stays in
+    /// `address_scores`: the one dominating most of the others, then the lowest address. The
+    /// others are removed and listed in `merged_addresses`.
+    ///
+    /// Streamed traces are not kept, so in stream mode only predicates satisfied by visiting their
+    /// address are linked to dominators if their counts match: every trace visiting an address
+    /// also visits its dominators, but a trace can end before reaching a post-dominator.
+    pub fn collapse_predicates(&mut self) {
+        if self.cfg.is_empty() {
+            return;
+        }
+        let dominators = Dominators::new(&self.cfg);
+        let stream_mode = self.aggregates.is_some();
+
+        // equivalent predicates have the same outcome counts
+        let mut classes: HashMap<(u64, ConfusionMatrix), HashSet<usize>> = HashMap::new();
+        for (address, predicate) in self.address_scores.iter() {
+            if self.cfg.contains(*address) && (!stream_mode || holds_on_visit(predicate)) {
+                classes
+                    .entry((predicate.score.to_bits(), predicate.matrix))
+                    .or_default()
+                    .insert(*address);
+            }
+        }
+        classes.retain(|_, addresses| addresses.len() > 1);
+
+        let satisfying_traces: HashMap<usize, Vec<bool>> = match stream_mode {
+            true => HashMap::new(),
+            false => classes
+                .values()
+                .flat_map(|addresses| addresses.iter())
+                .map(|address| (*address, self.satisfying_traces(*address)))
+                .collect(),
+        };
+        let equivalent =
+            |a1: &usize, a2: &usize| satisfying_traces.get(a1) == satisfying_traces.get(a2);
+
+        // union-find over the links between equivalent predicates
+        let mut parents: HashMap<usize, usize> = HashMap::new();
+        let find = |parents: &HashMap<usize, usize>, mut address: usize| {
+            while let Some(parent) = parents.get(&address) {
+                address = *parent;
+            }
+            address
+        };
+        for addresses in classes.values() {
+            for address in addresses.iter() {
+                let dominating = dominators
+                    .dominating_instructions(*address)
+                    .filter(|a| addresses.contains(a))
+                    .find(|a| equivalent(a, address));
+                let linked = dominating.or_else(|| match stream_mode {
+                    true => None,
+                    false => dominators
+                        .post_dominating_instructions(*address)
+                        .filter(|a| addresses.contains(a))
+                        .find(|a| equivalent(a, address)),
+                });
+                if let Some(linked) = linked {
+                    let (root1, root2) = (find(&parents, *address), find(&parents, linked));
+                    if root1 != root2 {
+                        parents.insert(root1, root2);
+                    }
+                }
+            }
+        }
+
+        let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+        for address in parents.keys().chain(parents.values()) {
+            groups
+                .entry(find(&parents, *address))
+                .or_default()
+                .push(*address);
+        }
+        for group in groups.values_mut() {
+            group.sort_unstable();
+            group.dedup();
+            let representative = *group
+                .iter()
+                .min_by_key(|a1| {
+                    let not_dominated = group.iter().filter(|a2| !dominators.dominates(**a1, **a2));
+                    (not_dominated.count(), **a1)
+                })
+                .unwrap();
+            for address in group.iter().filter(|a| **a != representative) {
+                self.address_scores.remove(address);
+                self.merged_addresses
+                    .entry(representative)
+                    .or_default()
+                    .push(*address);
+            }
+        }
+    }
+
+    /// Number of analyzed crashes and non-crashes.
+    pub fn trace_counts(&self) -> LabeledCount {
+        match &self.aggregates {
+            Some(aggregates) => LabeledCount {
+                crashes: aggregates.num_crashes,
+                non_crashes: aggregates.num_non_crashes,
+            },
+            None => LabeledCount {
+                crashes: self.crashes.len(),
+                non_crashes: self.non_crashes.len(),
+            },
+        }
+    }
+
+    /// Number of crashes and non-crashes visiting `address`.
+    pub fn visits(&self, address: usize) -> LabeledCount {
+        if let Some(aggregates) = &self.aggregates {
+            return aggregates
+                .addresses
+                .get(&address)
+                .map(|aggregate| aggregate.visits)
+                .unwrap_or_default();
+        }
+
+        LabeledCount {
+            crashes: self.crashes.iter_instructions_at_address(address).count(),
+            non_crashes: self
+                .non_crashes
+                .iter_instructions_at_address(address)
+                .count(),
+        }
+    }
+
+    /// Number of crashes and non-crashes taking the edge from `src` to `dst`.
+    pub fn edge_visits(&self, src: usize, dst: usize) -> LabeledCount {
+        if let Some(aggregates) = &self.aggregates {
+            return aggregates
+                .addresses
+                .get(&src)
+                .and_then(|aggregate| aggregate.edges.get(&dst))
+                .map(|edge| edge.taken)
+                .unwrap_or_default();
+        }
+
+        let takes_edge = |i: &&Instruction| i.successors.iter().any(|succ| succ.address == dst);
+        LabeledCount {
+            crashes: self
+                .crashes
+                .iter_instructions_at_address(src)
+                .filter(takes_edge)
+                .count(),
+            non_crashes: self
+                .non_crashes
+                .iter_instructions_at_address(src)
+                .filter(takes_edge)
+                .count(),
+        }
+    }
+
+    /// Lowest address the traced image was loaded at.
+    pub fn image_base(&self) -> usize {
+        self.memory_map.image_base()
+    }
+
     pub fn get_any_mnemonic(&self, address: usize) -> String {
+        if let Some(aggregates) = &self.aggregates {
+            return aggregates.addresses[&address].mnemonic.to_string();
+        }
+
         self.iter_all_instructions_at_address(address)
             .nth(0)
             .unwrap()
@@ -396,3 +791,142 @@ impl TraceAnalyzer {
             .to_string()
     }
 }
+
+/// Predicates satisfied by exactly the traces visiting their address.
+fn holds_on_visit(predicate: &Predicate) -> bool {
+    predicate.name == "is_visited" || predicate.name == "ins_count_greater_or_equal 1"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::tests::trace;
+
+    fn build(crashes: Vec<Trace>, non_crashes: Vec<Trace>, stream: bool) -> TraceAnalyzer {
+        TraceAnalyzer::builder(
+            TraceSource::Traces(crashes),
+            TraceSource::Traces(non_crashes),
+        )
+        .stream_traces(stream)
+        .collapse_predicates(true)
+        .build()
+        .unwrap()
+    }
+
+    const BLOCK: &[(usize, &str)] = &[
+        (0x1000, "mov rbx, rax"),
+        (0x1004, "mov rcx, rax"),
+        (0x1008, "mov rdx, rax"),
+        (0x100c, "ret"),
+    ];
+
+    /// Predicates `rax < 5` at each address of `BLOCK`, with the same counts but satisfied by
+    /// different traces at 0x1000 and 0x1004.
+    fn block_traces(crashes: bool) -> Vec<Trace> {
+        let (low, high) = match crashes {
+            true => (1, 9),
+            false => (9, 1),
+        };
+        (0..4)
+            .map(|index| {
+                trace(BLOCK, |address| match (address, index) {
+                    (0x1004, 0) | (0x1000, 3) | (0x1008, 3) => high,
+                    (0x100c, _) => 5,
+                    _ => low,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_same_predicates() {
+        let ranked = |analyzer: &TraceAnalyzer| {
+            let mut ret: Vec<(usize, String, u64)> = analyzer
+                .address_predicates
+                .iter()
+                .flat_map(|(address, predicates)| {
+                    predicates
+                        .iter()
+                        .map(move |p| (*address, p.name.to_string(), p.score.to_bits()))
+                })
+                .collect();
+            ret.sort();
+            ret
+        };
+        let in_memory = build(block_traces(true), block_traces(false), false);
+        let streamed = build(block_traces(true), block_traces(false), true);
+
+        assert_eq!(ranked(&in_memory), ranked(&streamed));
+    }
+
+    #[test]
+    fn test_collapse_same_traces() {
+        let analyzer = build(block_traces(true), block_traces(false), false);
+        let scores = &analyzer.address_scores;
+
+        assert_eq!(scores[&0x1000].matrix, scores[&0x1004].matrix);
+        assert_eq!(analyzer.merged_addresses[&0x1000], vec![0x1008]);
+        assert!(scores.contains_key(&0x1004));
+        assert!(!scores.contains_key(&0x1008));
+    }
+
+    const BRANCH: &[(usize, &str)] = &[(0x1000, "cmp rax, 0x5"), (0x1004, "jz 0x1010")];
+    const CRASH_PATH: &[(usize, &str)] = &[
+        (0x1010, "mov rbx, rax"),
+        (0x1014, "mov rcx, rax"),
+        (0x1018, "ret"),
+    ];
+    const NON_CRASH_PATH: &[(usize, &str)] = &[(0x1008, "mov rdx, rax"), (0x100c, "ret")];
+
+    fn branch_trace(path: &[(usize, &str)]) -> Trace {
+        let path: Vec<(usize, &str)> = BRANCH.iter().chain(path.iter()).cloned().collect();
+        trace(&path, |_| 5)
+    }
+
+    #[test]
+    fn test_collapse_dominance_chain() {
+        let crashes = vec![branch_trace(CRASH_PATH), branch_trace(CRASH_PATH)];
+        let non_crashes = vec![branch_trace(CRASH_PATH), branch_trace(NON_CRASH_PATH)];
+
+        // the edge predicate of the branch is the root of the chain
+        let analyzer = build(crashes.clone(), non_crashes.clone(), false);
+        assert_eq!(
+            analyzer.merged_addresses[&0x1004],
+            vec![0x1010, 0x1014, 0x1018]
+        );
+
+        // only predicates satisfied by visiting their address are merged in stream mode
+        let analyzer = build(crashes, non_crashes, true);
+        assert!(analyzer.address_scores.contains_key(&0x1004));
+        assert_eq!(analyzer.merged_addresses[&0x1010], vec![0x1014, 0x1018]);
+        assert_eq!(analyzer.merged_addresses.len(), 1);
+    }
+
+    #[test]
+    fn test_collapse_post_dominance() {
+        // only crashes have large values at 0x1010 and at the join 0x1020, which one non-crash
+        // reaches on another path
+        let join_trace = |path: (usize, &str), value: u64| {
+            let path = [
+                (0x1000, "jmp rax"),
+                path,
+                (0x1020, "mov rbx, rax"),
+                (0x1024, "ret"),
+            ];
+            trace(&path, |address| match address {
+                0x1010 | 0x1020 => value,
+                _ => 5,
+            })
+        };
+        let crashes = vec![join_trace((0x1010, "jmp 0x1020"), 9); 2];
+        let non_crashes = vec![
+            join_trace((0x1010, "jmp 0x1020"), 1),
+            join_trace((0x1008, "jmp 0x1020"), 1),
+        ];
+
+        // 0x1020 post-dominates 0x1010 but is not dominated by it
+        let analyzer = build(crashes, non_crashes, false);
+        assert_eq!(analyzer.merged_addresses[&0x1010], vec![0x1020]);
+        assert!(!analyzer.address_scores.contains_key(&0x1020));
+    }
+}
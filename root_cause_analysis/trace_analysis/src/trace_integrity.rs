@@ -1,58 +1,341 @@
-use crate::trace::REGISTERS;
+use crate::trace::{Instruction, Trace, REGISTERS};
 use crate::trace_analyzer::TraceAnalyzer;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::fmt;
 
-pub struct TraceIntegrityChecker {}
+/// Names of the checks selectable via `--enable-check` and `--disable-check`.
+pub const TRACE_CHECKS: &[&str] = &[
+    "cfg-empty",
+    "cfg-heads",
+    "cfg-leaves",
+    "cfg-head-first-address",
+    "cfg-addresses-unique",
+    "trace-addresses",
+    "empty-mnemonic",
+    "register-bounds",
+    "untracked-memory-write",
+    "untraced-successor",
+    "outside-image",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn tag(&self) -> &'static str {
+        match self {
+            Severity::Warning => "[W]",
+            Severity::Error => "[E]",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub check: String,
+    pub severity: Severity,
+    /// trace the finding refers to; `None` for findings on the joint CFG
+    pub trace: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.severity.tag(),
+            self.check,
+            self.message
+        )?;
+        match &self.trace {
+            Some(trace) => write!(f, " in trace {}", trace),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Integrity check of traces.
+///
+/// Checks of single traces implement `check_trace`; checks of the joint CFG override `check`.
+pub trait TraceCheck: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn severity(&self) -> Severity;
+
+    fn check_trace(&self, _trace: &Trace) -> Vec<String> {
+        vec![]
+    }
+
+    /// Returns the findings as pairs of trace name (if any) and message.
+    fn check(&self, trace_analyzer: &TraceAnalyzer) -> Vec<(Option<String>, String)> {
+        trace_analyzer
+            .iter_all_traces()
+            .flat_map(|trace| {
+                self.check_trace(trace)
+                    .into_iter()
+                    .map(move |message| (Some(trace.name.to_string()), message))
+            })
+            .collect()
+    }
+}
+
+pub fn trace_check(name: &str) -> Box<dyn TraceCheck> {
+    match name {
+        "cfg-empty" => Box::new(CfgEmpty),
+        "cfg-heads" => Box::new(CfgHeads),
+        "cfg-leaves" => Box::new(CfgLeaves),
+        "cfg-head-first-address" => Box::new(CfgHeadFirstAddress),
+        "cfg-addresses-unique" => Box::new(CfgAddressesUnique),
+        "trace-addresses" => Box::new(TraceAddresses),
+        "empty-mnemonic" => Box::new(EmptyMnemonic),
+        "register-bounds" => Box::new(RegisterBounds),
+        "untracked-memory-write" => Box::new(UntrackedMemoryWrite),
+        "untraced-successor" => Box::new(UntracedSuccessor),
+        "outside-image" => Box::new(OutsideImage),
+        _ => panic!("Unknown trace check {}", name),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CheckSummary {
+    pub check: String,
+    pub severity: Severity,
+    pub findings: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CheckReport {
+    pub findings: Vec<Finding>,
+    pub summary: Vec<CheckSummary>,
+}
+
+impl CheckReport {
+    pub fn errors(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .count()
+    }
+}
+
+/// Lists the findings one per line, followed by the number of findings per check.
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in self.findings.iter() {
+            writeln!(f, "{}", finding)?;
+        }
+
+        writeln!(f, "summary:")?;
+        for summary in self.summary.iter() {
+            writeln!(
+                f,
+                "{} {}: {}",
+                summary.severity.tag(),
+                summary.check,
+                summary.findings
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct TraceIntegrityChecker {
+    checks: Vec<Box<dyn TraceCheck>>,
+}
 
 impl TraceIntegrityChecker {
-    pub fn check_traces(trace_analyzer: &TraceAnalyzer) {
-        TraceIntegrityChecker::cfg_empty(trace_analyzer);
-        TraceIntegrityChecker::cfg_heads(trace_analyzer);
-        TraceIntegrityChecker::cfg_leaves(trace_analyzer);
-        TraceIntegrityChecker::cfg_head_equals_first_instruction(trace_analyzer);
-        TraceIntegrityChecker::cfg_addresses_unique(trace_analyzer);
-        TraceIntegrityChecker::instruction_mnemonic_not_empty(trace_analyzer);
-        TraceIntegrityChecker::compare_reg_min_last_max(trace_analyzer);
-        TraceIntegrityChecker::untracked_memory_write(trace_analyzer);
+    /// Uses the enabled checks (all if none are given) without the disabled ones.
+    pub fn new(enabled: &[String], disabled: &[String]) -> TraceIntegrityChecker {
+        TraceIntegrityChecker {
+            checks: TRACE_CHECKS
+                .iter()
+                .filter(|name| enabled.is_empty() || enabled.iter().any(|e| e == *name))
+                .filter(|name| !disabled.iter().any(|d| d == *name))
+                .map(|name| trace_check(name))
+                .collect(),
+        }
     }
 
-    fn cfg_empty(trace_analyzer: &TraceAnalyzer) {
-        // cfg is not empty
-        if trace_analyzer.cfg.is_empty() {
-            println!("[E] CFG is empty");
+    pub fn check_traces(&self, trace_analyzer: &TraceAnalyzer) -> CheckReport {
+        let results: Vec<_> = self
+            .checks
+            .par_iter()
+            .map(|check| (check, check.check(trace_analyzer)))
+            .collect();
+
+        let mut report = CheckReport {
+            findings: vec![],
+            summary: vec![],
+        };
+        for (check, findings) in results {
+            report.summary.push(CheckSummary {
+                check: check.name().to_string(),
+                severity: check.severity(),
+                findings: findings.len(),
+            });
+            report
+                .findings
+                .extend(findings.into_iter().map(|(trace, message)| Finding {
+                    check: check.name().to_string(),
+                    severity: check.severity(),
+                    trace,
+                    message,
+                }));
         }
+
+        report
     }
 
-    fn cfg_heads(trace_analyzer: &TraceAnalyzer) {
-        let cfg_heads = trace_analyzer.cfg.heads();
-        // there is only one cfg head (joint cfg of crashes and non-crashes)
-        if cfg_heads.len() != 1 {
-            println!("[E] CFG has {} heads (should have 1)", cfg_heads.len());
+    /// Runs the checks of single traces on `trace`.
+    pub fn check_trace(&self, trace: &Trace) -> Vec<Finding> {
+        self.checks
+            .iter()
+            .flat_map(|check| {
+                check
+                    .check_trace(trace)
+                    .into_iter()
+                    .map(move |message| Finding {
+                        check: check.name().to_string(),
+                        severity: check.severity(),
+                        trace: Some(trace.name.to_string()),
+                        message,
+                    })
+            })
+            .collect()
+    }
+}
+
+fn sorted_instructions(trace: &Trace) -> Vec<&Instruction> {
+    let mut ret: Vec<_> = trace.instructions.values().collect();
+    ret.sort_by_key(|instruction| instruction.address);
+
+    ret
+}
+
+fn register_name(index: usize) -> &'static str {
+    REGISTERS.get(index).cloned().unwrap_or("unknown")
+}
+
+struct CfgEmpty;
+
+impl TraceCheck for CfgEmpty {
+    fn name(&self) -> &'static str {
+        "cfg-empty"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, trace_analyzer: &TraceAnalyzer) -> Vec<(Option<String>, String)> {
+        match trace_analyzer.cfg.is_empty() {
+            true => vec![(None, "CFG is empty".to_string())],
+            false => vec![],
         }
     }
+}
+
+/// There is only one head in the joint CFG of crashes and non-crashes.
+struct CfgHeads;
+
+impl TraceCheck for CfgHeads {
+    fn name(&self) -> &'static str {
+        "cfg-heads"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
 
-    fn cfg_leaves(trace_analyzer: &TraceAnalyzer) {
-        // there is only one cfg exit
-        // this assumption might not hold every time (crashes may have different leaves from non-crashes)
-        if trace_analyzer.cfg.leaves().len() != 1 {
-            println!(
-                "[W] CFG has {} leaves (Should have 1 leaf unless Crash-CFG leaf != CFG leaf)",
-                trace_analyzer.cfg.leaves().len()
-            );
+    fn check(&self, trace_analyzer: &TraceAnalyzer) -> Vec<(Option<String>, String)> {
+        let cfg_heads = trace_analyzer.cfg.heads();
+        match cfg_heads.len() {
+            1 => vec![],
+            n => vec![(None, format!("CFG has {} heads (should have 1)", n))],
         }
     }
+}
 
-    fn cfg_head_equals_first_instruction(trace_analyzer: &TraceAnalyzer) {
-        let head = trace_analyzer.cfg.heads().pop().unwrap();
-        for trace in trace_analyzer.iter_all_traces() {
-            if head != trace.first_address {
-                println!("[E] CFG head (0x{:x}) is not equal to first instruction address (0x{:x}) reported in trace {}. Not re-running this check", head, trace.first_address, trace.name);
-                return;
-            }
+/// There is only one exit; this might not hold if crashes have other leaves than non-crashes.
+struct CfgLeaves;
+
+impl TraceCheck for CfgLeaves {
+    fn name(&self) -> &'static str {
+        "cfg-leaves"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check(&self, trace_analyzer: &TraceAnalyzer) -> Vec<(Option<String>, String)> {
+        match trace_analyzer.cfg.leaves().len() {
+            1 => vec![],
+            n => vec![(
+                None,
+                format!(
+                    "CFG has {} leaves (should have 1 leaf unless Crash-CFG leaf != CFG leaf)",
+                    n
+                ),
+            )],
         }
     }
+}
+
+struct CfgHeadFirstAddress;
+
+impl TraceCheck for CfgHeadFirstAddress {
+    fn name(&self) -> &'static str {
+        "cfg-head-first-address"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, trace_analyzer: &TraceAnalyzer) -> Vec<(Option<String>, String)> {
+        let head = match trace_analyzer.cfg.heads().pop() {
+            Some(head) => head,
+            None => return vec![],
+        };
+
+        trace_analyzer
+            .iter_all_traces()
+            .filter(|trace| trace.first_address != head)
+            .map(|trace| {
+                (
+                    Some(trace.name.to_string()),
+                    format!(
+                        "CFG head (0x{:x}) is not equal to first instruction address (0x{:x})",
+                        head, trace.first_address
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+struct CfgAddressesUnique;
+
+impl TraceCheck for CfgAddressesUnique {
+    fn name(&self) -> &'static str {
+        "cfg-addresses-unique"
+    }
 
-    fn cfg_addresses_unique(trace_analyzer: &TraceAnalyzer) {
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check(&self, trace_analyzer: &TraceAnalyzer) -> Vec<(Option<String>, String)> {
+        let mut ret = vec![];
         let cfg_addresses: Vec<usize> = trace_analyzer
             .cfg
             .bbs()
@@ -64,82 +347,220 @@ impl TraceIntegrityChecker {
         let address_union = trace_analyzer.address_union();
 
         if cfg_addresses.len() != cfg_addresses_unique.len() {
-            println!(
-                "[E] #addresses ({}) !=  #unique_addresses ({}) in CFG",
-                cfg_addresses.len(),
-                cfg_addresses_unique.len()
-            );
-        }
-
-        if cfg_addresses.len() != address_union.len() {
-            println!(
-                "[E] #addresses ({}) in CFG !=  #crash_address_union ({})",
-                cfg_addresses.len(),
-                address_union.len()
-            );
-        }
-    }
-
-    fn instruction_mnemonic_not_empty(trace_analyzer: &TraceAnalyzer) {
-        // instruction mnemonic != ""
-        for trace in trace_analyzer.iter_all_traces() {
-            trace.instructions.values().for_each(|i| {
-                if i.mnemonic == "".to_string() {
-                    println!("[E] Instruction {:x} has empty mnemonic in trace {}. Not re-running this check",
-                    i.address,
-                    trace.name);
-                    return;
-                }
-            });
+            ret.push((
+                None,
+                format!(
+                    "#addresses ({}) != #unique_addresses ({}) in CFG",
+                    cfg_addresses.len(),
+                    cfg_addresses_unique.len()
+                ),
+            ));
         }
+
+        // streamed traces are not kept, so there is no address union to compare with
+        if trace_analyzer.aggregates.is_none() && cfg_addresses.len() != address_union.len() {
+            ret.push((
+                None,
+                format!(
+                    "#addresses ({}) in CFG != #address_union ({})",
+                    cfg_addresses.len(),
+                    address_union.len()
+                ),
+            ));
+        }
+
+        ret
     }
+}
 
-    fn untracked_memory_write(trace_analyzer: &TraceAnalyzer) {
-        for trace in trace_analyzer.iter_all_traces() {
-            for instruction in trace.instructions.values() {
-                if instruction.mnemonic.contains("], ")
-                    && instruction.mnemonic.contains("mov")
-                    && !instruction.mnemonic.contains("rep")
-                {
-                    if !instruction.registers_min.get(23).is_some() {
-                        println!("[E] Memory write found in mnemonic but no memory address field tracked for instruction {:x} with mnemonic {} in trace {}. Not re-running this check",
-                        instruction.address,
-                        instruction.mnemonic,
-                        trace.name);
-                    }
-                    if !instruction.registers_min.get(24).is_some() {
-                        println!("[E] Memory write found in mnemonic but no memory value field tracked for instruction {:x} with mnemonic {} in trace {}. Not re-running this check",
-                        instruction.address,
-                        instruction.mnemonic,
-                        trace.name);
+/// The trace is not empty and contains its first and last address.
+struct TraceAddresses;
+
+impl TraceCheck for TraceAddresses {
+    fn name(&self) -> &'static str {
+        "trace-addresses"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check_trace(&self, trace: &Trace) -> Vec<String> {
+        if trace.instructions.is_empty() {
+            return vec!["trace contains no instructions".to_string()];
+        }
+
+        let mut ret = vec![];
+        if !trace.instructions.contains_key(&trace.first_address) {
+            ret.push(format!(
+                "first address 0x{:x} is not a traced instruction",
+                trace.first_address
+            ));
+        }
+        if !trace.instructions.contains_key(&trace.last_address) {
+            ret.push(format!(
+                "last address 0x{:x} is not a traced instruction",
+                trace.last_address
+            ));
+        }
+
+        ret
+    }
+}
+
+struct EmptyMnemonic;
+
+impl TraceCheck for EmptyMnemonic {
+    fn name(&self) -> &'static str {
+        "empty-mnemonic"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check_trace(&self, trace: &Trace) -> Vec<String> {
+        sorted_instructions(trace)
+            .into_iter()
+            .filter(|i| i.mnemonic.is_empty())
+            .map(|i| format!("Instruction {:x} has empty mnemonic", i.address))
+            .collect()
+    }
+}
+
+/// reg_min <= reg_last <= reg_max
+struct RegisterBounds;
+
+impl TraceCheck for RegisterBounds {
+    fn name(&self) -> &'static str {
+        "register-bounds"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check_trace(&self, trace: &Trace) -> Vec<String> {
+        let mut ret = vec![];
+        for instruction in sorted_instructions(trace) {
+            let mut indices: Vec<_> = instruction.registers_min.keys().cloned().collect();
+            indices.sort();
+
+            for i in indices {
+                let reg_min = instruction.registers_min.get(i).unwrap().value();
+                let (reg_max, reg_last) = match (
+                    instruction.registers_max.get(i),
+                    instruction.registers_last.get(i),
+                ) {
+                    (Some(reg_max), Some(reg_last)) => (reg_max.value(), reg_last.value()),
+                    _ => {
+                        ret.push(format!(
+                            "reg {} has a min value but no max or last value for instruction {:x}",
+                            register_name(i),
+                            instruction.address
+                        ));
+                        continue;
                     }
+                };
+
+                if !(reg_min <= reg_last && reg_last <= reg_max) {
+                    ret.push(format!(
+                        "min reg {} is not <= last reg <= max reg for instruction {:x}",
+                        register_name(i),
+                        instruction.address
+                    ));
                 }
             }
         }
+
+        ret
     }
+}
 
-    fn compare_reg_min_last_max(trace_analyzer: &TraceAnalyzer) {
-        // reg_min <= reg_last <= reg_max
-        for trace in trace_analyzer.iter_all_traces() {
-            for instruction in trace.instructions.values() {
-                (0..REGISTERS.len())
-                    .into_iter()
-                    .filter(|i| instruction.registers_min.get(*i).is_some())
-                    .for_each(|i| {
-                        let reg_min = instruction.registers_min.get(i).unwrap();
-                        let reg_max = instruction.registers_max.get(i).unwrap();
-//                        let reg_last = instruction.registers_last.get(i).unwrap();
-
-                        if !(reg_min.value() <= reg_max.value()) {
-                            println!("[E] min reg {} is not <= max reg for instruction {:x} in trace {}. Not re-running this check",
-                            REGISTERS[i],
-                            instruction.address,
-                            trace.name);
-                            return;
-                        }
-
-                    });
+struct UntrackedMemoryWrite;
+
+impl TraceCheck for UntrackedMemoryWrite {
+    fn name(&self) -> &'static str {
+        "untracked-memory-write"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn check_trace(&self, trace: &Trace) -> Vec<String> {
+        let mut ret = vec![];
+        for instruction in sorted_instructions(trace) {
+            if instruction.mnemonic.contains("], ")
+                && instruction.mnemonic.contains("mov")
+                && !instruction.mnemonic.contains("rep")
+            {
+                for (index, field) in [(23, "address"), (24, "value")].iter() {
+                    if instruction.registers_min.get(*index).is_none() {
+                        ret.push(format!(
+                            "Memory write found in mnemonic but no memory {} field tracked for instruction {:x} with mnemonic {}",
+                            field, instruction.address, instruction.mnemonic
+                        ));
+                    }
+                }
             }
         }
+
+        ret
+    }
+}
+
+struct UntracedSuccessor;
+
+impl TraceCheck for UntracedSuccessor {
+    fn name(&self) -> &'static str {
+        "untraced-successor"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check_trace(&self, trace: &Trace) -> Vec<String> {
+        sorted_instructions(trace)
+            .into_iter()
+            .flat_map(|i| i.successors.iter().map(move |s| (i.address, s.address)))
+            .filter(|(_, to)| !trace.instructions.contains_key(to))
+            .map(|(from, to)| {
+                format!(
+                    "edge 0x{:x} -> 0x{:x} leads to an untraced instruction",
+                    from, to
+                )
+            })
+            .collect()
+    }
+}
+
+struct OutsideImage;
+
+impl TraceCheck for OutsideImage {
+    fn name(&self) -> &'static str {
+        "outside-image"
+    }
+
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check_trace(&self, trace: &Trace) -> Vec<String> {
+        if trace.image_end <= trace.image_base {
+            return vec![];
+        }
+
+        sorted_instructions(trace)
+            .into_iter()
+            .filter(|i| i.address < trace.image_base || i.address >= trace.image_end)
+            .map(|i| {
+                format!(
+                    "instruction 0x{:x} is outside of the image 0x{:x}-0x{:x}",
+                    i.address, trace.image_base, trace.image_end
+                )
+            })
+            .collect()
     }
 }
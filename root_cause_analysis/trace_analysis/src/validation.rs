@@ -0,0 +1,112 @@
+use crate::trace::TRACE_FORMAT_VERSION;
+use crate::trace_analyzer::load_trace;
+use crate::trace_integrity::{Severity, TraceIntegrityChecker};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize)]
+pub struct TraceValidation {
+    pub path: String,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl TraceValidation {
+    fn validate(path: String, checker: &TraceIntegrityChecker) -> TraceValidation {
+        let (errors, warnings) = match load_trace(path.to_string()) {
+            Ok(trace) => {
+                let (mut errors, mut warnings) = (vec![], vec![]);
+                if trace.version == 0 {
+                    warnings.push("trace has no format version".to_string());
+                }
+                for finding in checker.check_trace(&trace) {
+                    let message = format!("{}: {}", finding.check, finding.message);
+                    match finding.severity {
+                        Severity::Error => errors.push(message),
+                        Severity::Warning => warnings.push(message),
+                    }
+                }
+                (errors, warnings)
+            }
+            Err(error) => (vec![error.to_string()], vec![]),
+        };
+
+        TraceValidation {
+            path,
+            errors,
+            warnings,
+        }
+    }
+
+    fn invalid(path: String, error: &str) -> TraceValidation {
+        TraceValidation {
+            path,
+            errors: vec![error.to_string()],
+            warnings: vec![],
+        }
+    }
+}
+
+/// Results of parsing traces and running the integrity checks of single traces.
+#[derive(Serialize, Deserialize)]
+pub struct ValidationReport {
+    /// newest supported trace format version; traces with a newer version are invalid
+    pub format_version: u32,
+    pub traces: usize,
+    pub invalid_traces: usize,
+    /// traces with errors or warnings
+    pub results: Vec<TraceValidation>,
+}
+
+impl ValidationReport {
+    pub fn validate(trace_dir: &str, disabled_checks: &[String]) -> ValidationReport {
+        // not `glob`, which skips file names that are not valid UTF-8
+        let entries = match fs::read_dir(trace_dir) {
+            Ok(entries) => entries.collect(),
+            Err(error) => vec![Err(error)],
+        };
+        let mut paths: Vec<Result<PathBuf, String>> = entries
+            .into_iter()
+            .map(|entry| entry.map(|entry| entry.path()).map_err(|e| e.to_string()))
+            .filter(|path| match path {
+                Ok(path) => {
+                    let path = path.to_string_lossy();
+                    path.ends_with(".zip") || path.ends_with("trace")
+                }
+                Err(_) => true,
+            })
+            .collect();
+        paths.sort();
+
+        let checker = TraceIntegrityChecker::new(&[], disabled_checks);
+        let traces = paths.len();
+        // unreadable paths and paths that are not valid UTF-8 are invalid traces
+        let results: Vec<_> = paths
+            .into_par_iter()
+            .map(|path| match path {
+                Ok(path) => match path.to_str() {
+                    Some(path) => TraceValidation::validate(path.to_string(), &checker),
+                    None => TraceValidation::invalid(
+                        path.display().to_string(),
+                        "file name is not valid UTF-8",
+                    ),
+                },
+                Err(error) => TraceValidation::invalid(trace_dir.to_string(), &error),
+            })
+            .filter(|result| !result.errors.is_empty() || !result.warnings.is_empty())
+            .collect();
+
+        ValidationReport {
+            format_version: TRACE_FORMAT_VERSION,
+            traces,
+            invalid_traces: results.iter().filter(|r| !r.errors.is_empty()).count(),
+            results,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.invalid_traces == 0
+    }
+}
@@ -36,6 +36,8 @@ END_LEGAL */
 #include "pin.H"
 
 #define NUM_REGS 23
+// keep in sync with trace_schema.json and TRACE_FORMAT_VERSION in trace_analysis
+#define TRACE_FORMAT_VERSION 1
 
 enum EdgeType {Direct, Indirect, Conditional, Syscall, Return, Regular, Unknown};
 static const std::string EDGE_TYPE_STR[7] = {
@@ -109,6 +111,7 @@ static EdgeType g_prev_ins_edge_type = Unknown;
 static ADDRINT g_prev_ins_addr = 0;
 static ADDRINT g_load_offset;
 static ADDRINT g_low_address;
+static ADDRINT g_high_address;
 static ADDRINT g_first_ins_addr;
 static UINT64 g_reg_state[23] = {0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0};
 static PIN_LOCK g_lock;
@@ -371,7 +374,9 @@ VOID parse_image(IMG img, VOID *v) {
     if (IMG_IsMainExecutable(img)) {
         g_load_offset  = IMG_LoadOffset(img);
         g_low_address  = IMG_LowAddress(img);
+        g_high_address = IMG_HighAddress(img);
         LOG("[*] Image base: " + StringFromAddrint(g_low_address) + "\n");
+        LOG("[*] Image end: " + StringFromAddrint(g_high_address) + "\n");
         LOG("[*] Load offset: " + StringFromAddrint(g_load_offset) + "\n");
         ADDRINT img_entry_addr = IMG_EntryAddress(img);
         LOG("[*] Image entry address: " + StringFromAddrint(img_entry_addr) + "\n");
@@ -405,7 +410,9 @@ std::string jsonify_reg_array(const Value* values) {
 std::string jsonify() {
     LOG("[+] Called jsonify\n");
     std::ostringstream ss;
-    ss << "{\"image_base\":" << g_low_address;
+    ss << "{\"version\":" << TRACE_FORMAT_VERSION;
+    ss << ",\"image_base\":" << g_low_address;
+    ss << ",\"image_end\":" << g_high_address;
     ss << ",\"first_address\":" << g_first_ins_addr;
     ss << ",\"last_address\":" << g_prev_ins_addr;
     ss << ",\"instructions\":[";